@@ -36,7 +36,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             client.stop_sync().await;
 
             // ルーム一覧を取得
-            let rooms = client.rooms();
+            let rooms = client.joined_rooms();
             println!("🏠 Found {} rooms", rooms.len());
             for room in rooms {
                 let room_name = room.display_name().await
@@ -1,15 +1,40 @@
 use crossterm::event::{KeyEvent, KeyCode};
 use crate::util::{NokError, NokResult};
 use crate::api::{ApiClient, WebSocketClient};
-use crate::matrix::MatrixConfig;
+use crate::matrix::{
+    discovery, MatrixConfig, NokLocationEventContent, NokRoomKindEventContent, RecoveryStatus, VerificationPhase,
+    OFFICE_STATE_EVENTS,
+};
+use matrix_sdk::ruma::events::{
+    room::{member::RoomMemberEventContent, name::RoomNameEventContent, topic::RoomTopicEventContent},
+    StaticEventContent,
+};
 
-use super::core::{AppCore, UiState, DataState, LogState, NetworkState, PaneIdentifier};
+use super::core::{AppCore, UiState, DataState, LogState, NetworkState, PaneIdentifier, ComposeMode, ConfirmAction, ConfirmAnswer, Confirmation, DevicePrompt, ProfileView, RoomDirectoryStep, SecurityPrompt, UserDirectoryStep, VerificationAnswer, answer_verification};
 use super::matrix_state::{MatrixState, LoginField};
 use super::legacy_state::LegacyState;
 use super::state_manager::{StateManager, CommunicationMode};
 use super::config::Config;
 use super::unified_config::UnifiedConfig;
-use super::user::User;
+use super::message::Message;
+use super::room::{Room, RoomKind};
+use super::office::{self, Direction, OfficeEditor, OfficeMap, PublishedPosition, Tile};
+use super::user::{User, extract_username_from_matrix_id};
+
+/// How long to wait before fetching the current room's timeline again
+/// after it failed to load
+const TIMELINE_RETRY_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How often the presence of room members is re-read for the Users pane;
+/// changes to rooms and their members come with the sync
+const USERS_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// How long we stand still after walking before our office position is
+/// published, so walking does not send a state event every step
+const POSITION_PUBLISH_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Pause in typing after which a room or user directory search is sent
+const DIRECTORY_SEARCH_DELAY: std::time::Duration = std::time::Duration::from_millis(400);
 
 /// New modular App structure
 /// Separates concerns into focused, manageable components
@@ -39,18 +64,14 @@ pub struct App {
 impl App {
     pub fn new() -> Self {
         // Load unified configuration
-        let mut config = UnifiedConfig::load();
+        let (mut config, mut notices) = UnifiedConfig::load();
         
         // Apply environment variable overrides
         config.apply_env_overrides();
         
         // Validate configuration
         if let Err(errors) = config.validate() {
-            eprintln!("Configuration validation errors:");
-            for error in errors {
-                eprintln!("  - {}", error);
-            }
-            eprintln!("Continuing with current configuration...");
+            notices.extend(errors.into_iter().map(|error| format!("Configuration error: {}", error)));
         }
         
         // Create current user from config
@@ -62,8 +83,21 @@ impl App {
         let websocket_client = WebSocketClient::new();
         
         // Create Matrix and Legacy states
-        let matrix_config = config.to_matrix_config();
-        let matrix_state = MatrixState::new(matrix_config);
+        let mut matrix_config = config.to_matrix_config();
+        match config.store_passphrase() {
+            Ok(passphrase) => matrix_config.store_passphrase = passphrase,
+            Err(e) => notices.push(format!("Failed to read the store key file: {}", e)),
+        }
+        // Without a passphrase from the key file, the login screen asks
+        let ask_passphrase = config.matrix.store_encrypted && matrix_config.store_passphrase.is_none();
+        let mut matrix_state = MatrixState::new(matrix_config);
+        if ask_passphrase {
+            matrix_state.login.ask_store_passphrase();
+        }
+        matrix_state.login.saved_accounts = config.matrix.accounts.iter()
+            .map(|account| account.user_id.clone())
+            .collect();
+        matrix_state.switch_saved_account(true);
         let legacy_state = LegacyState::new(api_client, websocket_client);
         
         // Create unified state manager with configured mode
@@ -95,6 +129,27 @@ impl App {
             app.logs.add_debug_log("Debug logging enabled".to_string());
             app.logs.add_debug_log(config.summary());
         }
+
+        // The terminal belongs to the UI by now, so nothing is printed
+        if let Some(notice) = notices.last() {
+            app.core.set_error(notice.clone());
+        }
+        for notice in notices {
+            app.logs.add_debug_log(notice);
+        }
+
+        if let Some(store) = config.unencrypted_store() {
+            app.ui.confirm = Some(Confirmation {
+                prompt: format!(
+                    "The Matrix store at {} is not encrypted, so anyone who can read it has your encryption keys. \
+                     It cannot be encrypted in place: switching overwrites and deletes it, and you log in as a new \
+                     device that gets its history back from key backup. Switch to an encrypted store? \
+                     (No keeps using it as it is)",
+                    store.display()
+                ),
+                action: ConfirmAction::EncryptStore,
+            });
+        }
         
         app
     }
@@ -147,12 +202,125 @@ impl App {
     pub async fn handle_key(&mut self, key: KeyEvent) -> NokResult<()> {
         use super::state::AppState;
 
+        if self.ui.confirm.is_some() {
+            return self.handle_confirm_key(key).await;
+        }
+        if self.state_manager.verification().is_some() {
+            return self.handle_verification_key(key).await;
+        }
+
         match self.core.state {
             AppState::Login => self.handle_login_key(key).await,
             AppState::Normal => self.handle_normal_key(key).await,
             AppState::Input => self.handle_input_key(key).await,
             AppState::Settings => self.handle_settings_key(key).await,
+            AppState::RoomDirectory => self.handle_directory_key(key).await,
+            AppState::UserDirectory => self.handle_user_directory_key(key).await,
+            AppState::Profile => self.handle_profile_key(key).await,
+        }
+    }
+
+    /// Answer the open confirmation: y runs its action, n or Esc drops it
+    async fn handle_confirm_key(&mut self, key: KeyEvent) -> NokResult<()> {
+        match self.ui.answer_confirmation(key.code) {
+            ConfirmAnswer::Yes(action) => self.run_confirmed(action).await?,
+            ConfirmAnswer::No => self.core.set_notification("Cancelled".to_string()),
+            ConfirmAnswer::Open => {}
+        }
+        Ok(())
+    }
+
+    /// Keys of the verification modal: accept or decline a request,
+    /// say whether the emojis match, Esc cancels. Once it is over any key
+    /// closes it.
+    async fn handle_verification_key(&mut self, key: KeyEvent) -> NokResult<()> {
+        let Some(phase) = self.state_manager.verification().map(|v| v.phase()) else {
+            return Ok(());
+        };
+        match answer_verification(&phase, key.code) {
+            Some(VerificationAnswer::Close) => {
+                self.state_manager.cancel_verification().await?;
+                self.ui.verification_phase = None;
+            }
+            Some(VerificationAnswer::Accept) => {
+                self.state_manager.accept_verification().await?;
+            }
+            Some(VerificationAnswer::Decline) => {
+                self.state_manager.cancel_verification().await?;
+                self.ui.verification_phase = None;
+                self.core.set_notification("Verification declined".to_string());
+            }
+            Some(VerificationAnswer::Match) => {
+                self.state_manager.confirm_verification(true).await?;
+            }
+            Some(VerificationAnswer::Mismatch) => {
+                self.state_manager.confirm_verification(false).await?;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Keys of the profile overlay: v verifies the user, Esc closes
+    async fn handle_profile_key(&mut self, key: KeyEvent) -> NokResult<()> {
+        match key.code {
+            KeyCode::Esc | KeyCode::Char('p') => {
+                self.ui.profile = None;
+                self.core.state = super::state::AppState::Normal;
+            }
+            KeyCode::Char('v') => {
+                if let Some(user_id) = self.ui.profile.as_ref().map(|p| p.user_id.clone()) {
+                    self.start_verification(&user_id, None).await?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn run_confirmed(&mut self, action: ConfirmAction) -> NokResult<()> {
+        match action {
+            ConfirmAction::EnableEncryption(room_id) => {
+                self.state_manager.enable_encryption(&room_id, &mut self.logs).await?;
+                if let Some(room) = self.data.rooms.iter_mut().find(|r| r.matrix_id.as_ref() == Some(&room_id)) {
+                    room.set_encrypted(true);
+                }
+                self.core.set_notification("🔒 Encryption is on for this room".to_string());
+            }
+            ConfirmAction::Logout { wipe_store } => {
+                self.logout(wipe_store).await?;
+            }
+            ConfirmAction::DeleteMessage(event_id) => {
+                self.delete_message(&event_id).await?;
+            }
+            ConfirmAction::EncryptStore => {
+                self.encrypt_store()?;
+            }
+            ConfirmAction::KnockInNewDm { user_id, name } => {
+                self.core.state = super::state::AppState::Normal;
+                self.open_dm(&user_id).await?;
+                if let Some(room_id) = self.current_matrix_room_id() {
+                    self.state_manager.knock_in_room(&room_id, &user_id, &mut self.logs).await?;
+                    self.core.set_notification(format!("Knocked on {}", name));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Shred the unencrypted store and open encrypted ones from now on
+    fn encrypt_store(&mut self) -> NokResult<()> {
+        self.config.encrypt_store()?;
+        let passphrase = self.config.store_passphrase()?;
+        let matrix = self.state_manager.matrix_mut();
+        matrix.config.state_store_path = self.config.matrix.store_path.clone();
+        matrix.config.store_path = self.config.matrix.store_path.clone();
+        matrix.config.store_passphrase = passphrase;
+        if matrix.config.store_passphrase.is_none() {
+            matrix.login.ask_store_passphrase();
         }
+        self.core.set_notification("🔒 The Matrix store is now encrypted".to_string());
+        Ok(())
     }
 
     /// Handle login screen input
@@ -168,6 +336,11 @@ impl App {
                     LoginField::Password => {
                         login_state.password.push(c);
                     }
+                    LoginField::StorePassphrase => {
+                        if let Some(passphrase) = &mut login_state.store_passphrase {
+                            passphrase.push(c);
+                        }
+                    }
                 }
             }
             KeyCode::Backspace => {
@@ -178,18 +351,36 @@ impl App {
                     LoginField::Password => {
                         login_state.password.pop();
                     }
+                    LoginField::StorePassphrase => {
+                        if let Some(passphrase) = &mut login_state.store_passphrase {
+                            passphrase.pop();
+                        }
+                    }
                 }
             }
             KeyCode::Tab => {
                 login_state.next_field();
             }
+            KeyCode::Up => {
+                self.state_manager.matrix_mut().switch_saved_account(false);
+            }
+            KeyCode::Down => {
+                self.state_manager.matrix_mut().switch_saved_account(true);
+            }
             KeyCode::Enter => {
                 if login_state.can_submit() {
                     self.attempt_login().await?;
                 }
             }
             KeyCode::Esc => {
-                self.core.should_quit = true;
+                // Adding an account is given up; the others carry on
+                if self.state_manager.matrix().account_ids().is_empty() {
+                    self.core.should_quit = true;
+                } else {
+                    self.state_manager.matrix_mut().drop_pending_logins();
+                    self.data.current_user.matrix_id = self.state_manager.matrix().user_id();
+                    self.core.state = super::state::AppState::Normal;
+                }
             }
             _ => {}
         }
@@ -198,12 +389,26 @@ impl App {
 
     /// Handle normal mode input
     async fn handle_normal_key(&mut self, key: KeyEvent) -> NokResult<()> {
+        // Pane-specific keybindings take precedence over global ones
+        if self.core.focused_pane == PaneIdentifier::Messages && self.handle_message_action_key(key).await? {
+            return Ok(());
+        }
+        if self.core.focused_pane == PaneIdentifier::Rooms && self.handle_invite_key(key).await? {
+            return Ok(());
+        }
+        if self.core.focused_pane == PaneIdentifier::AsciiArt && self.handle_office_editor_key(key).await? {
+            return Ok(());
+        }
+        if self.core.focused_pane == PaneIdentifier::AsciiArt && self.handle_office_key(key).await? {
+            return Ok(());
+        }
+
         match key.code {
             KeyCode::Char('q') => {
                 self.core.should_quit = true;
             }
             KeyCode::Char('s') => {
-                self.core.state = super::state::AppState::Settings;
+                self.open_settings().await;
             }
             KeyCode::Char('i') => {
                 self.core.state = super::state::AppState::Input;
@@ -211,12 +416,38 @@ impl App {
             KeyCode::Char('k') => {
                 self.send_knock().await?;
             }
+            KeyCode::Char('f') => {
+                self.cycle_floor();
+            }
+            KeyCode::Char('p') if self.core.focused_pane == PaneIdentifier::Users => {
+                let user_id = self.data.get_selected_user(self.ui.selected_user)
+                    .and_then(|user| user.matrix_id.clone());
+                match user_id {
+                    Some(user_id) => self.open_profile(&user_id).await,
+                    None => self.core.set_error("No user selected".to_string()),
+                }
+            }
+            KeyCode::Char('w') if self.core.focused_pane == PaneIdentifier::Users => {
+                self.data.users_here_only = !self.data.users_here_only;
+                self.ui.selected_user = None;
+                self.core.set_notification(if self.data.users_here_only {
+                    "Users: in this room now".to_string()
+                } else {
+                    "Users: all members".to_string()
+                });
+            }
             KeyCode::Up => {
                 self.navigate_up();
             }
             KeyCode::Down => {
                 self.navigate_down();
             }
+            KeyCode::Enter => {
+                self.confirm_selection().await?;
+            }
+            KeyCode::Esc if self.ui.open_thread.is_some() => {
+                self.close_thread();
+            }
             KeyCode::Tab => {
                 self.cycle_focus();
             }
@@ -225,175 +456,1872 @@ impl App {
         Ok(())
     }
 
-    /// Handle input mode
-    async fn handle_input_key(&mut self, key: KeyEvent) -> NokResult<()> {
+    /// Actions on the selected message: reply, edit, delete, react, open thread.
+    /// Returns true when the key was consumed.
+    async fn handle_message_action_key(&mut self, key: KeyEvent) -> NokResult<bool> {
+        let Some(message) = self.data.get_selected_message(self.ui.selected_message_idx, self.ui.open_thread.as_deref()) else {
+            return Ok(false);
+        };
+        let Some(event_id) = message.id.clone() else {
+            return Ok(false);
+        };
+        let is_own = self.state_manager.matrix().user_id()
+            .is_some_and(|own_id| message.is_from(&own_id));
+        let is_redacted = message.redacted;
+        let content = message.content.clone();
+        let thread_root = message.thread_root.clone();
+
         match key.code {
-            KeyCode::Char(c) => {
-                self.ui.input.push(c);
+            KeyCode::Char('r') if !is_redacted => {
+                self.ui.compose = ComposeMode::Reply(event_id);
+                self.core.state = super::state::AppState::Input;
             }
-            KeyCode::Backspace => {
-                self.ui.input.pop();
+            KeyCode::Char('e') if is_own && !is_redacted => {
+                self.ui.compose = ComposeMode::Edit(event_id);
+                self.ui.input = content;
+                self.core.state = super::state::AppState::Input;
             }
-            KeyCode::Enter => {
-                self.process_input().await?;
-                self.core.state = super::state::AppState::Normal;
+            KeyCode::Char('d') if is_own && !is_redacted => {
+                self.ask_delete_message(event_id, &content);
             }
-            KeyCode::Esc => {
-                self.ui.clear_input();
-                self.core.state = super::state::AppState::Normal;
+            KeyCode::Char('+') if !is_redacted => {
+                self.ui.compose = ComposeMode::React(event_id);
+                self.core.state = super::state::AppState::Input;
             }
-            _ => {}
+            KeyCode::Char('t') if !is_redacted || thread_root.is_some() => {
+                self.open_thread(thread_root.unwrap_or(event_id));
+            }
+            KeyCode::Char('r') | KeyCode::Char('e') | KeyCode::Char('d') | KeyCode::Char('+') | KeyCode::Char('t') => {
+                self.core.set_error("That action is not available for this message".to_string());
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Walk our avatar around the office map with the arrow keys or
+    /// i/j/k/l, knock on whoever is next to us with n, or open the floor
+    /// plan editor with e. Returns true when the key was consumed.
+    async fn handle_office_key(&mut self, key: KeyEvent) -> NokResult<bool> {
+        let direction = match key.code {
+            KeyCode::Up | KeyCode::Char('i') => Direction::Up,
+            KeyCode::Down | KeyCode::Char('k') => Direction::Down,
+            KeyCode::Left | KeyCode::Char('j') => Direction::Left,
+            KeyCode::Right | KeyCode::Char('l') => Direction::Right,
+            KeyCode::Char('e') => {
+                self.open_office_editor();
+                return Ok(true);
+            }
+            KeyCode::Char('n') => {
+                self.knock_nearby().await?;
+                return Ok(true);
+            }
+            _ => return Ok(false),
+        };
+        if self.data.office_room.is_some() {
+            self.ui.my_aa_position = self.data.office_map.step(self.ui.my_aa_position, direction);
+        }
+        Ok(true)
+    }
+
+    /// Knock on whoever stands, or sits at their desk, right next to us
+    async fn knock_nearby(&mut self) -> NokResult<()> {
+        let Some(room_id) = self.data.office_room.clone() else {
+            self.core.set_error("Enter a room to knock on someone".to_string());
+            return Ok(());
+        };
+        let own = self.ui.my_aa_position;
+        let reach = self.state_manager.knock_reach();
+        let avatars = self.data.office_avatars(own);
+        let Some(target) = avatars.iter()
+            .filter(|avatar| !avatar.is_self && avatar.position.distance(own) <= reach)
+            .min_by_key(|avatar| (avatar.position.distance(own), avatar.name.clone()))
+        else {
+            self.core.set_error("Walk up next to someone to knock".to_string());
+            return Ok(());
+        };
+
+        self.state_manager.knock_in_room(&room_id, &target.user_id, &mut self.logs).await?;
+        self.core.set_notification(format!("Knocked on {}", target.name));
+        if self.config.app.enable_sounds {
+            self.play_sound("knock", crate::audio::play_knock_sound);
         }
         Ok(())
     }
 
-    /// Handle settings mode
-    async fn handle_settings_key(&mut self, key: KeyEvent) -> NokResult<()> {
-        match key.code {
-            KeyCode::Esc => {
-                self.core.state = super::state::AppState::Normal;
+    /// Play a sound on its own thread. A failure, e.g. no audio device,
+    /// goes to the debug log: the terminal belongs to the UI.
+    fn play_sound(&self, name: &'static str, play: fn() -> Result<(), String>) {
+        let background = self.logs.background.clone();
+        std::thread::spawn(move || {
+            if let Err(e) = play() {
+                background.lock().unwrap().push(format!("Error playing {} sound: {}", name, e));
             }
-            KeyCode::Char('m') => {
-                self.toggle_matrix_mode().await?;
+        });
+    }
+
+    /// Announce knocks picked up by the sync loop. A knock from someone in
+    /// the office on screen is also animated at their position.
+    fn receive_knocks(&mut self) {
+        for (room_id, sender_id) in self.state_manager.take_knocks() {
+            let name = self.data.users.iter()
+                .find(|u| u.matrix_id.as_ref() == Some(&sender_id))
+                .map_or_else(|| extract_username_from_matrix_id(&sender_id), |u| u.name.clone());
+            self.logs.add_debug_log(format!("Knock from {} in {}", sender_id, room_id));
+            self.core.set_notification(format!("🚪 {} is knocking!", name));
+            if self.config.app.enable_sounds {
+                self.play_sound("knock", crate::audio::play_knock_sound);
             }
-            _ => {}
+            if self.data.office_room.as_ref() == Some(&room_id) {
+                self.data.office_knocks.push((sender_id, std::time::Instant::now()));
+            }
+        }
+        self.data.office_knocks.retain(|(_, at)| office::knock_frame(at.elapsed()).is_some());
+    }
+
+    /// Follow the rules of the current room's type: our presence and
+    /// status message change as we move between focus rooms, meeting
+    /// rooms and everything else
+    async fn apply_room_rules(&mut self, kind_changed: bool) -> NokResult<()> {
+        let room_id = self.current_matrix_room_id();
+        let kind = match &room_id {
+            Some(room_id) if kind_changed => {
+                let kind = self.state_manager.room_kind(room_id).await?;
+                if let Some(room) = self.data.rooms.iter_mut().find(|r| r.matrix_id.as_ref() == Some(room_id)) {
+                    room.kind = kind;
+                }
+                kind
+            }
+            Some(_) => self.data.get_current_room().and_then(|room| room.kind),
+            None => None,
+        };
+
+        let own_status = self.data.current_user.status.clone();
+        let Some(status) = self.state_manager.apply_room_kind(room_id.as_deref(), kind, &own_status, &mut self.logs).await? else {
+            return Ok(());
+        };
+        self.data.current_user.status = status;
+        match kind {
+            Some(RoomKind::Focus) => self.core.set_notification("Focus room: you are Busy and knocks wait until you leave".to_string()),
+            Some(RoomKind::Meeting) => self.core.set_notification("Meeting room: your status says you are in a meeting".to_string()),
+            Some(RoomKind::Lounge) => self.core.set_notification("Lounge: knock on anyone in the room".to_string()),
+            None => {}
         }
         Ok(())
     }
 
-    /// Attempt to login using current credentials
-    async fn attempt_login(&mut self) -> NokResult<()> {
-        let username = self.state_manager.matrix().login.username.clone();
-        let password = self.state_manager.matrix().login.password.clone();
+    /// Tell the rooms we move between that we now sit in the current one,
+    /// and re-read who else is sitting there once `occupants_changed`
+    async fn update_location(&mut self, occupants_changed: bool) {
+        let current = self.current_matrix_room_id();
+        for (room_id, present) in self.data.move_location(current.clone()) {
+            // Rooms not created by nok may not let members say so
+            if let Err(e) = self.state_manager.set_location(&room_id, present, &mut self.logs).await {
+                self.logs.add_debug_log(format!("Could not share location in {}: {}", room_id, e));
+            }
+        }
 
-        match self.state_manager.matrix_mut().login(&username, &password).await {
-            Ok(()) => {
-                self.logs.add_debug_log(format!("Logged in as {}", username));
-                self.core.state = super::state::AppState::Normal;
-                
-                // Start Matrix sync
-                if let Err(e) = self.state_manager.matrix().start_sync().await {
-                    self.logs.add_debug_log(format!("Failed to start sync: {}", e));
-                } else {
-                    self.logs.add_debug_log("Matrix sync started successfully".to_string());
-                    
-                    // Wait longer for initial sync and then load rooms
-                    self.logs.add_debug_log("Waiting for Matrix sync to stabilize...".to_string());
-                    tokio::time::sleep(tokio::time::Duration::from_millis(3000)).await;
-                    
-                    self.logs.add_debug_log("Attempting to sync rooms from Matrix...".to_string());
-                    if let Err(e) = self.sync_rooms_from_matrix().await {
-                        self.logs.add_debug_log(format!("Failed to sync rooms: {}", e));
-                    } else {
-                        self.logs.add_debug_log("Room sync completed successfully".to_string());
-                    }
+        let Some(room_id) = current.filter(|_| occupants_changed) else {
+            return;
+        };
+        match self.state_manager.room_occupants(&room_id).await {
+            Ok(occupants) => {
+                let occupants = self.data.online_occupants(occupants);
+                if let Some(room) = self.data.get_current_room_mut() {
+                    room.occupants = occupants;
                 }
-                
-                // Update network state
-                self.update_network_state();
             }
+            Err(e) => self.logs.add_debug_log(format!("Failed to read who is in {}: {}", room_id, e)),
+        }
+    }
+
+    /// Show a user's profile, with whether we verified them
+    async fn open_profile(&mut self, user_id: &str) {
+        let name = self.data.users.iter()
+            .find(|u| u.matrix_id.as_deref() == Some(user_id))
+            .map_or_else(|| extract_username_from_matrix_id(user_id), |u| u.name.clone());
+        let verified = match self.state_manager.is_user_verified(user_id).await {
+            Ok(verified) => verified,
             Err(e) => {
-                let error_msg = e.user_message();
-                self.state_manager.matrix_mut().login.set_error(error_msg.clone());
-                self.logs.add_debug_log(format!("Login failed: {}", error_msg));
+                self.logs.add_debug_log(format!("Failed to read the verification state of {}: {}", user_id, e));
+                None
             }
-        }
+        };
+        self.ui.profile = Some(ProfileView {
+            user_id: user_id.to_string(),
+            name,
+            verified,
+            last_verification: None,
+        });
+        self.core.state = super::state::AppState::Profile;
+    }
 
+    /// Ask to verify a user, or one of their devices by ID
+    async fn start_verification(&mut self, user_id: &str, device_id: Option<&str>) -> NokResult<()> {
+        self.state_manager.start_verification(user_id, device_id, &mut self.logs).await?;
+        self.ui.verification_phase = Some(VerificationPhase::Requested);
+        self.core.set_notification(format!("Verification request sent to {}", user_id));
         Ok(())
     }
 
-    /// Send a knock to the selected user
-    async fn send_knock(&mut self) -> NokResult<()> {
-        if let Some(selected_idx) = self.ui.selected_user {
-            if let Some(user) = self.data.users.get(selected_idx) {
-                if let Some(user_id) = &user.id {
-                    self.state_manager.send_knock(user_id, &mut self.logs).await?;
-                    self.core.set_notification(format!("Knocked on {}", user.name));
-                } else {
-                    self.core.set_error("User ID not available".to_string());
+    /// Move the running verification along and announce how it changed.
+    /// The result is noted on the profile of the user we verified.
+    async fn update_verification(&mut self) {
+        if let Err(e) = self.state_manager.poll_verification(&mut self.logs).await {
+            self.core.set_error(format!("Verification failed: {}", e));
+        }
+        let Some(verification) = self.state_manager.verification() else {
+            return;
+        };
+        let phase = verification.phase();
+        if self.ui.verification_phase.as_ref() == Some(&phase) {
+            return;
+        }
+        let other_user = verification.other_user_id();
+        let outcome = match &phase {
+            VerificationPhase::Incoming => {
+                self.core.set_notification(format!("🔐 {} wants to verify with you", other_user));
+                None
+            }
+            VerificationPhase::Done => Some((Some(true), "Verified just now".to_string())),
+            VerificationPhase::Cancelled(reason) => Some((None, format!("Last verification cancelled: {}", reason))),
+            _ => None,
+        };
+        if let Some((verified, note)) = outcome {
+            self.core.set_notification(format!("{} ({})", note, other_user));
+            if let Some(profile) = self.ui.profile.as_mut().filter(|p| p.user_id == other_user) {
+                if verified.is_some() {
+                    profile.verified = verified;
                 }
+                profile.last_verification = Some(note);
             }
-        } else {
-            self.core.set_error("No user selected".to_string());
         }
-        Ok(())
+        self.ui.verification_phase = Some(phase);
     }
 
-    /// Process input command
-    async fn process_input(&mut self) -> NokResult<()> {
-        let input = self.ui.input.trim().to_string();
-        
-        if input.starts_with("/") {
-            // Handle commands
-            self.process_command(&input).await?;
-        } else if input.starts_with("nok @") {
-            // Handle knock command
-            self.process_knock_command(&input).await?;
-        } else {
-            // Regular message
-            self.send_message(&input).await?;
+    /// Ask before turning on encryption in the current room, since it can
+    /// never be turned off again
+    fn ask_enable_encryption(&mut self) {
+        let Some(room) = self.data.get_current_room() else {
+            self.core.set_error("No room selected".to_string());
+            return;
+        };
+        let Some(room_id) = room.matrix_id.clone() else {
+            self.core.set_error("Encryption needs a Matrix room".to_string());
+            return;
+        };
+        if room.is_encrypted {
+            self.core.set_notification(format!("🔒 {} is already encrypted", room.name));
+            return;
         }
+        self.ui.confirm = Some(Confirmation {
+            prompt: format!(
+                "Turn on end-to-end encryption in {}?\nIt cannot be turned off again, and members on clients without encryption will no longer see new messages.",
+                room.name
+            ),
+            action: ConfirmAction::EnableEncryption(room_id),
+        });
+    }
 
-        self.ui.clear_input();
+    /// Mark rooms where the sync saw encryption being turned on
+    fn receive_encryption_changes(&mut self) {
+        for room_id in self.state_manager.take_encryption_changes() {
+            let Some(room) = self.data.rooms.iter_mut().find(|r| r.matrix_id.as_ref() == Some(&room_id)) else {
+                continue;
+            };
+            if !room.is_encrypted {
+                room.set_encrypted(true);
+                self.logs.add_debug_log(format!("Encryption turned on in {}", room_id));
+                self.core.set_notification(format!("🔒 Encryption is now on in {}", room.name));
+            }
+        }
+    }
+
+    /// Set or clear the current room's type
+    async fn set_room_kind(&mut self, name: Option<&str>) -> NokResult<()> {
+        let kind = match name {
+            Some("none") => None,
+            Some(name) => match RoomKind::parse(name) {
+                Some(kind) => Some(kind),
+                None => {
+                    self.core.set_error("Usage: /roomtype focus|meeting|lounge|none".to_string());
+                    return Ok(());
+                }
+            },
+            None => {
+                let current = self.data.get_current_room().and_then(|room| room.kind);
+                self.core.set_notification(format!("Room type: {}", current.map_or("none", RoomKind::as_str)));
+                return Ok(());
+            }
+        };
+        let Some(room_id) = self.current_matrix_room_id() else {
+            self.core.set_error("Enter a room to set its type".to_string());
+            return Ok(());
+        };
+        self.state_manager.set_room_kind(&room_id, kind, &mut self.logs).await?;
+        self.core.set_notification(format!("Room type set to {}", kind.map_or("none", RoomKind::as_str)));
         Ok(())
     }
 
-    /// Process slash commands
-    async fn process_command(&mut self, command: &str) -> NokResult<()> {
-        let parts: Vec<&str> = command.split_whitespace().collect();
-        
-        match parts.get(0).copied() {
-            Some("/help") => {
-                self.show_help();
+    /// Keys of the floor plan editor: move the cursor, paint tiles with
+    /// their plan characters, mark zones, save or discard. Everything but
+    /// Tab is consumed, so a stray key cannot quit with unsaved changes.
+    async fn handle_office_editor_key(&mut self, key: KeyEvent) -> NokResult<bool> {
+        if self.ui.office_editor.is_none() {
+            return Ok(false);
+        }
+        match key.code {
+            KeyCode::Tab => return Ok(false),
+            KeyCode::Enter => {
+                self.save_office_plan().await?;
+                return Ok(true);
             }
-            Some("/status") => {
-                if let Some(status) = parts.get(1) {
-                    self.set_status(status).await?;
-                }
+            KeyCode::Esc => {
+                self.ui.office_editor = None;
+                self.core.set_notification("Floor plan changes discarded".to_string());
+                return Ok(true);
             }
-            Some("/join") => {
-                if let Some(room) = parts.get(1) {
-                    self.join_room(room).await?;
+            _ => {}
+        }
+        let Some(editor) = self.ui.office_editor.as_mut() else {
+            return Ok(false);
+        };
+
+        match key.code {
+            KeyCode::Up | KeyCode::Char('i') => editor.move_cursor(Direction::Up),
+            KeyCode::Down | KeyCode::Char('k') => editor.move_cursor(Direction::Down),
+            KeyCode::Left | KeyCode::Char('j') => editor.move_cursor(Direction::Left),
+            KeyCode::Right | KeyCode::Char('l') => editor.move_cursor(Direction::Right),
+            KeyCode::Char('z') => {
+                if editor.mark_zone_corner() {
+                    // The zone's name is typed as a command
+                    self.ui.input = "/zone ".to_string();
+                    self.core.state = super::state::AppState::Input;
+                } else {
+                    self.core.set_notification("Move to the opposite corner and press z again".to_string());
                 }
             }
-            _ => {
-                self.core.set_error("Unknown command".to_string());
+            KeyCode::Char('x') => match editor.remove_zone_at_cursor() {
+                Some(name) => self.core.set_notification(format!("Removed zone '{}'", name)),
+                None => self.core.set_error("No zone here".to_string()),
+            },
+            KeyCode::Char(c) => {
+                if let Some(tile) = Tile::from_glyph(c) {
+                    editor.paint(tile);
+                }
             }
+            _ => {}
         }
-        
-        Ok(())
+        Ok(true)
     }
 
-    /// Process knock command
-    async fn process_knock_command(&mut self, command: &str) -> NokResult<()> {
-        if let Some(username) = command.strip_prefix("nok @") {
-            let username = username.trim();
-            
-            // Find user and send knock
-            if let Some(_user) = self.data.users.iter().find(|u| u.name == username) {
-                self.logs.add_debug_log(format!("Knocking {}", username));
-                // Implementation would depend on Matrix vs legacy mode
-            } else {
-                self.core.set_error(format!("User '{}' not found", username));
+    /// Accept or decline the invite selected in the Rooms pane.
+    /// Returns true when the key was consumed.
+    async fn handle_invite_key(&mut self, key: KeyEvent) -> NokResult<bool> {
+        let Some(room_id) = self.data.get_selected_invite(self.ui.selected_room_idx).map(|i| i.room_id.clone()) else {
+            return Ok(false);
+        };
+        match key.code {
+            KeyCode::Char('a') | KeyCode::Enter => self.accept_invite(&room_id).await?,
+            KeyCode::Char('d') => self.decline_invite(&room_id).await?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Room directory overlay: typing searches, Enter joins, Esc closes
+    async fn handle_directory_key(&mut self, key: KeyEvent) -> NokResult<()> {
+        match self.ui.room_directory.press(key.code) {
+            RoomDirectoryStep::Stay => {}
+            RoomDirectoryStep::Close => {
+                self.core.state = super::state::AppState::Normal;
+            }
+            RoomDirectoryStep::Load { since, page_number } => {
+                self.load_directory_page(Some(since), page_number).await;
+            }
+            RoomDirectoryStep::Join(target) => {
+                self.core.state = super::state::AppState::Normal;
+                self.join_room(&target).await?;
             }
         }
-        
         Ok(())
     }
 
-    /// Send a regular message
-    async fn send_message(&mut self, _message: &str) -> NokResult<()> {
-        if let Some(room) = self.data.get_current_room() {
-            self.logs.add_debug_log(format!("Sending message to room: {}", room.name));
-            // Implementation would depend on Matrix vs legacy mode
+    /// User directory overlay: typing searches; with the results focused
+    /// (Tab) k knocks, m or Enter opens a DM and i invites to the current room
+    async fn handle_user_directory_key(&mut self, key: KeyEvent) -> NokResult<()> {
+        match self.ui.user_directory.press(key.code) {
+            UserDirectoryStep::Stay => {}
+            UserDirectoryStep::Close => {
+                self.core.state = super::state::AppState::Normal;
+            }
+            UserDirectoryStep::Search => {
+                self.search_user_directory().await;
+                self.ui.user_directory.results_focused = !self.ui.user_directory.results.is_empty();
+            }
+            UserDirectoryStep::Knock { user_id, name } => self.knock_on(&user_id, &name).await?,
+            UserDirectoryStep::Invite(user_id) => self.invite_user(&user_id).await?,
+            UserDirectoryStep::Dm(user_id) => {
+                self.core.state = super::state::AppState::Normal;
+                self.open_dm(&user_id).await?;
+            }
         }
-        
         Ok(())
     }
 
-    /// Toggle between communication modes
-    async fn toggle_matrix_mode(&mut self) -> NokResult<()> {
-        let current_mode = self.state_manager.get_mode();
+    /// Handle input mode
+    async fn handle_input_key(&mut self, key: KeyEvent) -> NokResult<()> {
+        match key.code {
+            KeyCode::Char(c) => {
+                self.ui.input.push(c);
+            }
+            KeyCode::Backspace => {
+                self.ui.input.pop();
+            }
+            KeyCode::Enter => {
+                self.process_input().await?;
+                self.core.state = super::state::AppState::Normal;
+            }
+            KeyCode::Esc => {
+                self.ui.clear_input();
+                self.core.state = super::state::AppState::Normal;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Handle settings mode
+    async fn handle_settings_key(&mut self, key: KeyEvent) -> NokResult<()> {
+        if self.ui.security.prompt.is_some() {
+            return self.handle_security_prompt_key(key).await;
+        }
+        if self.ui.devices.prompt.is_some() {
+            return self.handle_device_prompt_key(key).await;
+        }
+        match key.code {
+            KeyCode::Esc => {
+                self.ui.security.recovery_key = None;
+                self.core.state = super::state::AppState::Normal;
+            }
+            KeyCode::Char('q') => {
+                self.core.should_quit = true;
+            }
+            KeyCode::Char('m') => {
+                self.toggle_matrix_mode().await?;
+            }
+            KeyCode::Char('b') => {
+                self.ui.security.recovery_key = None;
+                self.ui.security.prompt = Some(SecurityPrompt::Password);
+            }
+            KeyCode::Char('r') => {
+                self.ui.security.prompt = Some(SecurityPrompt::RecoveryKey);
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                self.ui.devices.selected = self.ui.devices.selected.saturating_sub(1);
+            }
+            KeyCode::Down | KeyCode::Char('j') => {
+                if self.ui.devices.selected + 1 < self.ui.devices.list.len() {
+                    self.ui.devices.selected += 1;
+                }
+            }
+            KeyCode::Char(' ') => {
+                self.ui.devices.toggle_mark();
+            }
+            KeyCode::Char('n') => {
+                if let Some(device) = self.ui.devices.selected_device() {
+                    self.ui.devices.input = device.display_name.clone().unwrap_or_default();
+                    self.ui.devices.prompt = Some(DevicePrompt::Rename);
+                }
+            }
+            KeyCode::Char('x') => {
+                if self.ui.devices.logout_targets().is_empty() {
+                    self.core.set_error("Select or mark another device to log out".to_string());
+                } else {
+                    self.ui.devices.prompt = Some(DevicePrompt::Password);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Open the settings screen with a fresh look at the security setup
+    /// and our devices
+    async fn open_settings(&mut self) {
+        self.core.state = super::state::AppState::Settings;
+        self.refresh_security_status().await;
+        self.refresh_devices().await;
+    }
+
+    async fn refresh_devices(&mut self) {
+        let list = match self.state_manager.list_devices().await {
+            Ok(list) => list,
+            Err(e) => {
+                self.logs.add_debug_log(format!("Failed to list devices: {}", e));
+                Vec::new()
+            }
+        };
+        self.ui.devices.set_list(list);
+    }
+
+    /// Typing a new device name, or the password that logs devices out
+    async fn handle_device_prompt_key(&mut self, key: KeyEvent) -> NokResult<()> {
+        let devices = &mut self.ui.devices;
+        match key.code {
+            KeyCode::Esc => {
+                devices.prompt = None;
+                devices.input.clear();
+            }
+            KeyCode::Backspace => {
+                devices.input.pop();
+            }
+            KeyCode::Char(c) => {
+                devices.input.push(c);
+            }
+            KeyCode::Enter => {
+                let input = std::mem::take(&mut devices.input);
+                match devices.prompt.take() {
+                    Some(DevicePrompt::Rename) => {
+                        let Some(device_id) = devices.selected_device().map(|device| device.device_id.clone()) else {
+                            return Ok(());
+                        };
+                        self.state_manager.rename_device(&device_id, &input, &mut self.logs).await?;
+                        self.refresh_devices().await;
+                        self.core.set_notification(format!("Renamed {} to {}", device_id, input.trim()));
+                    }
+                    Some(DevicePrompt::Password) => {
+                        let targets = devices.logout_targets();
+                        let result = self.state_manager.log_out_devices(&targets, &input, &mut self.logs).await;
+                        self.refresh_devices().await;
+                        result?;
+                        self.core.set_notification(format!("Logged out {} device(s)", targets.len()));
+                    }
+                    None => {}
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    async fn refresh_security_status(&mut self) {
+        self.ui.security.status = match self.state_manager.security_status().await {
+            Ok(status) => Some(status),
+            Err(e) => {
+                self.logs.add_debug_log(format!("Failed to read the security status: {}", e));
+                None
+            }
+        };
+    }
+
+    /// Typing a password, passphrase or recovery key in the settings screen.
+    /// Setting up asks for the account password, then a passphrase.
+    async fn handle_security_prompt_key(&mut self, key: KeyEvent) -> NokResult<()> {
+        let security = &mut self.ui.security;
+        match key.code {
+            KeyCode::Esc => {
+                security.prompt = None;
+                security.password = None;
+                security.input.clear();
+            }
+            KeyCode::Backspace => {
+                security.input.pop();
+            }
+            KeyCode::Char(c) => {
+                security.input.push(c);
+            }
+            KeyCode::Enter => {
+                let input = std::mem::take(&mut security.input);
+                match security.prompt.take() {
+                    Some(SecurityPrompt::Password) => {
+                        security.password = Some(input);
+                        security.prompt = Some(SecurityPrompt::Passphrase);
+                    }
+                    Some(SecurityPrompt::Passphrase) => {
+                        let password = security.password.take().unwrap_or_default();
+                        let passphrase = Some(input.as_str()).filter(|p| !p.is_empty());
+                        self.core.set_notification("Setting up cross-signing and key backup…".to_string());
+                        let key = self.state_manager.set_up_recovery(&password, passphrase, &mut self.logs).await;
+                        self.refresh_security_status().await;
+                        self.ui.security.recovery_key = Some(key?);
+                        self.core.set_notification("Key backup is on. Write the recovery key down now".to_string());
+                    }
+                    Some(SecurityPrompt::RecoveryKey) => {
+                        let restored = self.state_manager.restore_recovery(input.trim(), &mut self.logs).await;
+                        self.refresh_security_status().await;
+                        restored?;
+                        self.core.set_notification("Keys restored; encrypted history is being downloaded".to_string());
+                    }
+                    None => {}
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Attempt to login using current credentials
+    async fn attempt_login(&mut self) -> NokResult<()> {
+        let username = self.state_manager.matrix().login.username.clone();
+        let password = self.state_manager.matrix().login.password.clone();
+        // Bare usernames need a server from an earlier login
+        if discovery::server_name_of(username.trim()).is_none() && self.state_manager.matrix().config.server_name.is_empty() {
+            self.state_manager.matrix_mut().login.set_error("Enter your full Matrix ID, e.g. @alice:example.org".to_string());
+            return Ok(());
+        }
+        let user_id = self.state_manager.matrix().config.user_id_for(&username);
+        if self.state_manager.matrix().account_ids().contains(&user_id) {
+            self.state_manager.matrix_mut().login.set_error(format!("{} is already logged in", user_id));
+            return Ok(());
+        }
+
+        let homeserver_url = match self.homeserver_for(&user_id).await {
+            Ok(url) => url,
+            Err(e) => {
+                self.logs.add_debug_log(format!("Homeserver discovery failed: {}", e));
+                let server_name = discovery::server_name_of(&user_id).unwrap_or_default();
+                self.state_manager.matrix_mut().login.set_error(format!("Could not find a Matrix homeserver for {}", server_name));
+                return Ok(());
+            }
+        };
+        self.logs.add_debug_log(format!("Logging in {} on {}", user_id, homeserver_url));
+
+        let device_id = self.config.saved_device_id(&user_id);
+        if let Err(e) = self.state_manager.matrix_mut().open_account(&user_id, &homeserver_url, device_id).await {
+            self.logs.add_debug_log(format!("Failed to open the Matrix store: {}", e));
+            let error = if self.state_manager.matrix().login.store_passphrase.is_some() {
+                "Could not open the Matrix store. Check the store passphrase."
+            } else {
+                "Could not open the Matrix store."
+            };
+            self.state_manager.matrix_mut().login.set_error(error.to_string());
+            return Ok(());
+        }
+
+        match self.state_manager.matrix_mut().login(&user_id, &password).await {
+            Ok(()) => {
+                self.logs.add_debug_log(format!("Logged in as {}", user_id));
+                self.core.state = super::state::AppState::Normal;
+                self.data.current_user.matrix_id = self.state_manager.matrix().user_id();
+                self.remember_account();
+                
+                // Start Matrix sync
+                if let Err(e) = self.state_manager.matrix().start_sync().await {
+                    self.logs.add_debug_log(format!("Failed to start sync: {}", e));
+                } else {
+                    self.logs.add_debug_log("Matrix sync started successfully".to_string());
+                    
+                    // Wait longer for initial sync and then load rooms
+                    self.logs.add_debug_log("Waiting for Matrix sync to stabilize...".to_string());
+                    tokio::time::sleep(tokio::time::Duration::from_millis(3000)).await;
+                    
+                    // Invites pending from before login are listed but not announced
+                    if let Err(e) = self.refresh_invites(false).await {
+                        self.logs.add_debug_log(format!("Failed to load invites: {}", e));
+                    }
+
+                    // A new login to an account with key backup needs the
+                    // recovery key before old messages can be read
+                    if let Ok(status) = self.state_manager.security_status().await {
+                        if status.recovery == RecoveryStatus::Incomplete {
+                            self.open_settings().await;
+                            self.ui.security.prompt = Some(SecurityPrompt::RecoveryKey);
+                            self.core.set_notification("Enter your recovery key to read your encrypted history".to_string());
+                        }
+                    }
+
+                    self.logs.add_debug_log("Attempting to sync rooms from Matrix...".to_string());
+                    if let Err(e) = self.sync_rooms_from_matrix().await {
+                        self.logs.add_debug_log(format!("Failed to sync rooms: {}", e));
+                    } else {
+                        self.logs.add_debug_log("Room sync completed successfully".to_string());
+                        if let Err(e) = self.load_timeline().await {
+                            self.logs.add_debug_log(format!("Failed to load timeline: {}", e));
+                        }
+                    }
+                }
+                
+                // Update network state
+                self.update_network_state();
+            }
+            Err(e) => {
+                let error_msg = e.user_message();
+                self.state_manager.matrix_mut().login.set_error(error_msg.clone());
+                self.logs.add_debug_log(format!("Login failed: {}", error_msg));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Homeserver an account logs in on: the one it used before, ours for
+    /// an account on our server, otherwise the one its server's
+    /// .well-known points to
+    async fn homeserver_for(&self, user_id: &str) -> NokResult<String> {
+        if let Some(url) = self.config.saved_homeserver(user_id) {
+            return Ok(url);
+        }
+        let server_name = discovery::server_name_of(user_id)
+            .ok_or_else(|| NokError::InvalidInput(format!("{} is not a Matrix ID", user_id)))?;
+        let config = &self.state_manager.matrix().config;
+        if server_name == config.server_name && !config.homeserver_url.is_empty() {
+            return Ok(config.homeserver_url.clone());
+        }
+        discovery::discover_homeserver(server_name).await
+    }
+
+    /// Save the account that just logged in, the device it got and its
+    /// homeserver, for the account switcher and the next login
+    fn remember_account(&mut self) {
+        let matrix = self.state_manager.matrix();
+        let Some(client) = matrix.get_client() else {
+            return;
+        };
+        let Some(user_id) = matrix.user_id() else {
+            return;
+        };
+        let homeserver_url = client.homeserver_url().to_string();
+        if let Err(e) = self.config.remember_account(&user_id, client.device_id(), &homeserver_url) {
+            self.logs.add_debug_log(format!("Failed to save the account: {}", e));
+        }
+        // Bare usernames now log in on this account's server
+        let config = &mut self.state_manager.matrix_mut().config;
+        config.server_name = self.config.matrix.server_name.clone();
+        config.homeserver_url = self.config.matrix.homeserver_url.clone();
+        self.load_saved_accounts();
+    }
+
+    fn load_saved_accounts(&mut self) {
+        let login = &mut self.state_manager.matrix_mut().login;
+        login.saved_accounts = self.config.matrix.accounts.iter()
+            .map(|account| account.user_id.clone())
+            .collect();
+        login.saved_account_index = None;
+    }
+
+    /// Ask how to log out: `/logout` keeps the local store, `/logout wipe`
+    /// deletes it
+    fn ask_logout(&mut self, wipe_store: bool) {
+        let Some(user_id) = self.state_manager.matrix().user_id() else {
+            self.core.set_error("Not logged in to Matrix".to_string());
+            return;
+        };
+        let prompt = if wipe_store {
+            format!(
+                "Log {} out and delete everything stored for it on this machine?\nEncrypted history can only be read again with your recovery key.",
+                user_id
+            )
+        } else {
+            format!(
+                "Log {} out?\nRooms and cached messages stay on this machine; /logout wipe deletes them too.",
+                user_id
+            )
+        };
+        self.ui.confirm = Some(Confirmation {
+            prompt,
+            action: ConfirmAction::Logout { wipe_store },
+        });
+    }
+
+    /// Log out on the homeserver and go back to the login screen with the
+    /// account filled in
+    async fn logout(&mut self, wipe_store: bool) -> NokResult<()> {
+        let location_room = self.data.location_room.clone();
+        let user_id = self.state_manager.logout(wipe_store, location_room.as_deref(), &mut self.logs).await?;
+        // Announced again from whichever account is still in the room
+        self.data.location_room = None;
+        if let Err(e) = self.config.forget_device(&user_id) {
+            self.logs.add_debug_log(format!("Failed to save the config: {}", e));
+        }
+
+        // Other accounts carry on without the rooms only this one was in
+        if let Some(active) = self.state_manager.matrix().user_id() {
+            self.data.current_user.matrix_id = Some(active.clone());
+            self.data.invites.retain(|invite| invite.account.as_deref() != Some(user_id.as_str()));
+            self.sync_rooms_from_matrix().await?;
+            self.refresh_users().await?;
+            self.load_saved_accounts();
+            self.core.set_notification(format!("Logged out of {}; now acting as {}", user_id, active));
+            return Ok(());
+        }
+
+        let mut current_user = self.data.current_user.clone();
+        current_user.matrix_id = None;
+        self.data = DataState::new(current_user);
+        self.ui = UiState::new();
+        self.core.state = super::state::AppState::Login;
+        self.update_network_state();
+
+        self.load_saved_accounts();
+        self.state_manager.matrix_mut().switch_saved_account(true);
+        self.core.set_notification(format!("Logged out of {}", user_id));
+        Ok(())
+    }
+
+    /// Send a knock to the selected user
+    async fn send_knock(&mut self) -> NokResult<()> {
+        let Some(user) = self.data.get_selected_user(self.ui.selected_user).cloned() else {
+            self.core.set_error("No user selected".to_string());
+            return Ok(());
+        };
+        // Matrix knocks address the MXID, legacy ones the internal ID
+        let target = match self.state_manager.get_mode() {
+            CommunicationMode::Legacy => &user.id,
+            _ => &user.matrix_id,
+        };
+        if let Some(user_id) = target {
+            self.knock_on(user_id, &user.name).await?;
+        } else {
+            self.core.set_error("User ID not available".to_string());
+        }
+        Ok(())
+    }
+
+    /// Knock on someone in a room we share with them. Without one, ask
+    /// before opening a DM to knock in.
+    async fn knock_on(&mut self, user_id: &str, name: &str) -> NokResult<()> {
+        let matrix = self.state_manager.get_mode() != CommunicationMode::Legacy;
+        if matrix && !self.state_manager.can_knock(user_id).await? {
+            self.ui.confirm = Some(Confirmation {
+                prompt: format!("You share no room with {}.\nOpen a direct message with them and knock there?", name),
+                action: ConfirmAction::KnockInNewDm { user_id: user_id.to_string(), name: name.to_string() },
+            });
+            return Ok(());
+        }
+        self.state_manager.send_knock(user_id, &mut self.logs).await?;
+        self.core.set_notification(format!("Knocked on {}", name));
+        Ok(())
+    }
+
+    /// Process input command
+    async fn process_input(&mut self) -> NokResult<()> {
+        let input = self.ui.input.trim().to_string();
+
+        if input.is_empty() {
+            self.ui.clear_input();
+            return Ok(());
+        }
+
+        match self.ui.compose.clone() {
+            ComposeMode::Reply(event_id) => {
+                self.send_reply(&event_id, &input).await?;
+            }
+            ComposeMode::Edit(event_id) => {
+                self.edit_message(&event_id, &input).await?;
+            }
+            ComposeMode::React(event_id) => {
+                self.toggle_reaction(&event_id, &input).await?;
+            }
+            ComposeMode::Message if input.starts_with("/") => {
+                // Handle commands
+                self.process_command(&input).await?;
+            }
+            ComposeMode::Message if input.starts_with("nok @") => {
+                // Handle knock command
+                self.process_knock_command(&input).await?;
+            }
+            ComposeMode::Message if !self.data.huddle_members.is_empty() => {
+                self.send_huddle_message(&input).await?;
+            }
+            ComposeMode::Message if self.ui.open_thread.is_some() => {
+                self.send_thread_message(&input).await?;
+            }
+            ComposeMode::Message => {
+                // Regular message
+                self.send_message(&input).await?;
+            }
+        }
+
+        self.ui.clear_input();
+        Ok(())
+    }
+
+    /// Process slash commands
+    async fn process_command(&mut self, command: &str) -> NokResult<()> {
+        let parts: Vec<&str> = command.split_whitespace().collect();
+        
+        match parts.get(0).copied() {
+            Some("/help") => {
+                self.show_help();
+            }
+            Some("/status") => {
+                if let Some(status) = parts.get(1) {
+                    self.set_status(status).await?;
+                }
+            }
+            Some("/join") => {
+                // Exact aliases and IDs join directly, anything else searches the directory
+                match parts.get(1) {
+                    Some(room) if room.starts_with('#') || room.starts_with('!') => self.join_room(room).await?,
+                    _ => self.open_room_directory(&parts[1..].join(" ")).await,
+                }
+            }
+            Some("/create") => {
+                match parse_create_args(&parts[1..]) {
+                    Ok(args) => self.create_room(args).await?,
+                    Err(usage) => self.core.set_error(usage),
+                }
+            }
+            Some("/invite") => {
+                match parts.get(1) {
+                    Some(user) => {
+                        let user_id = self.resolve_user_id(user);
+                        self.invite_user(&user_id).await?;
+                    }
+                    None => self.core.set_error("Usage: /invite @user".to_string()),
+                }
+            }
+            Some("/leave") => {
+                self.leave_room().await?;
+            }
+            Some("/kick") => {
+                match parse_kick_args(&parts[1..]) {
+                    Some((user, reason)) => {
+                        let user_id = self.resolve_user_id(user);
+                        self.kick_user(&user_id, reason.as_deref()).await?;
+                    }
+                    None => self.core.set_error("Usage: /kick @user [reason]".to_string()),
+                }
+            }
+            Some("/topic") => {
+                let topic = command["/topic".len()..].trim();
+                self.set_topic(topic).await?;
+            }
+            Some("/profile") => {
+                match parts.get(1) {
+                    Some(user_id) => self.open_profile(user_id).await,
+                    None => self.core.set_error("Usage: /profile @user:server".to_string()),
+                }
+            }
+            Some("/verify") => {
+                match parts.get(1) {
+                    Some(user_id) => self.start_verification(user_id, parts.get(2).copied()).await?,
+                    None => self.core.set_error("Usage: /verify @user:server [device_id]".to_string()),
+                }
+            }
+            Some("/encrypt") => {
+                self.ask_enable_encryption();
+            }
+            Some("/account") => {
+                self.account_command(parts.get(1).copied());
+            }
+            Some("/logout") => {
+                match parts.get(1).copied() {
+                    None => self.ask_logout(false),
+                    Some("wipe") => self.ask_logout(true),
+                    Some(_) => self.core.set_error("Usage: /logout [wipe]".to_string()),
+                }
+            }
+            Some("/who") => {
+                self.open_user_directory(&parts[1..].join(" ")).await;
+            }
+            Some("/dm") => {
+                match parts.get(1) {
+                    Some(user) => {
+                        let user_id = self.resolve_user_id(user);
+                        self.open_dm(&user_id).await?;
+                    }
+                    None => self.core.set_error("Usage: /dm @user".to_string()),
+                }
+            }
+            Some("/upload") => {
+                // Paths may contain spaces, so take the rest of the line
+                let path = command["/upload".len()..].trim();
+                if path.is_empty() {
+                    self.core.set_error("Usage: /upload <path>".to_string());
+                } else {
+                    self.upload_file(path).await?;
+                }
+            }
+            Some("/save") => {
+                self.save_selected_attachment().await?;
+            }
+            Some("/plan") => {
+                // Paths may contain spaces, so take the rest of the line
+                match command["/plan".len()..].trim().split_once(' ') {
+                    Some(("import", path)) => self.import_office_plan(path.trim())?,
+                    Some(("export", path)) => self.export_office_plan(path.trim())?,
+                    _ => self.core.set_error("Usage: /plan import|export <path>".to_string()),
+                }
+            }
+            Some("/zone") => {
+                self.name_office_zone(command["/zone".len()..].trim());
+            }
+            Some("/huddle") => {
+                self.set_huddle_distance(parts.get(1).copied());
+            }
+            Some("/desk") => {
+                match parts.get(1).copied() {
+                    None => self.claim_desk(false).await?,
+                    Some("release") => self.claim_desk(true).await?,
+                    Some(_) => self.core.set_error("Usage: /desk [release]".to_string()),
+                }
+            }
+            Some("/roomtype") => {
+                self.set_room_kind(parts.get(1).copied()).await?;
+            }
+            _ => {
+                self.core.set_error("Unknown command".to_string());
+            }
+        }
+        
+        Ok(())
+    }
+
+    /// Process knock command
+    async fn process_knock_command(&mut self, command: &str) -> NokResult<()> {
+        if let Some(username) = command.strip_prefix("nok @") {
+            let username = username.trim();
+            
+            // Find user and send knock
+            if let Some(_user) = self.data.users.iter().find(|u| u.name == username) {
+                self.logs.add_debug_log(format!("Knocking {}", username));
+                // Implementation would depend on Matrix vs legacy mode
+            } else {
+                self.core.set_error(format!("User '{}' not found", username));
+            }
+        }
+        
+        Ok(())
+    }
+
+    /// Send a regular message
+    async fn send_message(&mut self, message: &str) -> NokResult<()> {
+        let Some(room_id) = self.current_matrix_room_id() else {
+            self.core.set_error("No room selected".to_string());
+            return Ok(());
+        };
+
+        self.state_manager.send_message(&room_id, message, &mut self.logs).await?;
+        Ok(())
+    }
+
+    /// Send a message into the thread open in the side view
+    async fn send_thread_message(&mut self, message: &str) -> NokResult<()> {
+        let (Some(room_id), Some(root)) = (self.current_matrix_room_id(), self.ui.open_thread.clone()) else {
+            return Ok(());
+        };
+        // Older clients render the thread message as a reply to the latest one
+        let latest = self.data.visible_messages(Some(&root)).last()
+            .and_then(|m| m.id.clone())
+            .unwrap_or_else(|| root.clone());
+
+        self.state_manager.send_thread_message(&room_id, &root, &latest, message, &mut self.logs).await?;
+        Ok(())
+    }
+
+    /// Show a thread next to the main timeline
+    fn open_thread(&mut self, root: String) {
+        self.ui.open_thread = Some(root);
+        self.ui.selected_message_idx = Some(0);
+        self.core.set_notification("Thread opened (Esc to close)".to_string());
+    }
+
+    fn close_thread(&mut self) {
+        self.ui.open_thread = None;
+        self.ui.selected_message_idx = None;
+    }
+
+    /// Upload a local file into the current room
+    async fn upload_file(&mut self, path: &str) -> NokResult<()> {
+        let Some(room_id) = self.current_matrix_room_id() else {
+            self.core.set_error("No room selected".to_string());
+            return Ok(());
+        };
+
+        let path = expand_home(path);
+        let kind = self.state_manager.upload_file(&room_id, &path, &mut self.logs).await?;
+        self.core.set_notification(format!("Uploaded {} as {}", path.display(), kind));
+        Ok(())
+    }
+
+    /// Download the selected message's attachment into the download directory
+    async fn save_selected_attachment(&mut self) -> NokResult<()> {
+        let Some(room_id) = self.current_matrix_room_id() else {
+            self.core.set_error("No room selected".to_string());
+            return Ok(());
+        };
+        let selected = self.data.get_selected_message(self.ui.selected_message_idx, self.ui.open_thread.as_deref())
+            .filter(|m| m.attachment.is_some() && !m.redacted)
+            .and_then(|m| m.id.clone());
+        let Some(event_id) = selected else {
+            self.core.set_error("Select a file, image or audio message to save".to_string());
+            return Ok(());
+        };
+
+        let dir = self.config.download_dir();
+        let path = self.state_manager.save_attachment(&room_id, &event_id, &dir, &mut self.logs).await?;
+        self.core.set_notification(format!("Saved to {}", path.display()));
+        Ok(())
+    }
+
+    /// Reply to a message in the current room
+    async fn send_reply(&mut self, in_reply_to: &str, message: &str) -> NokResult<()> {
+        let Some(room_id) = self.current_matrix_room_id() else {
+            return Ok(());
+        };
+
+        self.state_manager.send_reply(&room_id, in_reply_to, message, &mut self.logs).await?;
+        Ok(())
+    }
+
+    /// Replace the body of one of our own messages
+    async fn edit_message(&mut self, event_id: &str, new_content: &str) -> NokResult<()> {
+        let Some(room_id) = self.current_matrix_room_id() else {
+            return Ok(());
+        };
+
+        self.state_manager.edit_message(&room_id, event_id, new_content, &mut self.logs).await?;
+        self.core.set_notification("Message edited".to_string());
+        Ok(())
+    }
+
+    /// Ask before redacting one of our own messages, which cannot be undone
+    fn ask_delete_message(&mut self, event_id: String, content: &str) {
+        let excerpt: String = content.chars().take(60).collect();
+        let ellipsis = if excerpt.len() < content.len() { "…" } else { "" };
+        self.ui.confirm = Some(Confirmation {
+            prompt: format!("Delete your message \"{}{}\"?\nEveryone in the room sees that it was deleted.", excerpt, ellipsis),
+            action: ConfirmAction::DeleteMessage(event_id),
+        });
+    }
+
+    /// Redact one of our own messages
+    async fn delete_message(&mut self, event_id: &str) -> NokResult<()> {
+        let Some(room_id) = self.current_matrix_room_id() else {
+            return Ok(());
+        };
+
+        self.state_manager.redact_event(&room_id, event_id, &mut self.logs).await?;
+        self.core.set_notification("Message deleted".to_string());
+        Ok(())
+    }
+
+    /// Add a reaction, or take ours back if we already reacted with the same key
+    async fn toggle_reaction(&mut self, event_id: &str, input: &str) -> NokResult<()> {
+        let Some(room_id) = self.current_matrix_room_id() else {
+            return Ok(());
+        };
+        let key = reaction_key(input);
+
+        let own_reaction = self.data.messages.iter()
+            .find(|m| m.id.as_deref() == Some(event_id))
+            .and_then(|m| m.own_reaction(&key))
+            .map(|id| id.to_string());
+
+        match own_reaction {
+            Some(reaction_id) => {
+                self.state_manager.redact_event(&room_id, &reaction_id, &mut self.logs).await?;
+            }
+            None => {
+                self.state_manager.send_reaction(&room_id, event_id, &key, &mut self.logs).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Matrix ID of the room shown in the Messages pane
+    fn current_matrix_room_id(&self) -> Option<String> {
+        self.data.get_current_room().and_then(|room| room.matrix_id.clone())
+    }
+
+    /// Fetch the current room's recent timeline, on entering it. The sync
+    /// keeps it up to date after that (`receive_timeline_events`).
+    async fn load_timeline(&mut self) -> NokResult<()> {
+        let Some(room) = self.data.get_current_room() else {
+            return Ok(());
+        };
+        let Some(room_id) = room.matrix_id.clone() else {
+            return Ok(());
+        };
+        let room_name = room.name.clone();
+
+        // Mark the attempt up front so a failing homeserver is not hammered every tick
+        self.data.timeline_refreshed_at = Some(std::time::Instant::now());
+        let own_display_name = self.data.current_user.name.clone();
+        let messages = self.state_manager.load_timeline(&room_id, &room_name, Some(&own_display_name)).await?;
+        self.show_timeline(&room_id, messages).await;
+        Ok(())
+    }
+
+    /// Add what the sync brought in to the current room's timeline
+    async fn receive_timeline_events(&mut self) {
+        let Some(room) = self.data.get_current_room() else {
+            return;
+        };
+        let (Some(room_id), room_name) = (room.matrix_id.clone(), room.name.clone()) else {
+            return;
+        };
+        let own_display_name = self.data.current_user.name.clone();
+        if let Some(messages) = self.state_manager.update_timeline(&room_id, &room_name, Some(&own_display_name)).await {
+            self.show_timeline(&room_id, messages).await;
+        }
+    }
+
+    /// Show a freshly built timeline in the Messages pane
+    async fn show_timeline(&mut self, room_id: &str, mut messages: Vec<Message>) {
+        // Prefer display names already known from the Users pane
+        for message in &mut messages {
+            if let Some(user) = self.data.users.iter().find(|u| u.matrix_id.is_some() && u.matrix_id == message.sender_id) {
+                message.sender = user.name.clone();
+            }
+        }
+        let senders: Vec<(String, String)> = messages.iter()
+            .filter_map(|m| m.id.clone().map(|id| (id, m.sender.clone())))
+            .collect();
+        for message in &mut messages {
+            if let Some(reply) = message.reply_to.as_mut() {
+                if let Some((_, sender)) = senders.iter().find(|(id, _)| *id == reply.event_id) {
+                    reply.sender = sender.clone();
+                }
+            }
+        }
+
+        self.data.set_messages(messages);
+
+        // Viewing a room reads it; the badge clears once the receipt syncs back
+        if let Some(latest) = self.data.messages.last().and_then(|m| m.id.clone()) {
+            if self.data.last_read_event.as_ref() != Some(&latest) {
+                match self.state_manager.mark_read(room_id, &latest).await {
+                    Ok(()) => self.data.last_read_event = Some(latest),
+                    Err(e) => self.logs.add_debug_log(format!("Failed to send read receipt: {}", e)),
+                }
+            }
+        }
+
+        // Keep the selection inside the new list
+        let visible = self.data.visible_messages(self.ui.open_thread.as_deref()).len();
+        if let Some(selected) = self.ui.selected_message_idx {
+            if selected >= visible {
+                self.ui.selected_message_idx = visible.checked_sub(1);
+            }
+        }
+    }
+
+    /// Periodic work driven by the UI loop
+    pub async fn tick(&mut self) {
+        self.logs.take_background();
+        if !self.state_manager.matrix().is_logged_in() {
+            return;
+        }
+
+        // Search the directory once typing pauses
+        let search_due = self.ui.room_directory.query_changed_at
+            .is_some_and(|at| at.elapsed() >= DIRECTORY_SEARCH_DELAY);
+        if self.core.state == super::state::AppState::RoomDirectory && search_due {
+            self.ui.room_directory.query_changed_at = None;
+            self.load_directory_page(None, 0).await;
+        }
+
+        let search_due = self.ui.user_directory.query_changed_at
+            .is_some_and(|at| at.elapsed() >= DIRECTORY_SEARCH_DELAY);
+        if self.core.state == super::state::AppState::UserDirectory && search_due {
+            self.search_user_directory().await;
+        }
+
+        // A room's state is read in full on entering it; after that only
+        // what the sync loop saw change is read again
+        let changes = self.state_manager.take_state_changes();
+        let room_id = self.current_matrix_room_id();
+        let entered = room_id != self.data.location_room;
+        let users_due = self.data.users_refreshed_at
+            .is_none_or(|at| at.elapsed() >= USERS_REFRESH_INTERVAL);
+
+        // An office that failed to load is tried again on the next tick
+        let office_stale = self.data.office_room != room_id
+            || changes.in_room(room_id.as_deref(), &OFFICE_STATE_EVENTS);
+        if office_stale {
+            if let Err(e) = self.refresh_office().await {
+                self.logs.add_debug_log(format!("Office refresh failed: {}", e));
+            }
+        }
+        let kind_changed = entered || changes.in_room(room_id.as_deref(), &[NokRoomKindEventContent::TYPE]);
+        if let Err(e) = self.apply_room_rules(kind_changed).await {
+            self.core.set_error(format!("Failed to apply room rules: {}", e));
+        }
+        // Occupants who went offline are dropped along with the presence
+        let occupants_changed = entered || users_due
+            || changes.in_room(room_id.as_deref(), &[NokLocationEventContent::TYPE]);
+        self.update_location(occupants_changed).await;
+        self.receive_encryption_changes();
+        self.update_verification().await;
+        self.update_huddle();
+        self.publish_position().await;
+        self.receive_knocks();
+
+        self.refresh_room_counts();
+        if changes.anywhere(&[RoomMemberEventContent::TYPE]) {
+            if let Err(e) = self.refresh_invites(true).await {
+                self.logs.add_debug_log(format!("Invite refresh failed: {}", e));
+            }
+        }
+
+        // Joins, leaves, names and topics come with the sync; presence
+        // does not, so the Users pane is also re-read now and then
+        let rooms_changed = self.data.users_refreshed_at.is_none() || changes.anywhere(&[
+            RoomMemberEventContent::TYPE,
+            RoomNameEventContent::TYPE,
+            RoomTopicEventContent::TYPE,
+        ]);
+        if rooms_changed {
+            if let Err(e) = self.sync_rooms_from_matrix().await {
+                self.logs.add_debug_log(format!("Room sync failed: {}", e));
+            }
+        }
+        if rooms_changed || users_due {
+            if let Err(e) = self.refresh_users().await {
+                self.logs.add_debug_log(format!("Users refresh failed: {}", e));
+            }
+        }
+
+        // Rooms are loaded on entering them; this catches a load that failed
+        let unloaded = self.current_matrix_room_id()
+            .is_some_and(|room_id| !self.state_manager.has_timeline_of(&room_id));
+        let retry_due = self.data.timeline_refreshed_at
+            .is_none_or(|at| at.elapsed() >= TIMELINE_RETRY_INTERVAL);
+        if unloaded && retry_due {
+            if let Err(e) = self.load_timeline().await {
+                self.logs.add_debug_log(format!("Timeline load failed: {}", e));
+            }
+        }
+        self.receive_timeline_events().await;
+    }
+
+    /// Read everyone's office positions in the current room from synced
+    /// state, placing ourselves where we last stood when switching rooms
+    async fn refresh_office(&mut self) -> NokResult<()> {
+        let Some(room_id) = self.current_matrix_room_id() else {
+            self.data.office_room = None;
+            return Ok(());
+        };
+        self.data.office_map = match self.state_manager.office_plan(&room_id).await {
+            Ok(plan) => plan.unwrap_or_default(),
+            Err(NokError::InvalidInput(reason)) => {
+                self.logs.add_debug_log(format!("Ignoring unreadable floor plan in {}: {}", room_id, reason));
+                OfficeMap::default()
+            }
+            Err(e) => return Err(e),
+        };
+        let positions = self.state_manager.office_positions(&room_id).await?;
+        let desks = self.state_manager.office_desks(&room_id).await?;
+        let own_id = self.state_manager.matrix().user_id();
+        let own_published = positions.iter()
+            .find(|(user_id, _)| Some(user_id) == own_id.as_ref())
+            .map(|(_, published)| PublishedPosition {
+                position: self.data.office_map.clamp(published.position),
+                huddle: published.huddle.clone(),
+            });
+        let own_position = own_published.as_ref().map(|published| published.position);
+        let own_desk = desks.iter()
+            .find(|(user_id, _)| Some(user_id) == own_id.as_ref())
+            .map(|(_, desk)| self.data.office_map.clamp(*desk));
+        let refused = !self.state_manager.can_publish_position(&room_id).await?;
+
+        if self.data.office_room.as_ref() != Some(&room_id) {
+            self.data.office_room = Some(room_id);
+            self.ui.office_editor = None;
+            self.data.huddle_members.clear();
+            self.data.huddle_root = None;
+            self.data.pending_position = None;
+            self.data.position_refused = false;
+            // We sit down at our desk when coming in, if we have one
+            self.ui.my_aa_position = own_desk.or(own_position)
+                .unwrap_or_else(|| self.data.office_map.spawn_point(0));
+            // Only moving publishes, so visiting a room leaves no state behind
+            self.data.published_position = own_published.or(Some(PublishedPosition {
+                position: self.ui.my_aa_position,
+                huddle: None,
+            }));
+        } else {
+            // The floor plan may have changed under us
+            self.ui.my_aa_position = self.data.office_map.clamp(self.ui.my_aa_position);
+        }
+        // Power levels can change at any time, so this is checked on every
+        // refresh but reported only when it starts
+        if refused && !self.data.position_refused {
+            self.core.set_error(
+                "Others can't see you move here: a room admin must lower the power level of com.nok.office.position to 0".to_string()
+            );
+        }
+        self.data.position_refused = refused;
+        self.data.office_desks = desks.into_iter().collect();
+        self.data.office_positions = positions.into_iter()
+            .filter(|(user_id, _)| Some(user_id) != own_id.as_ref())
+            .collect();
+        Ok(())
+    }
+
+    /// Send our position once we have moved or changed huddle thread
+    /// since the last publish. A move is sent after we have stood still
+    /// for `POSITION_PUBLISH_DELAY`; a new huddle thread straight away.
+    async fn publish_position(&mut self) {
+        let Some(room_id) = self.data.office_room.clone() else {
+            return;
+        };
+        if self.data.position_refused {
+            return;
+        }
+        let current = PublishedPosition {
+            position: self.ui.my_aa_position,
+            huddle: self.data.huddle_root.clone(),
+        };
+        if self.data.published_position.as_ref() == Some(&current) {
+            self.data.pending_position = None;
+            return;
+        }
+        let huddle_changed = self.data.published_position.as_ref()
+            .is_none_or(|published| published.huddle != current.huddle);
+        if !huddle_changed {
+            match self.data.pending_position {
+                Some((position, since)) if position == current.position => {
+                    if since.elapsed() < POSITION_PUBLISH_DELAY {
+                        return;
+                    }
+                }
+                _ => {
+                    self.data.pending_position = Some((current.position, std::time::Instant::now()));
+                    return;
+                }
+            }
+        }
+        self.data.pending_position = None;
+        // Marked up front so a refusal is reported once, not every tick
+        self.data.published_position = Some(current.clone());
+        if let Err(e) = self.state_manager.publish_position(&room_id, &current, &mut self.logs).await {
+            self.core.set_error(format!("Could not share your position: {}", e));
+        }
+    }
+
+    /// Work out who stands close enough to huddle with us and which thread
+    /// the huddle talks in. The thread opens in the side view while the
+    /// huddle lasts.
+    fn update_huddle(&mut self) {
+        let avatars = self.data.office_avatars(self.ui.my_aa_position);
+        let others: Vec<&office::Avatar> = office::huddle(&avatars, self.config.app.huddle_distance)
+            .into_iter()
+            .filter(|avatar| !avatar.is_self)
+            .collect();
+        let members: Vec<String> = others.iter().map(|avatar| avatar.user_id.clone()).collect();
+
+        // Everyone adopts the smallest thread ID used in the huddle, so two
+        // huddles that merge end up talking in one thread
+        let root = if members.is_empty() {
+            None
+        } else {
+            members.iter()
+                .filter_map(|user_id| self.data.office_positions.get(user_id)?.huddle.clone())
+                .chain(self.data.huddle_root.clone())
+                .min()
+        };
+
+        if members.is_empty() && !self.data.huddle_members.is_empty() {
+            self.core.set_notification("You left the huddle".to_string());
+        } else if !members.is_empty() && self.data.huddle_members.is_empty() {
+            let names: Vec<&str> = others.iter().map(|avatar| avatar.name.as_str()).collect();
+            self.core.set_notification(format!("Huddling with {}; messages now go to the huddle", names.join(", ")));
+        }
+        if root != self.data.huddle_root {
+            if root.is_some() {
+                self.ui.open_thread = root.clone();
+                self.ui.selected_message_idx = Some(0);
+            } else if self.ui.open_thread == self.data.huddle_root {
+                self.ui.open_thread = None;
+            }
+        }
+        self.data.huddle_members = members;
+        self.data.huddle_root = root;
+    }
+
+    /// Send a message to the huddle, starting its thread on the first one
+    async fn send_huddle_message(&mut self, message: &str) -> NokResult<()> {
+        let Some(room_id) = self.data.office_room.clone() else {
+            return Ok(());
+        };
+        let root = match self.data.huddle_root.clone() {
+            Some(root) => root,
+            None => {
+                let mut names = vec![self.data.current_user.name.clone()];
+                names.extend(self.data.huddle_members.iter().map(|user_id| {
+                    self.data.users.iter()
+                        .find(|u| u.matrix_id.as_ref() == Some(user_id))
+                        .map_or_else(|| user_id.clone(), |u| u.name.clone())
+                }));
+                let notice = format!("🗣 Huddle: {}", names.join(", "));
+                let root = self.state_manager.start_huddle_thread(&room_id, &notice, &mut self.logs).await?;
+                self.data.huddle_root = Some(root.clone());
+                root
+            }
+        };
+        self.ui.open_thread = Some(root);
+        // Let the others pick up the thread straight away
+        self.publish_position().await;
+        self.send_thread_message(message).await
+    }
+
+    /// Show or change how close people must stand to huddle
+    fn set_huddle_distance(&mut self, distance: Option<&str>) {
+        let Some(distance) = distance else {
+            self.core.set_notification(format!("Huddle distance: {} cells", self.config.app.huddle_distance));
+            return;
+        };
+        let Ok(distance) = distance.parse::<u16>() else {
+            self.core.set_error("Usage: /huddle [cells] (0 turns huddles off)".to_string());
+            return;
+        };
+        self.config.app.huddle_distance = distance;
+        if let Err(e) = self.config.save() {
+            self.logs.add_debug_log(format!("Failed to save configuration: {}", e));
+        }
+        self.core.set_notification(match distance {
+            0 => "Huddles turned off".to_string(),
+            _ => format!("Huddle distance set to {} cells", distance),
+        });
+    }
+
+    /// Start editing the current room's floor plan from our position
+    fn open_office_editor(&mut self) {
+        if self.data.office_room.is_none() {
+            self.core.set_error("Enter a room to edit its floor plan".to_string());
+            return;
+        }
+        self.ui.office_editor = Some(OfficeEditor::new(self.data.office_map.clone(), self.ui.my_aa_position));
+        self.core.set_notification("Editing the floor plan (Enter saves, Esc discards)".to_string());
+    }
+
+    /// Save the edited floor plan to the room for everyone
+    async fn save_office_plan(&mut self) -> NokResult<()> {
+        let (Some(room_id), Some(editor)) = (self.data.office_room.clone(), self.ui.office_editor.as_ref()) else {
+            return Ok(());
+        };
+        // Whatever we save has to read back, e.g. a plan painted solid wall does not
+        let draft = OfficeMap::parse(&editor.draft.to_text())?;
+        self.state_manager.publish_office_plan(&room_id, &draft, &mut self.logs).await?;
+
+        self.data.office_map = draft;
+        self.ui.my_aa_position = self.data.office_map.clamp(self.ui.my_aa_position);
+        self.ui.office_editor = None;
+        self.core.set_notification("Floor plan saved".to_string());
+        Ok(())
+    }
+
+    /// Load a floor plan file into the editor, to be saved with Enter
+    fn import_office_plan(&mut self, path: &str) -> NokResult<()> {
+        if self.data.office_room.is_none() {
+            self.core.set_error("Enter a room to load a floor plan into".to_string());
+            return Ok(());
+        }
+        let path = expand_home(path);
+        let draft = OfficeMap::parse(&std::fs::read_to_string(&path)?)?;
+        let cursor = draft.clamp(self.ui.my_aa_position);
+        self.ui.office_editor = Some(OfficeEditor::new(draft, cursor));
+        self.core.focused_pane = PaneIdentifier::AsciiArt;
+        self.core.set_notification(format!("Loaded {}; press Enter in the office pane to save it", path.display()));
+        Ok(())
+    }
+
+    /// Write the floor plan being edited, or the room's current one, to a file
+    fn export_office_plan(&mut self, path: &str) -> NokResult<()> {
+        let map = self.ui.office_editor.as_ref()
+            .map(|editor| &editor.draft)
+            .unwrap_or(&self.data.office_map);
+        let path = expand_home(path);
+        std::fs::write(&path, map.to_text())?;
+        self.core.set_notification(format!("Floor plan written to {}", path.display()));
+        Ok(())
+    }
+
+    /// Name the zone marked out in the floor plan editor
+    fn name_office_zone(&mut self, name: &str) {
+        let named = !name.is_empty()
+            && self.ui.office_editor.as_mut().is_some_and(|editor| editor.name_pending_zone(name));
+        if named {
+            self.core.focused_pane = PaneIdentifier::AsciiArt;
+            self.core.set_notification(format!("Added zone '{}'", name));
+        } else {
+            self.core.set_error("Mark a zone's corners with z in the floor plan editor, then /zone <name>".to_string());
+        }
+    }
+
+    /// Claim the desk we are standing at; we start there whenever we
+    /// enter this room. `release` gives our desk up instead.
+    async fn claim_desk(&mut self, release: bool) -> NokResult<()> {
+        let (Some(room_id), Some(own_id)) = (self.data.office_room.clone(), self.state_manager.matrix().user_id()) else {
+            self.core.set_error("Enter a room to claim a desk".to_string());
+            return Ok(());
+        };
+
+        if release {
+            if !self.data.office_desks.contains_key(&own_id) {
+                self.core.set_error("You have no desk in this room".to_string());
+                return Ok(());
+            }
+            self.state_manager.claim_desk(&room_id, None, &mut self.logs).await?;
+            self.data.office_desks.remove(&own_id);
+            self.core.set_notification("Desk released".to_string());
+            return Ok(());
+        }
+
+        let position = self.ui.my_aa_position;
+        if self.data.office_map.tile(position) != Tile::Desk {
+            self.core.set_error("Walk onto a desk to claim it".to_string());
+            return Ok(());
+        }
+        if let Some(owner) = self.data.desk_owner(position).filter(|owner| *owner != own_id) {
+            let owner = self.data.users.iter()
+                .find(|u| u.matrix_id.as_deref() == Some(owner))
+                .map_or(owner, |u| u.name.as_str());
+            self.core.set_error(format!("That desk belongs to {}", owner));
+            return Ok(());
+        }
+        self.state_manager.claim_desk(&room_id, Some(position), &mut self.logs).await?;
+        self.data.office_desks.insert(own_id, position);
+        self.core.set_notification("Desk claimed; you will sit here when you enter this room".to_string());
+        Ok(())
+    }
+
+    /// Pick up unread badges from the sync loop and announce new mentions
+    fn refresh_room_counts(&mut self) {
+        let mut mentioned_in = Vec::new();
+        for room in &mut self.data.rooms {
+            let Some(room_id) = room.matrix_id.as_deref() else {
+                continue;
+            };
+            let Ok((unread, highlights)) = self.state_manager.room_unread_counts(room_id) else {
+                continue;
+            };
+            if highlights > room.highlight_count {
+                mentioned_in.push(room.name.clone());
+            }
+            room.unread_count = unread;
+            room.highlight_count = highlights;
+        }
+
+        if !mentioned_in.is_empty() {
+            self.notify_mention(&mentioned_in.join(", "));
+        }
+    }
+
+    /// Re-read pending invites, announcing ones that arrived since the last check
+    async fn refresh_invites(&mut self, announce: bool) -> NokResult<()> {
+        let invites = self.state_manager.pending_invites().await?;
+        let new_invites = self.data.set_invites(invites);
+        if announce {
+            for invite in &new_invites {
+                self.logs.add_debug_log(format!("Invited to {} by {}", invite.room_name, invite.inviter()));
+                if self.config.app.enable_notifications {
+                    self.core.set_notification(format!(
+                        "{} invited you to {} (Rooms pane: a to accept, d to decline)",
+                        invite.inviter(), invite.room_name
+                    ));
+                }
+            }
+        }
+        self.clamp_room_selection();
+        Ok(())
+    }
+
+    /// Join an invited room and switch to it
+    async fn accept_invite(&mut self, room_id: &str) -> NokResult<()> {
+        self.state_manager.accept_invite(room_id, &mut self.logs).await?;
+        self.data.invites.retain(|i| i.room_id != room_id);
+        self.sync_rooms_from_matrix().await?;
+        self.enter_matrix_room(room_id).await?;
+        self.refresh_users().await
+    }
+
+    /// Reject an invite and drop it from the Rooms pane
+    async fn decline_invite(&mut self, room_id: &str) -> NokResult<()> {
+        self.state_manager.decline_invite(room_id, &mut self.logs).await?;
+        if let Some(idx) = self.data.invites.iter().position(|i| i.room_id == room_id) {
+            let invite = self.data.invites.remove(idx);
+            self.core.set_notification(format!("Declined invite to {}", invite.room_name));
+        }
+        self.clamp_room_selection();
+        Ok(())
+    }
+
+    /// Keep the Rooms pane selection on an existing room or invite
+    fn clamp_room_selection(&mut self) {
+        let entries = self.data.room_pane_entries().len();
+        self.ui.selected_room_idx = self.ui.selected_room_idx.min(entries.saturating_sub(1));
+    }
+
+    /// Mention alert, kept separate from the knock sound
+    fn notify_mention(&mut self, rooms: &str) {
+        self.logs.add_debug_log(format!("Mentioned in {}", rooms));
+        if self.config.app.enable_notifications {
+            self.core.set_notification(format!("You were mentioned in {}", rooms));
+        }
+        if self.config.app.mention_sound {
+            self.play_sound("mention", crate::audio::play_mention_sound);
+        }
+    }
+
+    /// Enter on the focused pane
+    async fn confirm_selection(&mut self) -> NokResult<()> {
+        match self.core.focused_pane {
+            PaneIdentifier::Rooms => {
+                match self.data.get_selected_room_idx(self.ui.selected_room_idx) {
+                    // Floors fold and unfold instead of opening a timeline
+                    Some(idx) if self.data.rooms[idx].is_space => {
+                        let space = &mut self.data.rooms[idx];
+                        space.collapsed = !space.collapsed;
+                    }
+                    Some(idx) => self.enter_room(idx).await?,
+                    None => {}
+                }
+            }
+            PaneIdentifier::Users => {
+                let user_id = self.data.get_selected_user(self.ui.selected_user)
+                    .and_then(|user| user.matrix_id.clone());
+                match user_id {
+                    Some(user_id) => self.open_dm(&user_id).await?,
+                    None => self.core.set_error("No user selected".to_string()),
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Make a room current and load its timeline
+    async fn enter_room(&mut self, idx: usize) -> NokResult<()> {
+        if let Some(room) = self.data.rooms.get(idx) {
+            let room_name = room.name.clone();
+            self.data.set_current_room_idx(idx);
+            self.follow_room_account();
+            if let Some(position) = self.data.room_pane_position(idx) {
+                self.ui.selected_room_idx = position;
+            }
+            self.data.set_messages(Vec::new());
+            self.close_thread();
+            self.core.set_notification(format!("Entered {}", room_name));
+            self.load_timeline().await?;
+        }
+        Ok(())
+    }
+
+    /// Act as the account that is in the current room, so whatever we
+    /// send there, and whoever we DM or knock from it, goes out from it
+    fn follow_room_account(&mut self) {
+        let Some(account) = self.data.get_current_room().and_then(|room| room.account.clone()) else {
+            return;
+        };
+        if self.state_manager.matrix_mut().set_active_account(&account) {
+            self.data.current_user.matrix_id = Some(account);
+        }
+    }
+
+    /// `/account` lists our accounts, `/account add` logs in another one
+    /// and `/account <number or MXID>` makes one active
+    fn account_command(&mut self, arg: Option<&str>) {
+        let accounts = self.state_manager.matrix().account_ids();
+        match arg {
+            None => {
+                let active = self.state_manager.matrix().user_id();
+                let list: Vec<String> = accounts.iter().enumerate()
+                    .map(|(i, id)| {
+                        let marker = if Some(id) == active.as_ref() { " (active)" } else { "" };
+                        format!("{}. {}{}", i + 1, id, marker)
+                    })
+                    .collect();
+                self.core.set_notification(format!("Accounts: {}  (/account add, /account <n>)", list.join(", ")));
+            }
+            Some("add") => {
+                let login = &mut self.state_manager.matrix_mut().login;
+                login.clear_credentials();
+                login.clear_error();
+                login.saved_account_index = None;
+                login.set_field_focus(LoginField::Username);
+                self.core.state = super::state::AppState::Login;
+            }
+            Some(which) => {
+                let account = match which.parse::<usize>() {
+                    Ok(n) => accounts.get(n.wrapping_sub(1)).cloned(),
+                    Err(_) => accounts.iter().find(|id| id.as_str() == which).cloned(),
+                };
+                match account {
+                    Some(account) if self.state_manager.matrix_mut().set_active_account(&account) => {
+                        self.data.current_user.matrix_id = Some(account.clone());
+                        self.core.set_notification(format!("Acting as {}", account));
+                    }
+                    _ => self.core.set_error(format!("No logged in account {}", which)),
+                }
+            }
+        }
+    }
+
+    /// Switch to the DM with a user, creating the room on first use
+    async fn open_dm(&mut self, user_id: &str) -> NokResult<()> {
+        let room_id = self.state_manager.open_dm(user_id, &mut self.logs).await?;
+
+        let idx = match self.data.rooms.iter().position(|r| r.matrix_id.as_deref() == Some(room_id.as_str())) {
+            Some(idx) => idx,
+            None => {
+                let name = self.data.users.iter()
+                    .find(|u| u.matrix_id.as_deref() == Some(user_id))
+                    .map(|u| u.name.clone())
+                    .unwrap_or_else(|| extract_username_from_matrix_id(user_id));
+                let mut room = Room::from_matrix_room(room_id, name);
+                room.is_direct = true;
+                room.dm_user_id = Some(user_id.to_string());
+                room.account = self.state_manager.matrix().user_id();
+                self.data.add_room(room)
+            }
+        };
+        self.enter_room(idx).await
+    }
+
+    /// Turn "@alice:server", "@alice" or a Users pane name into a full MXID
+    fn resolve_user_id(&self, query: &str) -> String {
+        self.data.resolve_user_id(query, &self.config.matrix.server_name)
+    }
+
+    /// Rebuild the Users pane from the members of all joined rooms
+    async fn refresh_users(&mut self) -> NokResult<()> {
+        self.data.users_refreshed_at = Some(std::time::Instant::now());
+        let own_ids = self.state_manager.matrix().account_ids();
+        let room_ids: Vec<String> = self.data.rooms.iter().filter_map(|r| r.matrix_id.clone()).collect();
+
+        let mut users: Vec<User> = Vec::new();
+        for room_id in &room_ids {
+            for mut member in self.state_manager.room_members(room_id).await? {
+                if member.matrix_id.as_ref().is_some_and(|id| own_ids.contains(id)) {
+                    continue;
+                }
+                match users.iter_mut().find(|u| u.matrix_id == member.matrix_id) {
+                    Some(user) => user.rooms.push(room_id.clone()),
+                    None => {
+                        member.rooms.push(room_id.clone());
+                        users.push(member);
+                    }
+                }
+            }
+        }
+        users.sort_by_key(|u| u.name.to_lowercase());
+
+        // DMs are listed under the other person's display name
+        for room in &mut self.data.rooms {
+            let partner = room.dm_user_id.as_ref()
+                .and_then(|id| users.iter().find(|u| u.matrix_id.as_ref() == Some(id)));
+            if let Some(partner) = partner {
+                room.name = partner.name.clone();
+            }
+        }
+
+        // Keep the selection on the same person
+        let selected_id = self.data.get_selected_user(self.ui.selected_user)
+            .and_then(|u| u.matrix_id.clone());
+        self.data.users = users;
+        self.ui.selected_user = selected_id
+            .and_then(|id| self.data.visible_users().iter().position(|u| u.matrix_id.as_ref() == Some(&id)));
+        Ok(())
+    }
+
+    /// Toggle between communication modes
+    async fn toggle_matrix_mode(&mut self) -> NokResult<()> {
+        let current_mode = self.state_manager.get_mode();
         let new_mode = match current_mode {
             CommunicationMode::Matrix => CommunicationMode::Legacy,
             CommunicationMode::Legacy => CommunicationMode::Hybrid,
@@ -411,37 +2339,240 @@ impl App {
     /// Set user status
     async fn set_status(&mut self, status: &str) -> NokResult<()> {
         self.state_manager.set_presence(status, &mut self.logs).await?;
+        self.data.current_user.status = match status {
+            "away" => super::user::UserStatus::Away,
+            "busy" => super::user::UserStatus::Busy,
+            "offline" => super::user::UserStatus::Offline,
+            _ => super::user::UserStatus::Online,
+        };
         self.core.set_notification(format!("Status set to: {}", status));
         Ok(())
     }
 
-    /// Join a room
-    async fn join_room(&mut self, room_name: &str) -> NokResult<()> {
-        self.logs.add_debug_log(format!("Joining room: {}", room_name));
-        // Implementation would join room in Matrix or legacy system
+    /// Show the public room directory, searching for `query`
+    async fn open_room_directory(&mut self, query: &str) {
+        self.ui.room_directory.reset(query);
+        self.core.state = super::state::AppState::RoomDirectory;
+        self.load_directory_page(None, 0).await;
+    }
+
+    /// Fetch a page of the directory for the current query. Failures are
+    /// shown without closing the overlay so the search can be edited.
+    async fn load_directory_page(&mut self, since: Option<String>, page_number: usize) {
+        let query = self.ui.room_directory.query.clone();
+        match self.state_manager.public_rooms(&query, since.as_deref(), &mut self.logs).await {
+            Ok(page) => self.ui.room_directory.show_page(page, page_number),
+            Err(e) => self.core.set_error(format!("Room directory unavailable: {}", e)),
+        }
+    }
+
+    /// Show the user directory overlay, searching for `query`
+    async fn open_user_directory(&mut self, query: &str) {
+        self.ui.user_directory.reset(query);
+        self.core.state = super::state::AppState::UserDirectory;
+        if !query.is_empty() {
+            self.search_user_directory().await;
+        }
+    }
+
+    /// Run the user directory search for the current query
+    async fn search_user_directory(&mut self) {
+        self.ui.user_directory.query_changed_at = None;
+        let query = self.ui.user_directory.query.trim().to_string();
+        if query.is_empty() {
+            return;
+        }
+        match self.state_manager.search_users(&query, &mut self.logs).await {
+            Ok((results, limited)) => {
+                let directory = &mut self.ui.user_directory;
+                directory.results = results;
+                directory.limited = limited;
+                directory.selected = 0;
+            }
+            Err(e) => self.core.set_error(format!("User directory unavailable: {}", e)),
+        }
+    }
+
+    /// Join a room by alias or ID and switch to it
+    async fn join_room(&mut self, room: &str) -> NokResult<()> {
+        let room_id = self.state_manager.join_room(room, &mut self.logs).await?;
+        self.sync_rooms_from_matrix().await?;
+        self.enter_matrix_room(&room_id).await?;
+        self.refresh_users().await
+    }
+
+    /// Create a room from `/create` and switch to it
+    async fn create_room(&mut self, args: CreateRoomArgs) -> NokResult<()> {
+        let room_id = self.state_manager
+            .create_room(&args.name, args.alias.as_deref(), args.private, args.encrypted, &mut self.logs)
+            .await?;
+        self.sync_rooms_from_matrix().await?;
+        self.enter_matrix_room(&room_id).await
+    }
+
+    /// Invite someone into the current room
+    async fn invite_user(&mut self, user_id: &str) -> NokResult<()> {
+        let Some(room_id) = self.current_matrix_room_id() else {
+            self.core.set_error("No room selected".to_string());
+            return Ok(());
+        };
+        self.state_manager.invite_user(&room_id, user_id, &mut self.logs).await?;
+        self.core.set_notification(format!("Invited {}", user_id));
+        Ok(())
+    }
+
+    /// Leave the current room and drop it from the Rooms pane
+    async fn leave_room(&mut self) -> NokResult<()> {
+        let Some(room) = self.data.get_current_room() else {
+            self.core.set_error("No room selected".to_string());
+            return Ok(());
+        };
+        let (Some(room_id), room_name) = (room.matrix_id.clone(), room.name.clone()) else {
+            return Ok(());
+        };
+        self.state_manager.leave_room(&room_id, &mut self.logs).await?;
+        self.sync_rooms_from_matrix().await?;
+        self.refresh_users().await?;
+        self.core.set_notification(format!("Left {}", room_name));
+        Ok(())
+    }
+
+    /// Remove someone from the current room
+    async fn kick_user(&mut self, user_id: &str, reason: Option<&str>) -> NokResult<()> {
+        let Some(room_id) = self.current_matrix_room_id() else {
+            self.core.set_error("No room selected".to_string());
+            return Ok(());
+        };
+        self.state_manager.kick_user(&room_id, user_id, reason, &mut self.logs).await?;
+        self.refresh_users().await?;
+        self.core.set_notification(format!("Kicked {}", user_id));
+        Ok(())
+    }
+
+    /// Change the topic of the current room
+    async fn set_topic(&mut self, topic: &str) -> NokResult<()> {
+        let Some(room_id) = self.current_matrix_room_id() else {
+            self.core.set_error("No room selected".to_string());
+            return Ok(());
+        };
+        self.state_manager.set_topic(&room_id, topic, &mut self.logs).await?;
+        if let Some(room) = self.data.get_current_room_mut() {
+            room.set_topic((!topic.is_empty()).then(|| topic.to_string()));
+        }
+        self.core.set_notification("Topic updated".to_string());
         Ok(())
     }
 
+    /// Enter the room with the given Matrix ID if it is in the Rooms pane
+    async fn enter_matrix_room(&mut self, room_id: &str) -> NokResult<()> {
+        match self.data.rooms.iter().position(|r| r.matrix_id.as_deref() == Some(room_id)) {
+            Some(idx) => self.enter_room(idx).await,
+            None => Ok(()),
+        }
+    }
+
     /// Show help information
     fn show_help(&mut self) {
         let help_text = r#"
 Commands:
   /help - Show this help
   /status <status> - Set your status
-  /join <room> - Join a room
+  /join [search] - Browse the public room directory (Enter joins)
+  /join <#alias:server or !room_id> - Join a room directly
+  /create <name> [--alias <alias>] [--private] [--encrypted] - Create a room
+  /invite @user - Invite someone to the current room
+  /leave - Leave the current room
+  /kick @user [reason] - Remove someone from the current room
+  /topic <text> - Set the current room's topic
+  /profile @user - Show someone's profile and whether you verified them
+  /verify @user [device_id] - Verify someone (or one of their devices) by comparing emojis
+  /encrypt - Turn on end-to-end encryption in the current room (asks first)
+  /logout [wipe] - Log out on the server and return to login; wipe also deletes the local store
+  /account [add|<n>] - List your accounts, log in another one, or act as account n
   nok @username - Send knock to user
+  /dm @user - Open a direct message with someone
+  /who <query> - Search the user directory (Tab, then k knock / m DM / i invite)
+  /upload <path> - Send a file, image or audio clip
+  /save - Download the selected attachment
+  /desk [release] - Claim the desk you stand at as your spot in this room
+  /plan import|export <path> - Load a floor plan file into the editor, or write one out
+  /zone <name> - Name the zone marked in the floor plan editor
+  /huddle [cells] - Show or set how close people must stand to huddle (0 = off)
+  /roomtype [focus|meeting|lounge|none] - Show or set the current room's type
   
 Keys:
   q - Quit
   s - Settings
   i - Input mode
   k - Send knock to selected user
+  f - Switch floor (space); narrows the Rooms and Users panes
   Tab - Cycle focus
+  Enter - Enter selected room / fold a floor / open DM with selected user
+
+Rooms pane invites:
+  a / Enter - Accept the selected invite
+  d - Decline the selected invite
+
+Settings:
+  b - Set up cross-signing and key backup (gives you a recovery key)
+  r - Restore keys from backup with your recovery key
+
+Users pane:
+  w - Show only who is in this room now / all members
+  p - Show the selected user's profile (v verifies them)
+
+Messages pane:
+  r - Reply to selected message
+  e - Edit your message
+  d - Delete your message
+  + - React (emoji or :+1: :heart: :laugh: :tada: :eyes:)
+  t - Open thread (Esc closes, input goes to the thread)
+
+Office pane:
+  ←↑→↓ or i/j/k/l - Walk around the current room's office
+  n - Knock on the person next to you
+  e - Edit the floor plan
+  Standing next to someone starts a huddle: the input line then talks
+  only to the huddle, in a thread of the room shown in the side view
+
+Floor plan editor:
+  ←↑→↓ or i/j/k/l - Move the cursor
+  # . d + - Paint wall, floor, desk or door
+  z - Mark a zone's corners, then name it with /zone
+  x - Remove the zone under the cursor
+  Enter - Save the plan to the room / Esc - Discard changes
         "#;
         
         self.core.set_notification(help_text.to_string());
     }
 
+    /// Floor switcher: step through all floors, then back to everything
+    fn cycle_floor(&mut self) {
+        let floors: Vec<(String, String)> = self.data.rooms.iter()
+            .filter(|r| r.is_space)
+            .filter_map(|r| r.matrix_id.clone().map(|id| (id, r.name.clone())))
+            .collect();
+        if floors.is_empty() {
+            self.core.set_notification("No floors (spaces) joined".to_string());
+            return;
+        }
+
+        let next = match self.data.active_space.as_ref() {
+            None => floors.first(),
+            Some(current) => floors.iter()
+                .position(|(id, _)| id == current)
+                .and_then(|pos| floors.get(pos + 1)),
+        };
+        self.data.active_space = next.map(|(id, _)| id.clone());
+        match next {
+            Some((_, name)) => self.core.set_notification(format!("Floor: {}", name)),
+            None => self.core.set_notification("All floors".to_string()),
+        }
+
+        self.ui.selected_room_idx = 0;
+        self.ui.selected_user = None;
+    }
+
     /// Navigation helpers
     fn navigate_up(&mut self) {
         match self.core.focused_pane {
@@ -450,8 +2581,8 @@ Keys:
                     if selected > 0 {
                         self.ui.selected_user = Some(selected - 1);
                     }
-                } else if !self.data.users.is_empty() {
-                    self.ui.selected_user = Some(self.data.users.len() - 1);
+                } else {
+                    self.ui.selected_user = self.data.visible_users().len().checked_sub(1);
                 }
             }
             PaneIdentifier::Rooms => {
@@ -459,6 +2590,16 @@ Keys:
                     self.ui.selected_room_idx -= 1;
                 }
             }
+            PaneIdentifier::Messages => {
+                let visible = self.data.visible_messages(self.ui.open_thread.as_deref()).len();
+                if let Some(selected) = self.ui.selected_message_idx {
+                    if selected > 0 {
+                        self.ui.selected_message_idx = Some(selected - 1);
+                    }
+                } else if visible > 0 {
+                    self.ui.selected_message_idx = Some(visible - 1);
+                }
+            }
             _ => {}
         }
     }
@@ -466,19 +2607,31 @@ Keys:
     fn navigate_down(&mut self) {
         match self.core.focused_pane {
             PaneIdentifier::Users => {
+                let visible = self.data.visible_users().len();
                 if let Some(selected) = self.ui.selected_user {
-                    if selected < self.data.users.len() - 1 {
+                    if selected + 1 < visible {
                         self.ui.selected_user = Some(selected + 1);
                     }
-                } else if !self.data.users.is_empty() {
+                } else if visible > 0 {
                     self.ui.selected_user = Some(0);
                 }
             }
             PaneIdentifier::Rooms => {
-                if self.ui.selected_room_idx < self.data.rooms.len().saturating_sub(1) {
+                let entries = self.data.room_pane_entries().len();
+                if self.ui.selected_room_idx < entries.saturating_sub(1) {
                     self.ui.selected_room_idx += 1;
                 }
             }
+            PaneIdentifier::Messages => {
+                let visible = self.data.visible_messages(self.ui.open_thread.as_deref()).len();
+                if let Some(selected) = self.ui.selected_message_idx {
+                    if selected + 1 < visible {
+                        self.ui.selected_message_idx = Some(selected + 1);
+                    }
+                } else if visible > 0 {
+                    self.ui.selected_message_idx = Some(visible - 1);
+                }
+            }
             _ => {}
         }
     }
@@ -508,6 +2661,13 @@ Keys:
     /// Cleanup when app shuts down
     pub async fn shutdown(&mut self) -> NokResult<()> {
         self.logs.add_debug_log("Application shutting down".to_string());
+
+        // Nobody should see us sitting in a room we have closed
+        if let Some(room_id) = self.data.location_room.take() {
+            if let Err(e) = self.state_manager.set_location(&room_id, false, &mut self.logs).await {
+                self.logs.add_debug_log(format!("Could not clear location in {}: {}", room_id, e));
+            }
+        }
         
         // Shutdown state manager (handles both Matrix and legacy cleanup)
         self.state_manager.shutdown(&mut self.logs).await?;
@@ -530,67 +2690,229 @@ Keys:
         Ok(())
     }
 
-    /// Sync Matrix rooms to UI state
+    /// Sync Matrix rooms to UI state: add newly joined rooms, pick up name,
+    /// topic and membership changes, and drop rooms we left or were kicked from
     async fn sync_rooms_from_matrix(&mut self) -> NokResult<()> {
-        self.logs.add_debug_log("Starting room sync from Matrix client...".to_string());
-        
-        if let Some(matrix_client) = self.state_manager.matrix().get_client() {
-            self.logs.add_debug_log("Matrix client found, retrieving rooms...".to_string());
-            let matrix_rooms = matrix_client.rooms();
-            self.logs.add_debug_log(format!("Found {} Matrix rooms from client", matrix_rooms.len()));
-            
-            if matrix_rooms.is_empty() {
-                self.logs.add_debug_log("No Matrix rooms found - user may not have joined any rooms yet".to_string());
-                self.logs.add_debug_log("Try creating a room with: cargo run --bin create_test_room".to_string());
-                return Ok(());
-            }
-            
-            for (i, matrix_room) in matrix_rooms.iter().enumerate() {
-                let room_id = matrix_room.room_id().to_string();
-                self.logs.add_debug_log(format!("Processing room {} ({}): {}", i+1, room_id, room_id));
-                
-                // Get room name
-                let room_name = match matrix_room.display_name().await {
-                    Ok(name) => {
-                        self.logs.add_debug_log(format!("Room display name: {}", name));
-                        name.to_string()
-                    },
+        if self.state_manager.matrix().get_client().is_none() {
+            self.logs.add_debug_log("ERROR: Matrix client not found!".to_string());
+            return Err(crate::util::NokError::MatrixClientNotInitialized);
+        }
+        // Rooms of every logged in account, merged
+        let matrix_rooms = self.state_manager.matrix().joined_rooms();
+        if matrix_rooms.is_empty() {
+            self.logs.add_debug_log("No Matrix rooms found - user may not have joined any rooms yet".to_string());
+        }
+
+        let mut joined_ids = Vec::with_capacity(matrix_rooms.len());
+        for (account, matrix_room) in &matrix_rooms {
+            let room_id = matrix_room.room_id().to_string();
+            let room_name = match matrix_room.display_name().await {
+                Ok(name) => name.to_string(),
+                Err(e) => {
+                    self.logs.add_debug_log(format!("Failed to get display name of {}: {}, using room ID", room_id, e));
+                    room_id.clone()
+                }
+            };
+            let topic = matrix_room.topic();
+            let member_count = matrix_room.joined_members_count() as usize;
+            joined_ids.push(room_id.clone());
+
+            // Spaces become floors; their rooms come from the hierarchy API
+            let space_children = if matrix_room.is_space() {
+                match self.state_manager.space_children(&room_id).await {
+                    Ok(children) => Some(children),
                     Err(e) => {
-                        self.logs.add_debug_log(format!("Failed to get display name: {}, using room ID", e));
-                        room_id.clone()
+                        self.logs.add_debug_log(format!("Failed to load rooms of space {}: {}", room_id, e));
+                        None
                     }
-                };
-                
-                // Create nok Room from Matrix room
-                let mut room = super::room::Room::from_matrix_room(room_id.clone(), room_name.clone());
-                
-                // Set additional room properties
-                // Note: is_encrypted() might not be available in all matrix-sdk versions
-                // room.is_encrypted = matrix_room.is_encrypted().await.unwrap_or(false);
-                room.member_count = matrix_room.joined_members_count() as usize;
-                self.logs.add_debug_log(format!("Room member count: {}", room.member_count));
-                
-                // Add room if it doesn't already exist
-                if !self.data.rooms.iter().any(|r| r.matrix_id.as_ref() == Some(&room_id)) {
-                    self.logs.add_debug_log(format!("Adding new room to UI: '{}'", room.name));
-                    self.data.add_room(room);
-                } else {
-                    self.logs.add_debug_log(format!("Room already exists in UI: '{}'", room.name));
                 }
+            } else {
+                None
+            };
+
+            let kind = self.state_manager.room_kind(&room_id).await.unwrap_or_else(|e| {
+                self.logs.add_debug_log(format!("Failed to read the type of room {}: {}", room_id, e));
+                None
+            });
+            let encrypted = self.state_manager.is_room_encrypted(&room_id).await.unwrap_or_else(|e| {
+                self.logs.add_debug_log(format!("Failed to read the encryption state of {}: {}", room_id, e));
+                false
+            });
+            let occupants = match self.state_manager.room_occupants(&room_id).await {
+                Ok(occupants) => self.data.online_occupants(occupants),
+                Err(e) => {
+                    self.logs.add_debug_log(format!("Failed to read who is in {}: {}", room_id, e));
+                    Vec::new()
+                }
+            };
+
+            if let Some(room) = self.data.rooms.iter_mut().find(|r| r.matrix_id.as_ref() == Some(&room_id)) {
+                // DMs keep the partner name set by refresh_users
+                if !room.is_direct {
+                    room.name = room_name;
+                }
+                room.set_topic(topic);
+                room.set_member_count(member_count);
+                if let Some(children) = space_children {
+                    room.space_children = children;
+                }
+                room.kind = kind;
+                room.occupants = occupants;
+                room.set_encrypted(encrypted);
+                room.account = Some(account.clone());
+                continue;
             }
-            
-            self.logs.add_debug_log(format!("Room sync complete - Total UI rooms: {}", self.data.rooms.len()));
-            
-            // Set first room as current if none is selected
-            if self.data.get_current_room().is_none() && !self.data.rooms.is_empty() {
-                self.data.set_current_room_idx(0);
-                self.logs.add_debug_log(format!("Set '{}' as current room", self.data.rooms[0].name));
+
+            let mut room = Room::from_matrix_room(room_id.clone(), room_name);
+            room.set_topic(topic);
+            room.set_member_count(member_count);
+            room.is_space = matrix_room.is_space();
+            room.space_children = space_children.unwrap_or_default();
+            room.kind = kind;
+            room.occupants = occupants;
+            room.set_encrypted(encrypted);
+            room.account = Some(account.clone());
+            // Existing unread mentions are shown but not announced
+            if let Ok((unread, highlights)) = self.state_manager.room_unread_counts(&room_id) {
+                room.unread_count = unread;
+                room.highlight_count = highlights;
             }
-        } else {
-            self.logs.add_debug_log("ERROR: Matrix client not found!".to_string());
-            return Err(crate::util::NokError::MatrixClientNotInitialized);
+            // 1:1 rooms from m.direct go to the DM section
+            if let Some(partner) = self.state_manager.dm_partner(&room_id) {
+                room.is_direct = true;
+                room.dm_user_id = Some(partner);
+            }
+            self.logs.add_debug_log(format!("Adding new room to UI: '{}'", room.name));
+            self.data.add_room(room);
+        }
+
+        let current_id = self.current_matrix_room_id();
+        while let Some(idx) = self.data.rooms.iter()
+            .position(|r| r.matrix_id.as_ref().is_some_and(|id| !joined_ids.contains(id)))
+        {
+            if let Some(room) = self.data.remove_room(idx) {
+                self.logs.add_debug_log(format!("Removing room no longer joined: '{}'", room.name));
+            }
+        }
+        if self.data.active_space.as_ref().is_some_and(|id| !joined_ids.contains(id)) {
+            self.data.active_space = None;
+        }
+        self.clamp_room_selection();
+
+        // The room we were looking at is gone, show whatever is current now
+        if current_id.is_some() && self.current_matrix_room_id() != current_id {
+            self.follow_room_account();
+            self.data.set_messages(Vec::new());
+            self.close_thread();
+            self.data.timeline_refreshed_at = None;
         }
-        
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Options of the `/create` command
+#[derive(Debug)]
+struct CreateRoomArgs {
+    name: String,
+    alias: Option<String>,
+    private: bool,
+    encrypted: bool,
+}
+
+/// Parse `/create <name> [--alias <alias>] [--private] [--encrypted]`.
+/// The name may span several words; the alias accepts "#alias:server" too.
+fn parse_create_args(args: &[&str]) -> Result<CreateRoomArgs, String> {
+    const USAGE: &str = "Usage: /create <name> [--alias <alias>] [--private] [--encrypted]";
+    let mut name = Vec::new();
+    let mut alias = None;
+    let mut private = false;
+    let mut encrypted = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--alias" => {
+                let value = args.next().ok_or_else(|| USAGE.to_string())?;
+                let localpart = value.trim_start_matches('#').split(':').next().unwrap_or_default();
+                if localpart.is_empty() {
+                    return Err(USAGE.to_string());
+                }
+                alias = Some(localpart.to_string());
+            }
+            "--private" => private = true,
+            "--encrypted" => encrypted = true,
+            flag if flag.starts_with("--") => return Err(format!("Unknown option {}. {}", flag, USAGE)),
+            word => name.push(word),
+        }
+    }
+
+    if name.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    Ok(CreateRoomArgs { name: name.join(" "), alias, private, encrypted })
+}
+
+/// Parse `/kick <user> [reason]`; the reason may span several words
+fn parse_kick_args<'a>(args: &[&'a str]) -> Option<(&'a str, Option<String>)> {
+    let (user, reason) = args.split_first()?;
+    let reason = reason.join(" ");
+    Some((user, (!reason.is_empty()).then_some(reason)))
+}
+
+/// Map reaction shortcodes typed in the input line to emoji
+fn reaction_key(input: &str) -> String {
+    match input.trim() {
+        ":+1:" | "+1" => "👍".to_string(),
+        ":heart:" => "❤️".to_string(),
+        ":laugh:" => "😄".to_string(),
+        ":tada:" => "🎉".to_string(),
+        ":eyes:" => "👀".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Expand a leading `~/` in a path typed by the user
+fn expand_home(path: &str) -> std::path::PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => std::path::PathBuf::from(path),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_create_args() {
+        let args = parse_create_args(&["Team", "room", "--alias", "#team:example.org", "--private"]).unwrap();
+        assert_eq!(args.name, "Team room");
+        assert_eq!(args.alias.as_deref(), Some("team"));
+        assert!(args.private);
+        assert!(!args.encrypted);
+
+        let args = parse_create_args(&["--encrypted", "Ops", "--alias", "ops"]).unwrap();
+        assert_eq!(args.name, "Ops");
+        assert_eq!(args.alias.as_deref(), Some("ops"));
+        assert!(args.encrypted);
+    }
+
+    #[test]
+    fn test_parse_create_args_rejects_bad_input() {
+        assert!(parse_create_args(&[]).is_err());
+        assert!(parse_create_args(&["--private"]).is_err());
+        assert!(parse_create_args(&["Team", "--alias"]).is_err());
+        assert!(parse_create_args(&["Team", "--alias", "#:example.org"]).is_err());
+        assert!(parse_create_args(&["Team", "--public"]).unwrap_err().starts_with("Unknown option --public"));
+    }
+
+    #[test]
+    fn test_parse_kick_args() {
+        assert_eq!(parse_kick_args(&["@bob:example.org"]), Some(("@bob:example.org", None)));
+        assert_eq!(
+            parse_kick_args(&["bob", "too", "noisy"]),
+            Some(("bob", Some("too noisy".to_string())))
+        );
+        assert_eq!(parse_kick_args(&[]), None);
+    }
+}
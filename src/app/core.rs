@@ -1,11 +1,15 @@
 use super::state::AppState;
-use super::user::{User, UserStatus};
-use super::room::Room;
+use super::user::{extract_username_from_matrix_id, User, UserStatus};
+use super::room::{PublicRoomsPage, Room, RoomInvite};
+use super::office::{Avatar, OfficeEditor, OfficeMap, Position, PublishedPosition};
 use super::message::Message;
 use super::config::Config;
 use crate::ui::TabView;
+use crate::matrix::VerificationPhase;
+use crossterm::event::KeyCode;
 use crate::util::{ValidationError, NokError, NokResult};
 use chrono;
+use std::sync::{Arc, Mutex};
 
 /// Core application state - minimal essential data
 #[derive(Debug)]
@@ -26,9 +30,376 @@ pub struct UiState {
     pub selected_user: Option<usize>,
     pub selected_room_idx: usize,
     pub selected_message_idx: Option<usize>,
-    pub my_aa_position: (u16, u16), // ASCII Art pane position
+    /// Our avatar on the current room's office map
+    pub my_aa_position: Position,
     pub username_edit_buffer: String,
     pub status_selection_index: usize,
+    pub compose: ComposeMode,
+    /// Root event ID of the thread shown in the side view
+    pub open_thread: Option<String>,
+    pub room_directory: RoomDirectoryState,
+    pub user_directory: UserDirectoryState,
+    /// Floor plan being edited in the office pane
+    pub office_editor: Option<OfficeEditor>,
+    /// Yes/no question waiting for an answer
+    pub confirm: Option<Confirmation>,
+    /// User shown in the profile overlay
+    pub profile: Option<ProfileView>,
+    /// Phase of the running verification when last looked at, to
+    /// announce changes
+    pub verification_phase: Option<VerificationPhase>,
+    /// Security section of the settings screen
+    pub security: SecuritySettings,
+    pub devices: DeviceSettings,
+}
+
+/// Cross-signing and key backup in the settings screen
+#[derive(Debug, Default)]
+pub struct SecuritySettings {
+    /// Read when the settings screen opens
+    pub status: Option<crate::matrix::SecurityStatus>,
+    /// What the input line is asking for, if anything
+    pub prompt: Option<SecurityPrompt>,
+    pub input: String,
+    /// Account password given in the first step of the setup
+    pub password: Option<String>,
+    /// New recovery key, shown once right after the setup
+    pub recovery_key: Option<String>,
+}
+
+/// Secret typed into the settings screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SecurityPrompt {
+    /// Account password, to confirm new cross-signing keys
+    Password,
+    /// Optional passphrase that unlocks the recovery key too
+    Passphrase,
+    /// Recovery key (or its passphrase) to restore from the backup
+    RecoveryKey,
+}
+
+impl SecurityPrompt {
+    pub fn label(self) -> &'static str {
+        match self {
+            SecurityPrompt::Password => "Account password",
+            SecurityPrompt::Passphrase => "Recovery passphrase (Enter for none)",
+            SecurityPrompt::RecoveryKey => "Recovery key or passphrase",
+        }
+    }
+}
+
+/// Our sessions in the settings screen
+#[derive(Debug, Default)]
+pub struct DeviceSettings {
+    /// Read when the settings screen opens
+    pub list: Vec<crate::matrix::DeviceInfo>,
+    pub selected: usize,
+    /// Devices marked to be logged out together
+    pub marked: Vec<String>,
+    pub prompt: Option<DevicePrompt>,
+    pub input: String,
+}
+
+impl DeviceSettings {
+    /// Show a freshly read list: marks are dropped and the selection stays
+    /// on the list
+    pub fn set_list(&mut self, list: Vec<crate::matrix::DeviceInfo>) {
+        self.marked.clear();
+        self.list = list;
+        self.selected = self.selected.min(self.list.len().saturating_sub(1));
+    }
+
+    pub fn selected_device(&self) -> Option<&crate::matrix::DeviceInfo> {
+        self.list.get(self.selected)
+    }
+
+    /// Mark or unmark the selected device; this one cannot be marked
+    pub fn toggle_mark(&mut self) {
+        let Some(device) = self.list.get(self.selected).filter(|device| !device.is_current) else {
+            return;
+        };
+        let device_id = device.device_id.clone();
+        if let Some(index) = self.marked.iter().position(|id| *id == device_id) {
+            self.marked.remove(index);
+        } else {
+            self.marked.push(device_id);
+        }
+    }
+
+    /// What `x` logs out: the marked devices, or else the selected one
+    pub fn logout_targets(&self) -> Vec<String> {
+        if !self.marked.is_empty() {
+            return self.marked.clone();
+        }
+        self.selected_device()
+            .filter(|device| !device.is_current)
+            .map(|device| vec![device.device_id.clone()])
+            .unwrap_or_default()
+    }
+}
+
+/// Text typed into the device list
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DevicePrompt {
+    /// New display name for the selected device
+    Rename,
+    /// Account password, to log the devices out
+    Password,
+}
+
+impl DevicePrompt {
+    pub fn label(self) -> &'static str {
+        match self {
+            DevicePrompt::Rename => "New device name",
+            DevicePrompt::Password => "Account password",
+        }
+    }
+}
+
+/// Profile overlay of one user
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileView {
+    pub user_id: String,
+    pub name: String,
+    /// Whether we verified them; `None` while unknown or when they have
+    /// no cross-signing identity
+    pub verified: Option<bool>,
+    /// Outcome of the last verification with them, for display
+    pub last_verification: Option<String>,
+}
+
+/// A yes/no question shown over the main screen before an action that
+/// cannot be undone
+#[derive(Debug, Clone, PartialEq)]
+pub struct Confirmation {
+    pub prompt: String,
+    pub action: ConfirmAction,
+}
+
+/// What happens when a confirmation is answered with yes
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfirmAction {
+    /// Turn on encryption in the room with this Matrix ID
+    EnableEncryption(String),
+    /// Log out of Matrix, deleting the local store too with `wipe_store`
+    Logout { wipe_store: bool },
+    /// Open a DM with someone we share no room with, and knock there
+    KnockInNewDm { user_id: String, name: String },
+    /// Redact one of our own messages, by event ID
+    DeleteMessage(String),
+    /// Replace the unencrypted store left by an older version with an
+    /// encrypted one
+    EncryptStore,
+}
+
+/// What a key did to the open confirmation
+#[derive(Debug, PartialEq)]
+pub enum ConfirmAnswer {
+    /// Answered yes; the action is to be run
+    Yes(ConfirmAction),
+    /// Answered no; nothing happens
+    No,
+    /// Not an answer; the question stays open
+    Open,
+}
+
+/// What a key does in the verification modal
+#[derive(Debug, PartialEq)]
+pub enum VerificationAnswer {
+    /// Accept the request the other side sent
+    Accept,
+    /// Turn down the request the other side sent
+    Decline,
+    /// The emojis match
+    Match,
+    /// The emojis differ
+    Mismatch,
+    /// Cancel what is still running and close the modal
+    Close,
+}
+
+/// The answer `key` gives in `phase`, if any: Esc always closes, a finished
+/// verification closes on any key, and y/n answer the request or the emojis
+pub fn answer_verification(phase: &VerificationPhase, key: KeyCode) -> Option<VerificationAnswer> {
+    match (phase, key) {
+        (VerificationPhase::Done | VerificationPhase::Cancelled(_), _) | (_, KeyCode::Esc) => {
+            Some(VerificationAnswer::Close)
+        }
+        (VerificationPhase::Incoming, KeyCode::Char('y')) => Some(VerificationAnswer::Accept),
+        (VerificationPhase::Incoming, KeyCode::Char('n')) => Some(VerificationAnswer::Decline),
+        (VerificationPhase::Emojis(_), KeyCode::Char('y')) => Some(VerificationAnswer::Match),
+        (VerificationPhase::Emojis(_), KeyCode::Char('n')) => Some(VerificationAnswer::Mismatch),
+        _ => None,
+    }
+}
+
+/// Public room directory overlay opened by `/join`
+#[derive(Debug, Default)]
+pub struct RoomDirectoryState {
+    /// Search term typed into the overlay
+    pub query: String,
+    pub page: PublicRoomsPage,
+    /// Index of the current page, for display
+    pub page_number: usize,
+    pub selected: usize,
+    /// When the query last changed; the search runs once typing pauses
+    pub query_changed_at: Option<std::time::Instant>,
+}
+
+/// What a key in the room directory overlay leaves for the app to do
+#[derive(Debug, PartialEq)]
+pub enum RoomDirectoryStep {
+    /// Nothing; the overlay handled it
+    Stay,
+    Close,
+    /// Fetch the page behind the `since` token, shown as `page_number`
+    Load { since: String, page_number: usize },
+    /// Close the overlay and join this alias or room ID
+    Join(String),
+}
+
+impl RoomDirectoryState {
+    /// Start a fresh search for `query`
+    pub fn reset(&mut self, query: &str) {
+        *self = Self {
+            query: query.to_string(),
+            ..Self::default()
+        };
+    }
+
+    /// Show a freshly loaded page, selecting its first room
+    pub fn show_page(&mut self, page: PublicRoomsPage, page_number: usize) {
+        self.page = page;
+        self.page_number = page_number;
+        self.selected = 0;
+    }
+
+    /// Typing edits the search, arrows move through the page, PageUp and
+    /// PageDown (or Left and Right) turn pages, Enter joins, Esc closes
+    pub fn press(&mut self, key: KeyCode) -> RoomDirectoryStep {
+        match key {
+            KeyCode::Esc => return RoomDirectoryStep::Close,
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down if self.selected + 1 < self.page.rooms.len() => self.selected += 1,
+            KeyCode::PageDown | KeyCode::Right => {
+                if let Some(since) = self.page.next_batch.clone() {
+                    return RoomDirectoryStep::Load { since, page_number: self.page_number + 1 };
+                }
+            }
+            KeyCode::PageUp | KeyCode::Left => {
+                if let Some(since) = self.page.prev_batch.clone() {
+                    return RoomDirectoryStep::Load { since, page_number: self.page_number.saturating_sub(1) };
+                }
+            }
+            KeyCode::Enter => {
+                if let Some(room) = self.page.rooms.get(self.selected) {
+                    return RoomDirectoryStep::Join(room.join_target().to_string());
+                }
+            }
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.query_changed_at = Some(std::time::Instant::now());
+            }
+            KeyCode::Char(c) => {
+                self.query.push(c);
+                self.query_changed_at = Some(std::time::Instant::now());
+            }
+            _ => {}
+        }
+        RoomDirectoryStep::Stay
+    }
+}
+
+/// User directory overlay opened by `/who`
+#[derive(Debug, Default)]
+pub struct UserDirectoryState {
+    /// Search term typed into the overlay
+    pub query: String,
+    pub results: Vec<User>,
+    /// The server had more matches than it returned
+    pub limited: bool,
+    pub selected: usize,
+    /// Keys act on the selected person instead of editing the search
+    pub results_focused: bool,
+    /// When the query last changed; the search runs once typing pauses
+    pub query_changed_at: Option<std::time::Instant>,
+}
+
+impl UserDirectoryState {
+    /// Start a fresh search for `query`
+    pub fn reset(&mut self, query: &str) {
+        *self = Self {
+            query: query.to_string(),
+            ..Self::default()
+        };
+    }
+
+    pub fn selected_user(&self) -> Option<&User> {
+        self.results.get(self.selected)
+    }
+
+    /// Tab and Down move from the search into the results; there `k`
+    /// knocks on the selected person, `i` invites them to the current room
+    /// and `m` or Enter opens a DM. Enter in the search runs it at once
+    pub fn press(&mut self, key: KeyCode) -> UserDirectoryStep {
+        match key {
+            KeyCode::Esc => return UserDirectoryStep::Close,
+            KeyCode::Tab => self.results_focused = !self.results_focused && !self.results.is_empty(),
+            KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+            KeyCode::Down => {
+                self.results_focused = !self.results.is_empty();
+                if self.selected + 1 < self.results.len() {
+                    self.selected += 1;
+                }
+            }
+            KeyCode::Enter if !self.results_focused => return UserDirectoryStep::Search,
+            KeyCode::Backspace if !self.results_focused => {
+                self.query.pop();
+                self.query_changed_at = Some(std::time::Instant::now());
+            }
+            KeyCode::Char(c) if !self.results_focused => {
+                self.query.push(c);
+                self.query_changed_at = Some(std::time::Instant::now());
+            }
+            KeyCode::Char('k') | KeyCode::Char('m') | KeyCode::Char('i') | KeyCode::Enter => {
+                let Some(User { matrix_id: Some(user_id), name, .. }) = self.selected_user() else {
+                    return UserDirectoryStep::Stay;
+                };
+                let user_id = user_id.clone();
+                return match key {
+                    KeyCode::Char('k') => UserDirectoryStep::Knock { user_id, name: name.clone() },
+                    KeyCode::Char('i') => UserDirectoryStep::Invite(user_id),
+                    _ => UserDirectoryStep::Dm(user_id),
+                };
+            }
+            _ => {}
+        }
+        UserDirectoryStep::Stay
+    }
+}
+
+/// What a key in the user directory overlay leaves for the app to do
+#[derive(Debug, PartialEq)]
+pub enum UserDirectoryStep {
+    /// Nothing; the overlay handled it
+    Stay,
+    Close,
+    /// Run the search now instead of waiting for typing to pause
+    Search,
+    Knock { user_id: String, name: String },
+    /// Close the overlay and open a DM with this MXID
+    Dm(String),
+    /// Invite this MXID to the current room
+    Invite(String),
+}
+
+/// What the input line is currently composing
+#[derive(Clone, PartialEq, Debug)]
+pub enum ComposeMode {
+    Message,
+    Reply(String), // event ID being replied to
+    Edit(String),  // event ID being replaced
+    React(String), // event ID being annotated
 }
 
 /// Data collections and content management
@@ -39,6 +410,47 @@ pub struct DataState {
     pub messages: Vec<Message>,
     pub current_user: User,
     pub current_room: usize,
+    pub timeline_refreshed_at: Option<std::time::Instant>,
+    /// Latest event we sent a read receipt for
+    pub last_read_event: Option<String>,
+    pub users_refreshed_at: Option<std::time::Instant>,
+    /// Pending invites, listed in the Rooms pane after the joined rooms
+    pub invites: Vec<RoomInvite>,
+    /// Space picked in the floor switcher; narrows the Rooms and Users panes
+    pub active_space: Option<String>,
+    pub office_map: OfficeMap,
+    /// Room the office positions below belong to
+    pub office_room: Option<String>,
+    /// Positions others published in the office room, by MXID
+    pub office_positions: std::collections::HashMap<String, PublishedPosition>,
+    /// Our last position sent to the office room
+    pub published_position: Option<PublishedPosition>,
+    /// Where we stopped walking and when; published once we stand still
+    pub pending_position: Option<(Position, std::time::Instant)>,
+    /// The office room's power levels keep us from publishing our position
+    pub position_refused: bool,
+    /// MXIDs of the people standing close enough to huddle with us
+    pub huddle_members: Vec<String>,
+    /// Root of the thread our huddle talks in, once someone has spoken
+    pub huddle_root: Option<String>,
+    /// Knocks on us in the office room being animated: knocker's MXID and
+    /// when the knock arrived
+    pub office_knocks: Vec<(String, std::time::Instant)>,
+    /// Desks claimed in the office room, by MXID (ours included)
+    pub office_desks: std::collections::HashMap<String, Position>,
+    /// Room we last told everyone we are sitting in
+    pub location_room: Option<String>,
+    /// Users pane lists only the people sitting in the current room
+    pub users_here_only: bool,
+}
+
+/// A row of the Rooms pane
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RoomPaneEntry {
+    /// Index into `rooms`, indented by `depth` under its floor
+    Room { idx: usize, depth: usize },
+    /// Index into `invites`
+    Invite(usize),
 }
 
 /// Logging and debugging information
@@ -47,6 +459,9 @@ pub struct LogState {
     pub debug_logs: Vec<String>,
     pub settings_logs: Vec<String>,
     pub max_debug_logs: usize,
+    /// Debug lines from background threads, such as sound playback; moved
+    /// into `debug_logs` on the next tick
+    pub background: Arc<Mutex<Vec<String>>>,
 }
 
 /// Connection and network state
@@ -113,14 +528,41 @@ impl UiState {
             selected_user: None,
             selected_room_idx: 0,
             selected_message_idx: None,
-            my_aa_position: (0, 0),
+            my_aa_position: Position::new(1, 1),
             username_edit_buffer: String::new(),
             status_selection_index: 0,
+            compose: ComposeMode::Message,
+            open_thread: None,
+            room_directory: RoomDirectoryState::default(),
+            user_directory: UserDirectoryState::default(),
+            office_editor: None,
+            confirm: None,
+            profile: None,
+            verification_phase: None,
+            security: SecuritySettings::default(),
+            devices: DeviceSettings::default(),
+        }
+    }
+
+    /// Answer the open confirmation with a key: y or n (or Esc) closes it,
+    /// anything else leaves it open
+    pub fn answer_confirmation(&mut self, key: KeyCode) -> ConfirmAnswer {
+        match key {
+            KeyCode::Char('y') | KeyCode::Char('Y') => match self.confirm.take() {
+                Some(confirmation) => ConfirmAnswer::Yes(confirmation.action),
+                None => ConfirmAnswer::No,
+            },
+            KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => {
+                self.confirm = None;
+                ConfirmAnswer::No
+            }
+            _ => ConfirmAnswer::Open,
         }
     }
 
     pub fn clear_input(&mut self) {
         self.input.clear();
+        self.compose = ComposeMode::Message;
     }
 
     pub fn reset_selections(&mut self) {
@@ -138,6 +580,23 @@ impl DataState {
             messages: Vec::new(),
             current_user,
             current_room: 0,
+            timeline_refreshed_at: None,
+            last_read_event: None,
+            users_refreshed_at: None,
+            invites: Vec::new(),
+            active_space: None,
+            office_map: OfficeMap::default(),
+            office_room: None,
+            office_positions: std::collections::HashMap::new(),
+            published_position: None,
+            pending_position: None,
+            position_refused: false,
+            office_desks: std::collections::HashMap::new(),
+            huddle_members: Vec::new(),
+            huddle_root: None,
+            office_knocks: Vec::new(),
+            location_room: None,
+            users_here_only: false,
         }
     }
 
@@ -145,8 +604,156 @@ impl DataState {
         self.users.push(user);
     }
 
-    pub fn add_room(&mut self, room: Room) {
-        self.rooms.push(room);
+    /// Add a room, keeping DMs after the regular rooms. Returns its index.
+    pub fn add_room(&mut self, room: Room) -> usize {
+        let idx = if room.is_direct {
+            self.rooms.len()
+        } else {
+            self.rooms.iter().position(|r| r.is_direct).unwrap_or(self.rooms.len())
+        };
+        if idx <= self.current_room && idx < self.rooms.len() {
+            self.current_room += 1;
+        }
+        self.rooms.insert(idx, room);
+        idx
+    }
+
+    /// MXID for a user typed into a command: a full MXID as is, else the
+    /// name or username of someone in the Users pane, else a username on
+    /// `server_name`
+    pub fn resolve_user_id(&self, query: &str, server_name: &str) -> String {
+        if query.starts_with('@') && query.contains(':') {
+            return query.to_string();
+        }
+
+        let name = query.trim_start_matches('@');
+        self.users.iter()
+            .find(|u| u.name == name || u.matrix_id.as_deref().map(extract_username_from_matrix_id).as_deref() == Some(name))
+            .and_then(|u| u.matrix_id.clone())
+            .unwrap_or_else(|| format!("@{}:{}", name, server_name))
+    }
+
+    /// Drop a room from the list, keeping `current_room` on the same room
+    /// when possible
+    pub fn remove_room(&mut self, idx: usize) -> Option<Room> {
+        if idx >= self.rooms.len() {
+            return None;
+        }
+        let room = self.rooms.remove(idx);
+        if idx < self.current_room || self.current_room >= self.rooms.len() {
+            self.current_room = self.current_room.saturating_sub(1);
+        }
+        Some(room)
+    }
+
+    /// Rows of the Rooms pane: floors (spaces) with their rooms nested
+    /// unless collapsed, then rooms outside any space, DMs and invites.
+    /// With a floor picked in the switcher only that floor and DMs with
+    /// its members are listed.
+    pub fn room_pane_entries(&self) -> Vec<RoomPaneEntry> {
+        let mut entries = Vec::new();
+        let push_floor = |entries: &mut Vec<RoomPaneEntry>, space_idx: usize, space: &Room| {
+            entries.push(RoomPaneEntry::Room { idx: space_idx, depth: 0 });
+            if space.collapsed {
+                return;
+            }
+            for (idx, room) in self.rooms.iter().enumerate() {
+                if room.matrix_id.as_deref().is_some_and(|id| space.contains_room(id)) {
+                    entries.push(RoomPaneEntry::Room { idx, depth: 1 });
+                }
+            }
+        };
+
+        match self.active_space.as_deref() {
+            Some(space_id) => {
+                if let Some(space_idx) = self.rooms.iter().position(|r| r.matrix_id.as_deref() == Some(space_id)) {
+                    push_floor(&mut entries, space_idx, &self.rooms[space_idx]);
+                }
+                for (idx, room) in self.rooms.iter().enumerate() {
+                    let partner_on_floor = room.dm_user_id.as_ref()
+                        .and_then(|id| self.users.iter().find(|u| u.matrix_id.as_ref() == Some(id)))
+                        .is_some_and(|u| u.rooms.iter().any(|r| r == space_id));
+                    if room.is_direct && partner_on_floor {
+                        entries.push(RoomPaneEntry::Room { idx, depth: 0 });
+                    }
+                }
+            }
+            None => {
+                for (idx, space) in self.rooms.iter().enumerate().filter(|(_, r)| r.is_space) {
+                    push_floor(&mut entries, idx, space);
+                }
+                for (idx, room) in self.rooms.iter().enumerate() {
+                    let in_space = room.matrix_id.as_deref()
+                        .is_some_and(|id| self.rooms.iter().any(|space| space.contains_room(id)));
+                    if !room.is_space && !room.is_direct && !in_space {
+                        entries.push(RoomPaneEntry::Room { idx, depth: 0 });
+                    }
+                }
+                for (idx, room) in self.rooms.iter().enumerate() {
+                    if room.is_direct {
+                        entries.push(RoomPaneEntry::Room { idx, depth: 0 });
+                    }
+                }
+            }
+        }
+
+        entries.extend((0..self.invites.len()).map(RoomPaneEntry::Invite));
+        entries
+    }
+
+    /// Replace the pending invites with a fresh list from all our accounts,
+    /// returning those that were not listed before
+    pub fn set_invites(&mut self, invites: Vec<RoomInvite>) -> Vec<RoomInvite> {
+        let new = invites.iter()
+            .filter(|invite| !self.invites.iter().any(|known| {
+                known.room_id == invite.room_id && known.account == invite.account
+            }))
+            .cloned()
+            .collect();
+        self.invites = invites;
+        new
+    }
+
+    /// Position of a room in the Rooms pane, if it is listed
+    pub fn room_pane_position(&self, room_idx: usize) -> Option<usize> {
+        self.room_pane_entries().iter()
+            .position(|entry| matches!(entry, RoomPaneEntry::Room { idx, .. } if *idx == room_idx))
+    }
+
+    /// The invite at a Rooms pane row, if that row is an invite
+    pub fn get_selected_invite(&self, selected_idx: usize) -> Option<&RoomInvite> {
+        match self.room_pane_entries().get(selected_idx) {
+            Some(RoomPaneEntry::Invite(idx)) => self.invites.get(*idx),
+            _ => None,
+        }
+    }
+
+    /// Index into `rooms` of the room at a Rooms pane row
+    pub fn get_selected_room_idx(&self, selected_idx: usize) -> Option<usize> {
+        match self.room_pane_entries().get(selected_idx) {
+            Some(RoomPaneEntry::Room { idx, .. }) => Some(*idx),
+            _ => None,
+        }
+    }
+
+    /// Users shown in the Users pane: everyone, or the members of the
+    /// floor picked in the switcher, narrowed to the people sitting in
+    /// the current room when that filter is on
+    pub fn visible_users(&self) -> Vec<&User> {
+        let occupants = match (self.users_here_only, self.get_current_room()) {
+            (true, Some(room)) => Some(&room.occupants),
+            (true, None) => return Vec::new(),
+            (false, _) => None,
+        };
+        self.users.iter()
+            .filter(|u| match self.active_space.as_deref() {
+                Some(space_id) => u.rooms.iter().any(|r| r == space_id),
+                None => true,
+            })
+            .filter(|u| occupants.is_none_or(|occupants| {
+                u.matrix_id.as_ref().is_some_and(|id| occupants.contains(id))
+            }))
+            .collect()
     }
 
     pub fn add_message(&mut self, message: Message) {
@@ -161,8 +768,99 @@ impl DataState {
         self.rooms.get_mut(self.current_room)
     }
 
+    /// Messages shown in the main timeline (thread replies hidden), or in
+    /// the thread view when a thread root is given
+    pub fn visible_messages(&self, thread_root: Option<&str>) -> Vec<&Message> {
+        match thread_root {
+            Some(root) => self.messages.iter().filter(|m| m.in_thread(root)).collect(),
+            None => self.messages.iter().filter(|m| m.thread_root.is_none()).collect(),
+        }
+    }
+
+    pub fn get_selected_message(&self, selected_idx: Option<usize>, thread_root: Option<&str>) -> Option<&Message> {
+        selected_idx.and_then(|idx| self.visible_messages(thread_root).get(idx).copied())
+    }
+
+    /// Replace the message list with a freshly built timeline
+    pub fn set_messages(&mut self, messages: Vec<Message>) {
+        self.messages = messages;
+        self.timeline_refreshed_at = Some(std::time::Instant::now());
+    }
+
+    /// Everyone to draw on the office map: members of the office room at
+    /// their published positions (or at their desk, or lined up at the
+    /// bottom until they move), and ourselves
+    pub fn office_avatars(&self, own_position: Position) -> Vec<Avatar> {
+        let Some(room_id) = self.office_room.as_ref() else {
+            return Vec::new();
+        };
+
+        let mut avatars = Vec::new();
+        let mut unplaced = 0;
+        for user in self.users.iter().filter(|u| u.rooms.contains(room_id)) {
+            let Some(user_id) = user.matrix_id.as_ref() else {
+                continue;
+            };
+            let published = self.office_positions.get(user_id).map(|p| p.position);
+            let position = match published.or_else(|| self.office_desks.get(user_id).copied()) {
+                Some(position) => self.office_map.clamp(position),
+                None => {
+                    unplaced += 1;
+                    self.office_map.spawn_point(unplaced)
+                }
+            };
+            avatars.push(Avatar {
+                user_id: user_id.clone(),
+                name: user.name.clone(),
+                position,
+                status: user.status.clone(),
+                is_self: false,
+                present: published.is_some(),
+            });
+        }
+
+        avatars.push(Avatar {
+            user_id: self.current_user.matrix_id.clone().unwrap_or_default(),
+            name: self.current_user.name.clone(),
+            position: own_position,
+            status: self.current_user.status.clone(),
+            is_self: true,
+            present: true,
+        });
+        avatars
+    }
+
+    /// Make `current` the room we sit in, returning the location updates to
+    /// send: leaving the previous room, then arriving in the new one
+    pub fn move_location(&mut self, current: Option<String>) -> Vec<(String, bool)> {
+        if current == self.location_room {
+            return Vec::new();
+        }
+        let previous = std::mem::replace(&mut self.location_room, current.clone());
+        previous.map(|room_id| (room_id, false)).into_iter()
+            .chain(current.map(|room_id| (room_id, true)))
+            .collect()
+    }
+
+    /// Drop people known to be offline from a room's occupants: a client
+    /// that crashed never says it left
+    pub fn online_occupants(&self, occupants: Vec<String>) -> Vec<String> {
+        occupants.into_iter()
+            .filter(|user_id| !self.users.iter().any(|u| {
+                u.matrix_id.as_ref() == Some(user_id) && u.status == UserStatus::Offline
+            }))
+            .collect()
+    }
+
+    /// Who claimed the desk at `pos`, if anyone
+    pub fn desk_owner(&self, pos: Position) -> Option<&str> {
+        self.office_desks.iter()
+            .find(|(_, desk)| **desk == pos)
+            .map(|(user_id, _)| user_id.as_str())
+    }
+
     pub fn get_selected_user(&self, selected_idx: Option<usize>) -> Option<&User> {
-        selected_idx.and_then(|idx| self.users.get(idx))
+        selected_idx.and_then(|idx| self.visible_users().get(idx).copied())
     }
 
     pub fn set_current_room_idx(&mut self, idx: usize) {
@@ -178,6 +876,7 @@ impl LogState {
             debug_logs: Vec::new(),
             settings_logs: Vec::new(),
             max_debug_logs: 100,
+            background: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
@@ -193,6 +892,14 @@ impl LogState {
         }
     }
 
+    /// Log what background threads wrote since the last call
+    pub fn take_background(&mut self) {
+        let messages = std::mem::take(&mut *self.background.lock().unwrap());
+        for message in messages {
+            self.add_debug_log(message);
+        }
+    }
+
     pub fn add_settings_log(&mut self, message: String) {
         let timestamp = chrono::Local::now().format("%H:%M:%S").to_string();
         let log_entry = format!("[{}] {}", timestamp, message);
@@ -262,4 +969,458 @@ impl Default for NetworkState {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::room::PublicRoom;
+
+    fn data_with(rooms: Vec<Room>) -> DataState {
+        let mut data = DataState::new(User::new("me".to_string()));
+        for room in rooms {
+            data.add_room(room);
+        }
+        data
+    }
+
+    fn room(id: &str) -> Room {
+        Room::from_matrix_room(id.to_string(), id.trim_start_matches('!').to_string())
+    }
+
+    fn dm(id: &str, partner: &str) -> Room {
+        let mut room = room(id);
+        room.is_direct = true;
+        room.dm_user_id = Some(partner.to_string());
+        room
+    }
+
+    /// Rooms pane rows by room name, indented by depth
+    fn rows(data: &DataState) -> Vec<String> {
+        data.room_pane_entries().iter()
+            .map(|entry| match entry {
+                RoomPaneEntry::Room { idx, depth } => format!("{}{}", "  ".repeat(*depth), data.rooms[*idx].name),
+                RoomPaneEntry::Invite(idx) => format!("invite {}", data.invites[*idx].room_name),
+            })
+            .collect()
+    }
+
+    fn space(id: &str, children: &[&str]) -> Room {
+        let mut room = room(id);
+        room.is_space = true;
+        room.space_children = children.iter().map(|child| child.to_string()).collect();
+        room
+    }
+
+    fn user(user_id: &str, rooms: &[&str]) -> User {
+        let mut user = User::new(user_id.trim_start_matches('@').split(':').next().unwrap_or_default().to_string());
+        user.matrix_id = Some(user_id.to_string());
+        user.rooms = rooms.iter().map(|room| room.to_string()).collect();
+        user
+    }
+
+    fn invite(room_id: &str, account: &str) -> RoomInvite {
+        RoomInvite {
+            room_id: room_id.to_string(),
+            room_name: room_id.trim_start_matches('!').to_string(),
+            topic: None,
+            inviter_id: Some("@alice:nok.local".to_string()),
+            inviter_name: None,
+            account: Some(account.to_string()),
+        }
+    }
+
+    #[test]
+    fn test_dms_are_listed_after_rooms() {
+        let mut data = data_with(vec![room("!general"), dm("!alice", "@alice:nok.local")]);
+        data.set_current_room_idx(1);
+
+        // A room added later still goes before the DMs, and the current
+        // room stays the same room
+        let idx = data.add_room(room("!random"));
+        assert_eq!(idx, 1);
+        assert_eq!(data.get_current_room().map(|r| r.name.as_str()), Some("alice"));
+        data.add_room(dm("!bob", "@bob:nok.local"));
+
+        assert_eq!(rows(&data), vec!["general", "random", "alice", "bob"]);
+        assert_eq!(data.room_pane_position(2), Some(2));
+
+        data.remove_room(0);
+        assert_eq!(data.get_current_room().map(|r| r.name.as_str()), Some("alice"));
+    }
+
+    #[test]
+    fn test_invites_merge_and_follow_the_rooms() {
+        let mut data = data_with(vec![room("!general"), dm("!alice", "@alice:nok.local")]);
+
+        let new = data.set_invites(vec![invite("!party", "@me:nok.local")]);
+        assert_eq!(new.len(), 1);
+
+        // Known invites are not announced again; the same room for our
+        // other account is a new invite
+        let new = data.set_invites(vec![
+            invite("!party", "@me:nok.local"),
+            invite("!party", "@me:work.example"),
+            invite("!standup", "@me:work.example"),
+        ]);
+        let new: Vec<(&str, Option<&str>)> = new.iter().map(|i| (i.room_id.as_str(), i.account.as_deref())).collect();
+        assert_eq!(new, vec![("!party", Some("@me:work.example")), ("!standup", Some("@me:work.example"))]);
+
+        assert_eq!(rows(&data), vec!["general", "alice", "invite party", "invite party", "invite standup"]);
+        assert_eq!(data.get_selected_invite(4).map(|i| i.room_id.as_str()), Some("!standup"));
+        assert_eq!(data.get_selected_room_idx(4), None);
+
+        // Answered invites drop out of the list
+        assert!(data.set_invites(vec![invite("!standup", "@me:work.example")]).is_empty());
+        assert_eq!(rows(&data), vec!["general", "alice", "invite standup"]);
+    }
+
+    #[test]
+    fn test_floors_nest_their_rooms_and_collapse() {
+        let mut data = data_with(vec![
+            space("!floor1", &["!dev", "!ops"]),
+            room("!dev"),
+            room("!ops"),
+            room("!lobby"),
+            space("!floor2", &["!sales"]),
+            room("!sales"),
+            dm("!alice", "@alice:nok.local"),
+            dm("!bob", "@bob:nok.local"),
+        ]);
+        data.users = vec![
+            user("@alice:nok.local", &["!floor1", "!dev"]),
+            user("@bob:nok.local", &["!floor2"]),
+        ];
+
+        assert_eq!(
+            rows(&data),
+            vec!["floor1", "  dev", "  ops", "floor2", "  sales", "lobby", "alice", "bob"]
+        );
+
+        let floor1 = data.rooms.iter().position(|r| r.name == "floor1").unwrap();
+        data.rooms[floor1].collapsed = true;
+        assert_eq!(rows(&data), vec!["floor1", "floor2", "  sales", "lobby", "alice", "bob"]);
+        data.rooms[floor1].collapsed = false;
+
+        // Picking a floor lists only it and DMs with people on it
+        data.active_space = Some("!floor1".to_string());
+        assert_eq!(rows(&data), vec!["floor1", "  dev", "  ops", "alice"]);
+        let names: Vec<&str> = data.visible_users().iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(names, vec!["alice"]);
+    }
+
+    #[test]
+    fn test_location_follows_the_current_room() {
+        let mut data = data_with(vec![room("!dev"), room("!ops")]);
+
+        assert_eq!(data.move_location(Some("!dev".to_string())), vec![("!dev".to_string(), true)]);
+        assert!(data.move_location(Some("!dev".to_string())).is_empty());
+        assert_eq!(
+            data.move_location(Some("!ops".to_string())),
+            vec![("!dev".to_string(), false), ("!ops".to_string(), true)]
+        );
+        assert_eq!(data.move_location(None), vec![("!ops".to_string(), false)]);
+        assert_eq!(data.location_room, None);
+    }
+
+    #[test]
+    fn test_offline_people_are_not_occupants() {
+        let mut data = data_with(vec![room("!dev")]);
+        let mut carol = user("@carol:nok.local", &["!dev"]);
+        carol.status = UserStatus::Offline;
+        data.users = vec![user("@alice:nok.local", &["!dev"]), carol];
+
+        let occupants = data.online_occupants(vec![
+            "@alice:nok.local".to_string(),
+            "@carol:nok.local".to_string(),
+            "@dave:nok.local".to_string(),
+        ]);
+        // Someone we know nothing about is taken at their word
+        assert_eq!(occupants, vec!["@alice:nok.local", "@dave:nok.local"]);
+
+        data.rooms[0].occupants = occupants;
+        data.users_here_only = true;
+        let names: Vec<&str> = data.visible_users().iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(names, vec!["alice"]);
+    }
+
+    #[test]
+    fn test_confirmation_waits_for_an_answer() {
+        let mut ui = UiState::new();
+        let ask = |ui: &mut UiState| ui.confirm = Some(Confirmation {
+            prompt: "Turn on encryption?".to_string(),
+            action: ConfirmAction::EnableEncryption("!dev:nok.local".to_string()),
+        });
+
+        ask(&mut ui);
+        assert_eq!(ui.answer_confirmation(KeyCode::Char('x')), ConfirmAnswer::Open);
+        assert_eq!(ui.answer_confirmation(KeyCode::Enter), ConfirmAnswer::Open);
+        assert!(ui.confirm.is_some());
+
+        assert_eq!(ui.answer_confirmation(KeyCode::Esc), ConfirmAnswer::No);
+        assert!(ui.confirm.is_none());
+
+        ask(&mut ui);
+        assert_eq!(
+            ui.answer_confirmation(KeyCode::Char('Y')),
+            ConfirmAnswer::Yes(ConfirmAction::EnableEncryption("!dev:nok.local".to_string()))
+        );
+        assert!(ui.confirm.is_none());
+        // Nothing is run twice
+        assert_eq!(ui.answer_confirmation(KeyCode::Char('y')), ConfirmAnswer::No);
+    }
+
+    #[test]
+    fn test_verification_keys_follow_the_phase() {
+        let emojis = VerificationPhase::Emojis(vec![("🐶".to_string(), "Dog".to_string())]);
+
+        // Waiting phases only listen to Esc
+        for phase in [VerificationPhase::Requested, VerificationPhase::Starting, VerificationPhase::Confirmed] {
+            assert_eq!(answer_verification(&phase, KeyCode::Char('y')), None);
+            assert_eq!(answer_verification(&phase, KeyCode::Esc), Some(VerificationAnswer::Close));
+        }
+
+        assert_eq!(answer_verification(&VerificationPhase::Incoming, KeyCode::Char('y')), Some(VerificationAnswer::Accept));
+        assert_eq!(answer_verification(&VerificationPhase::Incoming, KeyCode::Char('n')), Some(VerificationAnswer::Decline));
+        assert_eq!(answer_verification(&emojis, KeyCode::Char('y')), Some(VerificationAnswer::Match));
+        assert_eq!(answer_verification(&emojis, KeyCode::Char('n')), Some(VerificationAnswer::Mismatch));
+        assert_eq!(answer_verification(&emojis, KeyCode::Enter), None);
+
+        // Any key dismisses a finished verification
+        assert_eq!(answer_verification(&VerificationPhase::Done, KeyCode::Enter), Some(VerificationAnswer::Close));
+        assert_eq!(
+            answer_verification(&VerificationPhase::Cancelled("mismatch".to_string()), KeyCode::Char('y')),
+            Some(VerificationAnswer::Close)
+        );
+    }
+
+    fn device(device_id: &str, is_current: bool) -> crate::matrix::DeviceInfo {
+        crate::matrix::DeviceInfo {
+            device_id: device_id.to_string(),
+            display_name: None,
+            last_seen_ip: None,
+            last_seen: None,
+            verified: false,
+            is_current,
+        }
+    }
+
+    #[test]
+    fn test_marked_devices_are_logged_out_together() {
+        let mut devices = DeviceSettings::default();
+        devices.set_list(vec![device("HERE", true), device("PHONE", false), device("LAPTOP", false)]);
+
+        // This session is never a target
+        devices.toggle_mark();
+        assert!(devices.marked.is_empty());
+        assert!(devices.logout_targets().is_empty());
+
+        // Without marks the selected device is the target
+        devices.selected = 2;
+        assert_eq!(devices.logout_targets(), vec!["LAPTOP".to_string()]);
+
+        devices.toggle_mark();
+        devices.selected = 1;
+        devices.toggle_mark();
+        assert_eq!(devices.logout_targets(), vec!["LAPTOP".to_string(), "PHONE".to_string()]);
+
+        devices.toggle_mark();
+        assert_eq!(devices.logout_targets(), vec!["LAPTOP".to_string()]);
+    }
+
+    #[test]
+    fn test_reading_the_device_list_again() {
+        let mut devices = DeviceSettings::default();
+        devices.set_list(vec![device("HERE", true), device("PHONE", false), device("LAPTOP", false)]);
+        devices.selected = 2;
+        devices.toggle_mark();
+
+        // LAPTOP was logged out
+        devices.set_list(vec![device("HERE", true), device("PHONE", false)]);
+        assert!(devices.marked.is_empty());
+        assert_eq!(devices.selected_device().map(|d| d.device_id.as_str()), Some("PHONE"));
+
+        devices.set_list(Vec::new());
+        assert_eq!(devices.selected, 0);
+        assert!(devices.selected_device().is_none());
+        assert!(devices.logout_targets().is_empty());
+    }
+
+    #[test]
+    fn test_background_lines_reach_the_debug_log() {
+        let mut logs = LogState::new();
+        let background = logs.background.clone();
+        std::thread::spawn(move || background.lock().unwrap().push("Error playing knock sound: no device".to_string()))
+            .join()
+            .unwrap();
+
+        logs.take_background();
+        assert_eq!(logs.debug_logs.len(), 1);
+        assert!(logs.debug_logs[0].ends_with("Error playing knock sound: no device"));
+        logs.take_background();
+        assert_eq!(logs.debug_logs.len(), 1);
+    }
+
+    #[test]
+    fn test_command_targets_resolve_to_mxids() {
+        let mut data = data_with(Vec::new());
+        data.users = vec![user("@bob:work.example", &[])];
+        data.users[0].name = "Bobby".to_string();
+
+        assert_eq!(data.resolve_user_id("@carol:example.org", "example.org"), "@carol:example.org");
+        // People in the Users pane by display name or username
+        assert_eq!(data.resolve_user_id("Bobby", "example.org"), "@bob:work.example");
+        assert_eq!(data.resolve_user_id("@bob", "example.org"), "@bob:work.example");
+        // Anyone else is taken to be on our server
+        assert_eq!(data.resolve_user_id("dave", "example.org"), "@dave:example.org");
+    }
+
+    #[test]
+    fn test_leaving_a_room_keeps_the_current_one() {
+        let mut data = data_with(vec![room("!a:x"), room("!b:x"), room("!c:x")]);
+        data.current_room = 2;
+
+        // Leaving a room above the current one
+        assert_eq!(data.remove_room(0).and_then(|r| r.matrix_id), Some("!a:x".to_string()));
+        assert_eq!(data.get_current_room().and_then(|r| r.matrix_id.clone()), Some("!c:x".to_string()));
+
+        // Leaving the current, last room moves up to the one before it
+        data.remove_room(1);
+        assert_eq!(data.get_current_room().and_then(|r| r.matrix_id.clone()), Some("!b:x".to_string()));
+        assert!(data.remove_room(5).is_none());
+    }
+
+    fn public_page(names: &[&str], next_batch: Option<&str>, prev_batch: Option<&str>) -> PublicRoomsPage {
+        PublicRoomsPage {
+            rooms: names.iter()
+                .map(|name| PublicRoom {
+                    room_id: format!("!{}:example.org", name),
+                    alias: Some(format!("#{}:example.org", name)),
+                    name: Some(name.to_string()),
+                    topic: None,
+                    member_count: 1,
+                })
+                .collect(),
+            next_batch: next_batch.map(str::to_string),
+            prev_batch: prev_batch.map(str::to_string),
+            total: None,
+        }
+    }
+
+    #[test]
+    fn test_room_directory_pages() {
+        let mut directory = RoomDirectoryState::default();
+        directory.reset("team");
+        directory.show_page(public_page(&["dev", "ops"], Some("p2"), None), 0);
+
+        // No page before the first
+        assert_eq!(directory.press(KeyCode::PageUp), RoomDirectoryStep::Stay);
+        assert_eq!(directory.press(KeyCode::Right), RoomDirectoryStep::Load { since: "p2".to_string(), page_number: 1 });
+
+        directory.show_page(public_page(&["qa"], None, Some("p1")), 1);
+        assert_eq!(directory.press(KeyCode::PageDown), RoomDirectoryStep::Stay);
+        assert_eq!(directory.press(KeyCode::Left), RoomDirectoryStep::Load { since: "p1".to_string(), page_number: 0 });
+
+        // A server handing out a previous token on its first page
+        directory.show_page(public_page(&["dev"], None, Some("p0")), 0);
+        assert_eq!(directory.press(KeyCode::PageUp), RoomDirectoryStep::Load { since: "p0".to_string(), page_number: 0 });
+    }
+
+    #[test]
+    fn test_room_directory_selection_stays_on_the_page() {
+        let mut directory = RoomDirectoryState::default();
+        directory.show_page(public_page(&["dev", "ops", "qa"], Some("p2"), None), 0);
+
+        directory.press(KeyCode::Up);
+        assert_eq!(directory.selected, 0);
+        for _ in 0..5 {
+            directory.press(KeyCode::Down);
+        }
+        assert_eq!(directory.selected, 2);
+        assert_eq!(directory.press(KeyCode::Enter), RoomDirectoryStep::Join("#qa:example.org".to_string()));
+
+        // A new page starts at its top, and its own length bounds the cursor
+        directory.show_page(public_page(&["web"], None, Some("p1")), 1);
+        assert_eq!(directory.selected, 0);
+        directory.press(KeyCode::Down);
+        assert_eq!(directory.selected, 0);
+        assert_eq!(directory.press(KeyCode::Enter), RoomDirectoryStep::Join("#web:example.org".to_string()));
+
+        // Nothing to join on an empty page
+        directory.show_page(PublicRoomsPage::default(), 0);
+        assert_eq!(directory.press(KeyCode::Enter), RoomDirectoryStep::Stay);
+        assert_eq!(directory.press(KeyCode::Esc), RoomDirectoryStep::Close);
+    }
+
+    #[test]
+    fn test_room_directory_typing_searches_again() {
+        let mut directory = RoomDirectoryState::default();
+        directory.reset("tea");
+        assert_eq!(directory.press(KeyCode::Char('m')), RoomDirectoryStep::Stay);
+        assert_eq!(directory.query, "team");
+        assert!(directory.query_changed_at.is_some());
+        directory.press(KeyCode::Backspace);
+        assert_eq!(directory.query, "tea");
+    }
+
+    fn directory_with(user_ids: &[&str]) -> UserDirectoryState {
+        let mut directory = UserDirectoryState::default();
+        directory.reset("a");
+        directory.results = user_ids.iter().map(|user_id| user(user_id, &[])).collect();
+        directory
+    }
+
+    #[test]
+    fn test_user_directory_actions_target_the_selection() {
+        let mut directory = directory_with(&["@alice:example.org", "@bob:example.org"]);
+
+        // In the search, letters are typed rather than acted on
+        assert_eq!(directory.press(KeyCode::Char('k')), UserDirectoryStep::Stay);
+        assert_eq!(directory.query, "ak");
+        assert_eq!(directory.press(KeyCode::Enter), UserDirectoryStep::Search);
+
+        directory.press(KeyCode::Tab);
+        assert!(directory.results_focused);
+        assert_eq!(
+            directory.press(KeyCode::Char('k')),
+            UserDirectoryStep::Knock { user_id: "@alice:example.org".to_string(), name: "alice".to_string() }
+        );
+        directory.press(KeyCode::Down);
+        directory.press(KeyCode::Down);
+        assert_eq!(directory.selected, 1);
+        assert_eq!(directory.press(KeyCode::Char('i')), UserDirectoryStep::Invite("@bob:example.org".to_string()));
+        assert_eq!(directory.press(KeyCode::Char('m')), UserDirectoryStep::Dm("@bob:example.org".to_string()));
+        directory.press(KeyCode::Up);
+        assert_eq!(directory.press(KeyCode::Enter), UserDirectoryStep::Dm("@alice:example.org".to_string()));
+        assert_eq!(directory.query, "ak");
+
+        // Back in the search, typing resumes
+        directory.press(KeyCode::Tab);
+        assert!(!directory.results_focused);
+        assert_eq!(directory.press(KeyCode::Char('m')), UserDirectoryStep::Stay);
+        assert_eq!(directory.query, "akm");
+        assert_eq!(directory.press(KeyCode::Esc), UserDirectoryStep::Close);
+    }
+
+    #[test]
+    fn test_user_directory_without_results() {
+        let mut directory = directory_with(&[]);
+
+        // Nothing to focus, so keys keep editing the search
+        directory.press(KeyCode::Tab);
+        directory.press(KeyCode::Down);
+        assert!(!directory.results_focused);
+        assert_eq!(directory.press(KeyCode::Char('i')), UserDirectoryStep::Stay);
+        assert_eq!(directory.query, "ai");
+        assert_eq!(directory.press(KeyCode::Enter), UserDirectoryStep::Search);
+
+        // Focused on a stale selection past the results, or on someone
+        // without an MXID, there is no one to act on
+        directory.results_focused = true;
+        directory.selected = 3;
+        assert_eq!(directory.press(KeyCode::Char('k')), UserDirectoryStep::Stay);
+        assert_eq!(directory.press(KeyCode::Enter), UserDirectoryStep::Stay);
+        directory.results = vec![User::new("ghost".to_string())];
+        directory.selected = 0;
+        assert_eq!(directory.press(KeyCode::Char('m')), UserDirectoryStep::Stay);
+    }
+}
@@ -1,10 +1,20 @@
-use crate::matrix::{MatrixClient, MatrixConfig};
+use std::path::{Path, PathBuf};
+
+use matrix_sdk::ruma::{OwnedRoomId, UserId};
+
+use crate::matrix::{store, MatrixClient, MatrixConfig};
 use crate::util::{ValidationError, NokError, NokResult};
 
 /// Matrix-specific state management
 #[derive(Debug)]
 pub struct MatrixState {
-    pub client: Option<MatrixClient>,
+    /// One client per account, each with its own store. Several can be
+    /// logged in at once; a client whose login failed stays here until the
+    /// login is retried or given up.
+    pub clients: Vec<MatrixClient>,
+    /// Index into `clients` of the account used for whatever is not tied
+    /// to a room
+    pub active: usize,
     pub config: MatrixConfig,
     pub enabled: bool,
     pub login: LoginState,
@@ -18,18 +28,27 @@ pub struct LoginState {
     pub error: Option<String>,
     pub field_focus: LoginField,
     pub is_logging_in: bool,
+    /// Passphrase typed for the state store; `None` when the login screen
+    /// does not ask for one
+    pub store_passphrase: Option<String>,
+    /// Accounts that logged in from this machine before, most recent first
+    pub saved_accounts: Vec<String>,
+    /// Saved account filled into the username field
+    pub saved_account_index: Option<usize>,
 }
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum LoginField {
     Username,
     Password,
+    StorePassphrase,
 }
 
 impl MatrixState {
     pub fn new(config: MatrixConfig) -> Self {
         Self {
-            client: None,
+            clients: Vec::new(),
+            active: 0,
             config,
             enabled: false,
             login: LoginState::new(),
@@ -42,53 +61,221 @@ impl MatrixState {
 
     pub fn disable(&mut self) {
         self.enabled = false;
-        self.client = None;
+        self.clients.clear();
+        self.active = 0;
     }
 
     pub fn is_enabled(&self) -> bool {
         self.enabled
     }
 
-    pub fn is_initialized(&self) -> bool {
-        self.client.is_some()
-    }
-
+    /// Whether the active account is logged in
     pub fn is_logged_in(&self) -> bool {
-        self.client.as_ref()
+        self.get_client()
             .map(|client| client.user_id().is_some())
             .unwrap_or(false)
     }
 
-    pub fn set_client(&mut self, client: MatrixClient) {
-        self.client = Some(client);
+    /// Client of the active account
+    pub fn get_client(&self) -> Option<&MatrixClient> {
+        self.clients.get(self.active)
     }
 
-    pub fn get_client(&self) -> Option<&MatrixClient> {
-        self.client.as_ref()
+    /// Clients of all logged in accounts
+    pub fn logged_in_clients(&self) -> impl Iterator<Item = &MatrixClient> {
+        self.clients.iter().filter(|client| client.user_id().is_some())
+    }
+
+    /// MXIDs of all logged in accounts, in login order
+    pub fn account_ids(&self) -> Vec<String> {
+        self.logged_in_clients()
+            .filter_map(|client| client.user_id())
+            .map(|id| id.to_string())
+            .collect()
+    }
+
+    /// Client of one of our logged in accounts
+    pub fn client_for_account(&self, user_id: &str) -> Option<&MatrixClient> {
+        self.logged_in_clients().find(|client| client.user_id().is_some_and(|id| id == user_id))
+    }
+
+    /// Client of the account that is in a room (joined or invited), the
+    /// active one first when several are
+    pub fn client_for_room(&self, room_id: &OwnedRoomId) -> Option<&MatrixClient> {
+        let ranks: Vec<Option<u8>> = self.clients.iter()
+            .map(|client| (client.user_id().is_some() && client.get_room(room_id).is_some()).then_some(0))
+            .collect();
+        pick_account(self.active, &ranks).map(|index| &self.clients[index])
+    }
+
+    /// Client of the account to reach a user from: the account itself when
+    /// it is one of ours, else one with a DM with them, else one sharing a
+    /// room with them, the active one first among equals
+    pub async fn client_for_user(&self, user_id: &UserId) -> Option<&MatrixClient> {
+        let mut ranks = Vec::with_capacity(self.clients.len());
+        for client in &self.clients {
+            let rank = match client.user_id() {
+                None => None,
+                Some(own) if own == user_id => Some(0),
+                Some(_) if client.has_dm_with(user_id) => Some(1),
+                Some(_) => client.shared_room_with(user_id).await.map(|_| 2),
+            };
+            ranks.push(rank);
+        }
+        pick_account(self.active, &ranks).map(|index| &self.clients[index])
+    }
+
+    /// Client of an account on the server a room ID or alias belongs to,
+    /// the active one first
+    pub fn client_for_server(&self, room_id_or_alias: &str) -> Option<&MatrixClient> {
+        let server = room_id_or_alias.split_once(':').map(|(_, server)| server)?;
+        let ranks: Vec<Option<u8>> = self.clients.iter()
+            .map(|client| client.user_id().filter(|id| id.server_name().as_str() == server).map(|_| 0))
+            .collect();
+        pick_account(self.active, &ranks).map(|index| &self.clients[index])
     }
 
-    pub fn clear_client(&mut self) {
-        self.client = None;
+    /// Make a logged in account the active one. Returns false when there
+    /// is no such account.
+    pub fn set_active_account(&mut self, user_id: &str) -> bool {
+        let index = self.clients.iter()
+            .position(|client| client.user_id().is_some_and(|id| id == user_id));
+        if let Some(index) = index {
+            self.active = index;
+        }
+        index.is_some()
     }
 
-    /// Initialize Matrix client
-    pub async fn initialize_client(&mut self) -> NokResult<()> {
+    /// Drop clients whose login never completed, e.g. when adding an
+    /// account is given up, and fall back to the first logged in account
+    pub fn drop_pending_logins(&mut self) {
+        let active_id = self.user_id();
+        self.clients.retain(|client| client.user_id().is_some());
+        self.active = active_id
+            .and_then(|id| self.clients.iter().position(|client| client.user_id().is_some_and(|own| own == id)))
+            .unwrap_or(0);
+    }
+
+    /// Initialize a Matrix client and make it the active one
+    pub async fn initialize_client(&mut self, config: MatrixConfig) -> NokResult<()> {
         if !self.enabled {
             return Err(NokError::InternalError("Matrix mode is disabled".to_string()));
         }
 
-        let client = MatrixClient::new(self.config.clone()).await?;
-        self.client = Some(client);
+        let client = MatrixClient::new(config).await?;
+        self.clients.push(client);
+        self.active = self.clients.len() - 1;
         Ok(())
     }
 
+    /// Open the store of the account about to log in on `homeserver_url`
+    /// and make its client the active one; each account keeps its own
+    /// store. A passphrase typed on the login screen opens it when the key
+    /// file gave none.
+    pub async fn open_account(&mut self, user_id: &str, homeserver_url: &str, device_id: Option<String>) -> NokResult<()> {
+        let store_path = store::account_store_path(Path::new(&self.config.store_path), user_id)
+            .to_string_lossy()
+            .into_owned();
+        if let Some(index) = self.clients.iter().position(|client| client.state_store_path() == store_path) {
+            if self.clients[index].user_id().is_some() {
+                return Err(NokError::InvalidInput(format!("{} is already logged in", user_id)));
+            }
+            self.active = index;
+            return Ok(());
+        }
+
+        let typed_passphrase = self.config.store_passphrase.is_none() && self.login.store_passphrase.is_some();
+        if typed_passphrase {
+            self.config.store_passphrase = self.login.store_passphrase.clone();
+        }
+        let config = MatrixConfig {
+            homeserver_url: homeserver_url.to_string(),
+            state_store_path: store_path,
+            device_id,
+            ..self.config.clone()
+        };
+        let result = self.initialize_client(config).await;
+        if result.is_err() && typed_passphrase {
+            self.config.store_passphrase = None;
+        }
+        result
+    }
+
+    /// Log the active account out on the homeserver, which invalidates its
+    /// access token, and close its store. The first other account becomes
+    /// active. Returns the account that logged out and where its store is.
+    pub async fn logout(&mut self) -> NokResult<(String, PathBuf)> {
+        let client = self.get_client()
+            .ok_or(NokError::MatrixClientNotInitialized)?;
+        let user_id = self.user_id()
+            .ok_or_else(|| NokError::AuthenticationFailed("Not logged in".to_string()))?;
+
+        // A running sync would fail on the dead token
+        client.stop_sync().await;
+        if let Err(e) = client.logout().await {
+            let _ = client.start_sync().await;
+            return Err(NokError::AuthenticationFailed(format!("Could not log out: {}", e)));
+        }
+        let client = self.clients.remove(self.active);
+        self.active = 0;
+
+        // A passphrase typed at login is asked for again once no account
+        // is left logged in
+        if self.clients.is_empty() && self.login.store_passphrase.is_some() {
+            self.config.store_passphrase = None;
+            self.login.ask_store_passphrase();
+        }
+        Ok((user_id, PathBuf::from(client.state_store_path())))
+    }
+
+    /// Clean up the store of an account that logged out: `wipe` deletes
+    /// it, otherwise only the keys of the logged out device go
+    pub fn clear_store(store_path: &Path, wipe: bool) -> std::io::Result<()> {
+        if wipe {
+            store::remove_store(store_path)
+        } else {
+            store::remove_crypto_store(store_path)
+        }
+    }
+
+    /// Short name for one of our accounts, for tagging its rooms and
+    /// users: the homeserver, or the whole MXID when two accounts share a
+    /// homeserver. `None` while only one account is logged in.
+    pub fn account_tag(&self, account: &str) -> Option<String> {
+        account_tag(&self.account_ids(), account)
+    }
+
+    /// Fill the username field with the next (`forward`) or previous saved
+    /// account
+    pub fn switch_saved_account(&mut self, forward: bool) {
+        let count = self.login.saved_accounts.len();
+        if count == 0 {
+            return;
+        }
+        let index = match (self.login.saved_account_index, forward) {
+            (None, _) => 0,
+            (Some(index), true) => (index + 1) % count,
+            (Some(index), false) => (index + count - 1) % count,
+        };
+        let user_id = &self.login.saved_accounts[index];
+        // Accounts on our homeserver are typed by username alone
+        let suffix = format!(":{}", self.config.server_name);
+        let username = user_id.strip_suffix(&suffix)
+            .map(|id| id.trim_start_matches('@'))
+            .unwrap_or(user_id);
+        self.login.username = username.to_string();
+        self.login.password.clear();
+        self.login.saved_account_index = Some(index);
+        self.login.clear_error();
+    }
+
     /// Login to Matrix homeserver
     pub async fn login(&mut self, username: &str, password: &str) -> NokResult<()> {
         if !self.enabled {
             return Err(NokError::InternalError("Matrix mode is disabled".to_string()));
         }
 
-        let client = self.client.as_ref()
+        let client = self.clients.get(self.active)
             .ok_or(NokError::MatrixClientNotInitialized)?;
 
         self.login.set_logging_in(true);
@@ -106,35 +293,72 @@ impl MatrixState {
         result
     }
 
-    /// Start Matrix sync
+    /// Start Matrix sync of every logged in account; accounts already
+    /// syncing carry on
     pub async fn start_sync(&self) -> NokResult<()> {
-        let client = self.client.as_ref()
-            .ok_or(NokError::MatrixClientNotInitialized)?;
-
-        client.start_sync().await
-            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+        if self.logged_in_clients().next().is_none() {
+            return Err(NokError::MatrixClientNotInitialized);
+        }
+        for client in self.logged_in_clients() {
+            client.start_sync().await
+                .map_err(|e| NokError::MatrixSyncError(e.to_string()))?;
+        }
+        Ok(())
     }
 
-    /// Stop Matrix sync
+    /// Stop Matrix sync of every account
     pub async fn stop_sync(&self) {
-        if let Some(client) = &self.client {
+        for client in &self.clients {
             client.stop_sync().await;
         }
     }
 
-    /// Get Matrix user ID
+    /// Matrix user ID of the active account
     pub fn user_id(&self) -> Option<String> {
-        self.client.as_ref()
+        self.get_client()
             .and_then(|client| client.user_id())
             .map(|id| id.to_string())
     }
 
-    /// Get Matrix rooms
-    pub fn rooms(&self) -> Vec<matrix_sdk::Room> {
-        self.client.as_ref()
-            .map(|client| client.rooms())
-            .unwrap_or_default()
+    /// Joined rooms of all logged in accounts, each with the account that
+    /// is in it. A room two accounts share is listed once.
+    pub fn joined_rooms(&self) -> Vec<(String, matrix_sdk::Room)> {
+        let mut rooms: Vec<(String, matrix_sdk::Room)> = Vec::new();
+        for client in self.logged_in_clients() {
+            let Some(account) = client.user_id() else {
+                continue;
+            };
+            for room in client.joined_rooms() {
+                if !rooms.iter().any(|(_, known)| known.room_id() == room.room_id()) {
+                    rooms.push((account.to_string(), room));
+                }
+            }
+        }
+        rooms
+    }
+}
+
+/// Index of the account to act from, given how well each one fits
+/// (`None`: not at all, lower is better): the best fit, the active account
+/// first among equals
+fn pick_account(active: usize, ranks: &[Option<u8>]) -> Option<usize> {
+    let best = ranks.iter().flatten().min()?;
+    if ranks.get(active).copied().flatten() == Some(*best) {
+        return Some(active);
     }
+    ranks.iter().position(|rank| *rank == Some(*best))
+}
+
+/// Tag of `account` among `accounts`: its server, or the whole MXID when
+/// another account is on the same server. `None` for a single account.
+fn account_tag(accounts: &[String], account: &str) -> Option<String> {
+    if accounts.len() < 2 {
+        return None;
+    }
+    let server = |id: &str| id.split_once(':').map(|(_, server)| server.to_string()).unwrap_or_default();
+    let own_server = server(account);
+    let shared = accounts.iter().filter(|id| server(id) == own_server).count() > 1;
+    Some(if shared { account.to_string() } else { own_server })
 }
 
 impl LoginState {
@@ -145,6 +369,9 @@ impl LoginState {
             error: None,
             field_focus: LoginField::Username,
             is_logging_in: false,
+            store_passphrase: None,
+            saved_accounts: Vec::new(),
+            saved_account_index: None,
         }
     }
 
@@ -169,6 +396,9 @@ impl LoginState {
     pub fn clear_credentials(&mut self) {
         self.username.clear();
         self.password.clear();
+        if let Some(passphrase) = &mut self.store_passphrase {
+            passphrase.clear();
+        }
     }
 
     pub fn set_error(&mut self, error: String) {
@@ -183,10 +413,16 @@ impl LoginState {
         self.field_focus = field;
     }
 
+    /// Show the store passphrase field
+    pub fn ask_store_passphrase(&mut self) {
+        self.store_passphrase = Some(String::new());
+    }
+
     pub fn next_field(&mut self) {
         self.field_focus = match self.field_focus {
             LoginField::Username => LoginField::Password,
-            LoginField::Password => LoginField::Username,
+            LoginField::Password if self.store_passphrase.is_some() => LoginField::StorePassphrase,
+            LoginField::Password | LoginField::StorePassphrase => LoginField::Username,
         };
     }
 
@@ -218,7 +454,8 @@ impl LoginState {
     }
 
     pub fn can_submit(&self) -> bool {
-        self.is_form_valid() && !self.is_logging_in
+        let passphrase_given = self.store_passphrase.as_ref().is_none_or(|passphrase| !passphrase.is_empty());
+        self.is_form_valid() && passphrase_given && !self.is_logging_in
     }
 }
 
@@ -226,4 +463,60 @@ impl Default for LoginState {
     fn default() -> Self {
         Self::new()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick_account_prefers_best_fit_then_active() {
+        // Only the second account is in the room
+        assert_eq!(pick_account(0, &[None, Some(0)]), Some(1));
+        // Both are: the active one acts
+        assert_eq!(pick_account(1, &[Some(0), Some(0)]), Some(1));
+        assert_eq!(pick_account(2, &[Some(0), Some(0), None]), Some(0));
+        // A DM beats a shared room, even on another account
+        assert_eq!(pick_account(0, &[Some(2), Some(1)]), Some(1));
+        assert_eq!(pick_account(0, &[None, None]), None);
+        assert_eq!(pick_account(0, &[]), None);
+    }
+
+    #[test]
+    fn test_account_tag() {
+        let one = vec!["@alice:example.org".to_string()];
+        assert_eq!(account_tag(&one, "@alice:example.org"), None);
+
+        let accounts = vec![
+            "@alice:example.org".to_string(),
+            "@alice:work.example".to_string(),
+            "@bob:work.example".to_string(),
+        ];
+        assert_eq!(account_tag(&accounts, "@alice:example.org"), Some("example.org".to_string()));
+        // Two accounts on one server need their full MXIDs
+        assert_eq!(account_tag(&accounts, "@bob:work.example"), Some("@bob:work.example".to_string()));
+    }
+
+    #[test]
+    fn test_switch_saved_account() {
+        let config = MatrixConfig { server_name: "example.org".to_string(), ..MatrixConfig::default() };
+        let mut state = MatrixState::new(config);
+        state.switch_saved_account(true);
+        assert_eq!(state.login.saved_account_index, None);
+
+        state.login.saved_accounts = vec!["@alice:example.org".to_string(), "@bob:other.example".to_string()];
+        state.login.password = "secret".to_string();
+        state.switch_saved_account(true);
+        // Accounts on our server are filled in by username alone
+        assert_eq!(state.login.username, "alice");
+        assert_eq!(state.login.saved_account_index, Some(0));
+        assert!(state.login.password.is_empty());
+
+        state.switch_saved_account(true);
+        assert_eq!(state.login.username, "@bob:other.example");
+        state.switch_saved_account(true);
+        assert_eq!(state.login.saved_account_index, Some(0));
+        state.switch_saved_account(false);
+        assert_eq!(state.login.saved_account_index, Some(1));
+    }
+}
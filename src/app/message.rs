@@ -8,6 +8,85 @@ pub struct Message {
     pub message_type: String,
     pub timestamp: u64,
     pub room: String,
+    /// Matrix User ID of the sender (@username:domain形式)
+    pub sender_id: Option<String>,
+    /// Quoted message this one replies to (`m.in_reply_to`)
+    pub reply_to: Option<ReplyPreview>,
+    /// Set when an `m.replace` edit has been applied
+    pub edited: bool,
+    /// Set when the event has been redacted
+    pub redacted: bool,
+    /// Aggregated `m.annotation` reactions
+    pub reactions: Vec<Reaction>,
+    /// Root event ID when this message belongs to an `m.thread`
+    pub thread_root: Option<String>,
+    /// Number of thread replies, counted on the root message
+    pub thread_replies: usize,
+    /// Media attached to an `m.file` / `m.image` / `m.audio` / `m.video` message
+    pub attachment: Option<Attachment>,
+    /// Styled lines rendered from an HTML `formatted_body`
+    pub formatted: Option<Vec<RichLine>>,
+    /// Set when push rules (or our own mention detection) flag this message
+    pub highlighted: bool,
+}
+
+/// Short excerpt of the message being replied to
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplyPreview {
+    pub event_id: String,
+    pub sender: String,
+    pub content: String,
+}
+
+/// Media carried by a message; the content itself is fetched on `/save`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Attachment {
+    pub kind: String,
+    pub filename: String,
+    pub size: Option<u64>,
+}
+
+impl Attachment {
+    /// Timeline label such as "[image] cat.png (12 KB)"
+    pub fn label(&self) -> String {
+        match self.size {
+            Some(size) if size >= 1024 * 1024 => format!("[{}] {} ({:.1} MB)", self.kind, self.filename, size as f64 / (1024.0 * 1024.0)),
+            Some(size) if size >= 1024 => format!("[{}] {} ({} KB)", self.kind, self.filename, size / 1024),
+            Some(size) => format!("[{}] {} ({} B)", self.kind, self.filename, size),
+            None => format!("[{}] {}", self.kind, self.filename),
+        }
+    }
+}
+
+/// One line of formatted message text
+pub type RichLine = Vec<RichSpan>;
+
+/// Run of text sharing one style
+#[derive(Clone, Debug, PartialEq)]
+pub struct RichSpan {
+    pub text: String,
+    pub style: TextStyle,
+}
+
+/// Inline and block styles carried over from HTML
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TextStyle {
+    pub bold: bool,
+    pub italic: bool,
+    pub strike: bool,
+    pub code: bool,
+    pub code_block: bool,
+    pub link: bool,
+    pub quote: bool,
+}
+
+/// One reaction key and who used it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reaction {
+    pub key: String,
+    pub count: usize,
+    /// Event ID of our own reaction with this key, used to take it back
+    pub own_event_id: Option<String>,
 }
 
 impl Message {
@@ -24,6 +103,16 @@ impl Message {
             message_type: "text".to_string(),
             timestamp: now,
             room,
+            sender_id: None,
+            reply_to: None,
+            edited: false,
+            redacted: false,
+            reactions: Vec::new(),
+            thread_root: None,
+            thread_replies: 0,
+            attachment: None,
+            formatted: None,
+            highlighted: false,
         }
     }
 
@@ -34,7 +123,7 @@ impl Message {
             .unwrap()
             .as_secs();
 
-        let diff = now - self.timestamp;
+        let diff = now.saturating_sub(self.timestamp);
 
         if diff < 60 {
             "now".to_string()
@@ -46,4 +135,69 @@ impl Message {
             format!("{}d ago", diff / 86400)
         }
     }
+
+    /// Body as shown in the Messages pane, with edit/redaction markers
+    pub fn display_content(&self) -> String {
+        if self.redacted {
+            "(deleted)".to_string()
+        } else if let Some(attachment) = &self.attachment {
+            attachment.label()
+        } else if self.edited {
+            format!("{} (edited)", self.content)
+        } else {
+            self.content.clone()
+        }
+    }
+
+    /// Whether this message was sent by the given Matrix user
+    pub fn is_from(&self, matrix_id: &str) -> bool {
+        self.sender_id.as_deref() == Some(matrix_id)
+    }
+
+    /// Whether this message is shown in the thread view for `root`
+    pub fn in_thread(&self, root: &str) -> bool {
+        self.id.as_deref() == Some(root) || self.thread_root.as_deref() == Some(root)
+    }
+
+    /// Count a reaction, remembering our own reaction event if it is ours
+    pub fn add_reaction(&mut self, key: &str, own_event_id: Option<String>) {
+        if let Some(reaction) = self.reactions.iter_mut().find(|r| r.key == key) {
+            reaction.count += 1;
+            if own_event_id.is_some() {
+                reaction.own_event_id = own_event_id;
+            }
+        } else {
+            self.reactions.push(Reaction {
+                key: key.to_string(),
+                count: 1,
+                own_event_id,
+            });
+        }
+    }
+
+    /// Drop a reaction that was redacted
+    pub fn remove_reaction_event(&mut self, key: &str, is_own: bool) {
+        if let Some(reaction) = self.reactions.iter_mut().find(|r| r.key == key) {
+            reaction.count = reaction.count.saturating_sub(1);
+            if is_own {
+                reaction.own_event_id = None;
+            }
+        }
+        self.reactions.retain(|r| r.count > 0);
+    }
+
+    /// Our own reaction event for a key, if we already reacted with it
+    pub fn own_reaction(&self, key: &str) -> Option<&str> {
+        self.reactions.iter()
+            .find(|r| r.key == key)
+            .and_then(|r| r.own_event_id.as_deref())
+    }
+
+    /// Reactions rendered as "👍 2  ❤️ 1"
+    pub fn reactions_summary(&self) -> String {
+        self.reactions.iter()
+            .map(|r| format!("{} {}", r.key, r.count))
+            .collect::<Vec<_>>()
+            .join("  ")
+    }
 }
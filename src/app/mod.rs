@@ -3,6 +3,7 @@ pub mod user;
 mod room;
 mod message;
 mod config;
+mod office;
 
 // New modular architecture
 pub mod core;
@@ -16,12 +17,13 @@ use crossterm::event::KeyEvent;
 use crossterm::event::KeyCode;
 pub use state::AppState;
 pub use user::{User, UserStatus};
-pub use room::Room;
-pub use message::Message;
+pub use room::{PublicRoom, PublicRoomsPage, Room, RoomInvite};
+pub use message::{Attachment, Message, ReplyPreview, RichLine, RichSpan, TextStyle};
 pub use config::Config;
+pub use office::{knock_frame, Avatar, OfficeEditor, OfficeMap, Position, Tile, Zone};
 
 // Re-export new modular components
-pub use core::{AppCore, UiState, DataState, LogState, NetworkState};
+pub use core::{AppCore, UiState, DataState, LogState, NetworkState, ComposeMode, RoomPaneEntry, Confirmation};
 pub use core::PaneIdentifier as CorePaneIdentifier;
 pub use core::ConnectionStatus as CoreConnectionStatus;
 pub use matrix_state::{MatrixState, LoginState};
@@ -714,6 +716,10 @@ impl App {
                     _ => {}
                 }
             },
+            // Only the new App opens the directory and profile overlays
+            AppState::RoomDirectory | AppState::UserDirectory | AppState::Profile => {
+                self.state = AppState::Normal;
+            },
         }
     }
 
@@ -1057,7 +1063,7 @@ impl App {
     /// Matrix syncからルーム一覧を更新
     pub async fn sync_matrix_rooms(&mut self) -> Result<(), Box<dyn std::error::Error>> {
         if let Some(ref client) = self.matrix_client {
-                        let matrix_rooms = client.rooms();
+                        let matrix_rooms = client.joined_rooms();
             let rooms_count = matrix_rooms.len();
 
             // 既存のMatrix ルームを更新
@@ -1066,6 +1072,9 @@ impl App {
                 let display_name = matrix_room.display_name().await
                     .map(|name| name.to_string())
                     .unwrap_or_else(|_| room_id.clone());
+                let encrypted = matrix_room.latest_encryption_state().await
+                    .map(|state| state.is_encrypted())
+                    .unwrap_or(false);
 
                 // 既存ルームを検索、なければ作成
                 if let Some(existing_room) = self.rooms.iter_mut()
@@ -1073,8 +1082,8 @@ impl App {
                     // 既存ルームを更新
                     existing_room.name = display_name.clone();
                     existing_room.set_member_count(matrix_room.active_members_count() as usize);
-                    // is_encrypted()メソッドがないため、一旦falseに設定
-                    existing_room.set_encrypted(false);
+                    // 暗号化状態はルームステート (m.room.encryption) から取得
+                    existing_room.set_encrypted(encrypted);
 
                     // トピック更新
                     if let Some(topic) = matrix_room.topic() {
@@ -1084,8 +1093,7 @@ impl App {
                     // 新しいルームを作成
                     let mut room = Room::from_matrix_room(room_id, display_name);
                     room.set_member_count(matrix_room.active_members_count() as usize);
-                    // is_encrypted()メソッドがないため、一旦falseに設定
-                    room.set_encrypted(false);
+                    room.set_encrypted(encrypted);
 
                     if let Some(topic) = matrix_room.topic() {
                         room.set_topic(Some(topic.to_string()));
@@ -1108,7 +1116,7 @@ impl App {
             let mut all_users = std::collections::HashSet::new();
 
                         // 全ルームからメンバーを取得
-            let matrix_rooms = client.rooms();
+            let matrix_rooms = client.joined_rooms();
             for matrix_room in &matrix_rooms {
                 use matrix_sdk::RoomMemberships;
                 let members = matrix_room.members(RoomMemberships::all()).await?;
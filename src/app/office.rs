@@ -0,0 +1,535 @@
+use super::user::UserStatus;
+use crate::util::error::{NokError, NokResult};
+
+/// Default size of a room's office map in cells, walls included
+pub const OFFICE_WIDTH: u16 = 48;
+pub const OFFICE_HEIGHT: u16 = 14;
+
+/// Frames of the door-knock drawn over someone knocking on us, each shown
+/// for `KNOCK_FRAME_TIME`
+const KNOCK_FRAMES: [&str; 8] = ["[|]", "[|)", "[|))", "[|]", "[|)", "[|))", "knock", "knock knock"];
+const KNOCK_FRAME_TIME: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Largest floor plan we accept, so a shared plan cannot eat the terminal
+pub const MAX_PLAN_WIDTH: u16 = 160;
+pub const MAX_PLAN_HEIGHT: u16 = 60;
+
+/// A cell on the office map
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Position {
+    pub x: u16,
+    pub y: u16,
+}
+
+impl Position {
+    pub fn new(x: u16, y: u16) -> Self {
+        Self { x, y }
+    }
+
+    /// The neighbouring cell in a direction (may be outside the map)
+    pub fn neighbour(self, direction: Direction) -> Position {
+        match direction {
+            Direction::Up => Position::new(self.x, self.y.saturating_sub(1)),
+            Direction::Down => Position::new(self.x, self.y.saturating_add(1)),
+            Direction::Left => Position::new(self.x.saturating_sub(1), self.y),
+            Direction::Right => Position::new(self.x.saturating_add(1), self.y),
+        }
+    }
+
+    /// Steps between two cells when diagonal steps count as one
+    pub fn distance(self, other: Position) -> u16 {
+        self.x.abs_diff(other.x).max(self.y.abs_diff(other.y))
+    }
+}
+
+/// What someone last published on a room's office map
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublishedPosition {
+    pub position: Position,
+    /// Root event of the thread their huddle talks in
+    pub huddle: Option<String>,
+}
+
+/// Someone drawn on the office map
+#[derive(Debug, Clone)]
+pub struct Avatar {
+    pub user_id: String,
+    pub name: String,
+    pub position: Position,
+    pub status: UserStatus,
+    pub is_self: bool,
+    /// Whether they walked to where they are drawn, rather than being
+    /// shown at their desk or a spawn point until they move
+    pub present: bool,
+}
+
+/// The huddle we are part of: everyone present within `distance` of us,
+/// or of someone already in it, so a chain of people forms one group.
+/// Includes ourselves, or is empty when nobody is close enough.
+pub fn huddle(avatars: &[Avatar], distance: u16) -> Vec<&Avatar> {
+    let Some(own) = avatars.iter().position(|avatar| avatar.is_self) else {
+        return Vec::new();
+    };
+    if distance == 0 {
+        return Vec::new();
+    }
+
+    let mut members = vec![own];
+    let mut next = 0;
+    while let Some(&member) = members.get(next) {
+        next += 1;
+        let from = avatars[member].position;
+        for (idx, avatar) in avatars.iter().enumerate() {
+            let reachable = avatar.present
+                && avatar.status != UserStatus::Offline
+                && from.distance(avatar.position) <= distance;
+            if reachable && !members.contains(&idx) {
+                members.push(idx);
+            }
+        }
+    }
+
+    if members.len() < 2 {
+        return Vec::new();
+    }
+    members.into_iter().map(|idx| &avatars[idx]).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+/// The knock animation frame to show `elapsed` after a knock arrived,
+/// `None` once it has played out
+pub fn knock_frame(elapsed: std::time::Duration) -> Option<&'static str> {
+    let frame = elapsed.as_millis() / KNOCK_FRAME_TIME.as_millis();
+    KNOCK_FRAMES.get(usize::try_from(frame).ok()?).copied()
+}
+
+/// What a cell of the floor plan is made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tile {
+    Floor,
+    Wall,
+    /// A desk someone can sit at and claim
+    Desk,
+    Door,
+}
+
+impl Tile {
+    /// Character for the tile in floor plan text
+    pub fn glyph(self) -> char {
+        match self {
+            Tile::Floor => '.',
+            Tile::Wall => '#',
+            Tile::Desk => 'd',
+            Tile::Door => '+',
+        }
+    }
+
+    pub fn from_glyph(c: char) -> Option<Tile> {
+        match c {
+            '.' | ' ' => Some(Tile::Floor),
+            '#' => Some(Tile::Wall),
+            'd' => Some(Tile::Desk),
+            '+' => Some(Tile::Door),
+            _ => None,
+        }
+    }
+}
+
+/// A named area of the floor plan, such as a kitchen or meeting corner
+#[derive(Debug, Clone, PartialEq)]
+pub struct Zone {
+    pub name: String,
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Zone {
+    /// The rectangle spanned by two opposite corners, both included
+    pub fn from_corners(name: &str, a: Position, b: Position) -> Self {
+        Self {
+            name: name.to_string(),
+            x: a.x.min(b.x),
+            y: a.y.min(b.y),
+            width: a.x.abs_diff(b.x) + 1,
+            height: a.y.abs_diff(b.y) + 1,
+        }
+    }
+
+    pub fn contains(&self, pos: Position) -> bool {
+        pos.x >= self.x && pos.y >= self.y && pos.x - self.x < self.width && pos.y - self.y < self.height
+    }
+}
+
+/// Layout of a room's office: walls, desks, doors and named zones.
+///
+/// Floor plans are written as text so they can live in a file or in room
+/// state alike. Each grid line is a row of tiles (`#` wall, `.` floor,
+/// `d` desk, `+` door), `zone <x> <y> <width> <height> <name>` lines name
+/// an area, and lines starting with `;` are comments:
+///
+/// ```text
+/// ##########
+/// #.d..d...#
+/// #........+
+/// ##########
+/// zone 1 1 4 1 Team desks
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct OfficeMap {
+    pub width: u16,
+    pub height: u16,
+    tiles: Vec<Tile>,
+    pub zones: Vec<Zone>,
+}
+
+impl Default for OfficeMap {
+    fn default() -> Self {
+        Self::blank(OFFICE_WIDTH, OFFICE_HEIGHT)
+    }
+}
+
+impl OfficeMap {
+    /// An empty floor surrounded by walls
+    pub fn blank(width: u16, height: u16) -> Self {
+        let tiles = (0..height)
+            .flat_map(|y| (0..width).map(move |x| {
+                if x == 0 || y == 0 || x + 1 == width || y + 1 == height {
+                    Tile::Wall
+                } else {
+                    Tile::Floor
+                }
+            }))
+            .collect();
+        Self { width, height, tiles, zones: Vec::new() }
+    }
+
+    /// Read a floor plan from its text form
+    pub fn parse(text: &str) -> NokResult<Self> {
+        let mut rows: Vec<Vec<Tile>> = Vec::new();
+        let mut zones = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            if let Some(rest) = line.strip_prefix("zone ") {
+                let zone = parse_zone(rest).ok_or_else(|| NokError::InvalidInput(format!(
+                    "Line {}: expected 'zone <x> <y> <width> <height> <name>'", n + 1
+                )))?;
+                zones.push(zone);
+                continue;
+            }
+            let row = line.chars()
+                .map(|c| Tile::from_glyph(c).ok_or(c))
+                .collect::<Result<Vec<Tile>, char>>()
+                .map_err(|c| NokError::InvalidInput(format!("Line {}: unknown tile '{}'", n + 1, c)))?;
+            rows.push(row);
+        }
+
+        let width = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let height = rows.len();
+        if width == 0 || width > usize::from(MAX_PLAN_WIDTH) || height > usize::from(MAX_PLAN_HEIGHT) {
+            return Err(NokError::InvalidInput(format!(
+                "Floor plans must be between 1x1 and {}x{} tiles", MAX_PLAN_WIDTH, MAX_PLAN_HEIGHT
+            )));
+        }
+        // Short rows are padded with floor, as trailing spaces are trimmed
+        let tiles: Vec<Tile> = rows.into_iter()
+            .flat_map(|mut row| {
+                row.resize(width, Tile::Floor);
+                row
+            })
+            .collect();
+        if !tiles.iter().any(|tile| *tile != Tile::Wall) {
+            return Err(NokError::InvalidInput("The floor plan has nowhere to stand".to_string()));
+        }
+
+        let map = Self { width: width as u16, height: height as u16, tiles, zones };
+        if let Some(zone) = map.zones.iter().find(|zone| !map.fits(zone)) {
+            return Err(NokError::InvalidInput(format!("Zone '{}' lies outside the floor plan", zone.name)));
+        }
+        Ok(map)
+    }
+
+    /// The floor plan in the text form read by `parse`
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for row in self.tiles.chunks(usize::from(self.width)) {
+            text.extend(row.iter().map(|tile| tile.glyph()));
+            text.push('\n');
+        }
+        for zone in &self.zones {
+            text.push_str(&format!("zone {} {} {} {} {}\n", zone.x, zone.y, zone.width, zone.height, zone.name));
+        }
+        text
+    }
+
+    /// The tile at a cell; everything outside the plan is wall
+    pub fn tile(&self, pos: Position) -> Tile {
+        if pos.x >= self.width || pos.y >= self.height {
+            return Tile::Wall;
+        }
+        self.tiles[usize::from(pos.y) * usize::from(self.width) + usize::from(pos.x)]
+    }
+
+    pub fn set_tile(&mut self, pos: Position, tile: Tile) {
+        if pos.x < self.width && pos.y < self.height {
+            self.tiles[usize::from(pos.y) * usize::from(self.width) + usize::from(pos.x)] = tile;
+        }
+    }
+
+    /// Whether an avatar may stand on a cell
+    pub fn is_walkable(&self, pos: Position) -> bool {
+        self.tile(pos) != Tile::Wall
+    }
+
+    /// The named zone a cell belongs to, if any
+    pub fn zone_at(&self, pos: Position) -> Option<&Zone> {
+        self.zones.iter().find(|zone| zone.contains(pos))
+    }
+
+    fn fits(&self, zone: &Zone) -> bool {
+        zone.width > 0
+            && zone.height > 0
+            && u32::from(zone.x) + u32::from(zone.width) <= u32::from(self.width)
+            && u32::from(zone.y) + u32::from(zone.height) <= u32::from(self.height)
+    }
+
+    /// Move from `from` one cell in `direction`, staying put when blocked
+    pub fn step(&self, from: Position, direction: Direction) -> Position {
+        let to = from.neighbour(direction);
+        if self.is_walkable(to) {
+            to
+        } else {
+            from
+        }
+    }
+
+    /// Pull a position received from someone else (or left over from an
+    /// older floor plan) onto the nearest cell one can stand on
+    pub fn clamp(&self, pos: Position) -> Position {
+        if self.is_walkable(pos) {
+            return pos;
+        }
+        self.cells()
+            .filter(|cell| self.is_walkable(*cell))
+            .min_by_key(|cell| cell.x.abs_diff(pos.x) + cell.y.abs_diff(pos.y))
+            .unwrap_or(pos)
+    }
+
+    /// Where the `n`th person without a position or desk is drawn: on
+    /// every other floor cell, from the bottom row upwards
+    pub fn spawn_point(&self, n: usize) -> Position {
+        let mut points: Vec<Position> = self.cells()
+            .filter(|cell| cell.x % 2 == 1 && self.tile(*cell) == Tile::Floor)
+            .collect();
+        if points.is_empty() {
+            points = self.cells().filter(|cell| self.is_walkable(*cell)).collect();
+        }
+        points.sort_by_key(|cell| (std::cmp::Reverse(cell.y), cell.x));
+        points.get(n % points.len().max(1)).copied().unwrap_or(Position::new(0, 0))
+    }
+
+    fn cells(&self) -> impl Iterator<Item = Position> + '_ {
+        (0..self.height).flat_map(move |y| (0..self.width).map(move |x| Position::new(x, y)))
+    }
+}
+
+fn parse_zone(spec: &str) -> Option<Zone> {
+    let mut parts = spec.splitn(5, ' ');
+    let mut number = || parts.next()?.parse::<u16>().ok();
+    let (x, y, width, height) = (number()?, number()?, number()?, number()?);
+    let name = parts.next()?.trim();
+    if name.is_empty() {
+        return None;
+    }
+    Some(Zone { name: name.to_string(), x, y, width, height })
+}
+
+/// A floor plan being edited in the office pane
+#[derive(Debug, Clone)]
+pub struct OfficeEditor {
+    pub draft: OfficeMap,
+    pub cursor: Position,
+    /// First corner of a zone being marked out
+    pub zone_anchor: Option<Position>,
+    /// Both corners of a zone waiting for a name from `/zone`
+    pub pending_zone: Option<(Position, Position)>,
+}
+
+impl OfficeEditor {
+    pub fn new(draft: OfficeMap, cursor: Position) -> Self {
+        Self { draft, cursor, zone_anchor: None, pending_zone: None }
+    }
+
+    /// Move the cursor, which may go anywhere on the plan, walls included
+    pub fn move_cursor(&mut self, direction: Direction) {
+        let to = self.cursor.neighbour(direction);
+        if to.x < self.draft.width && to.y < self.draft.height {
+            self.cursor = to;
+        }
+    }
+
+    pub fn paint(&mut self, tile: Tile) {
+        self.draft.set_tile(self.cursor, tile);
+    }
+
+    /// Mark a zone corner at the cursor. Returns true once the second
+    /// corner is set and the zone only needs a name.
+    pub fn mark_zone_corner(&mut self) -> bool {
+        match self.zone_anchor.take() {
+            Some(anchor) => {
+                self.pending_zone = Some((anchor, self.cursor));
+                true
+            }
+            None => {
+                self.zone_anchor = Some(self.cursor);
+                self.pending_zone = None;
+                false
+            }
+        }
+    }
+
+    /// Add the marked zone under `name`; false when no zone is marked
+    pub fn name_pending_zone(&mut self, name: &str) -> bool {
+        let Some((a, b)) = self.pending_zone.take() else {
+            return false;
+        };
+        self.draft.zones.push(Zone::from_corners(name, a, b));
+        true
+    }
+
+    /// Remove the zone under the cursor, returning its name
+    pub fn remove_zone_at_cursor(&mut self) -> Option<String> {
+        let idx = self.draft.zones.iter().position(|zone| zone.contains(self.cursor))?;
+        Some(self.draft.zones.remove(idx).name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step_stops_at_walls() {
+        let map = OfficeMap::default();
+        let corner = Position::new(1, 1);
+        assert_eq!(map.step(corner, Direction::Up), corner);
+        assert_eq!(map.step(corner, Direction::Left), corner);
+        assert_eq!(map.step(corner, Direction::Right), Position::new(2, 1));
+
+        let far = Position::new(map.width - 2, map.height - 2);
+        assert_eq!(map.step(far, Direction::Down), far);
+        assert_eq!(map.step(far, Direction::Right), far);
+    }
+
+    #[test]
+    fn test_clamp_keeps_positions_on_the_floor() {
+        let map = OfficeMap::default();
+        assert_eq!(map.clamp(Position::new(0, 0)), Position::new(1, 1));
+        assert!(map.is_walkable(map.clamp(Position::new(500, 500))));
+        assert_eq!(map.tile(Position::new(0, 3)), Tile::Wall);
+    }
+
+    #[test]
+    fn test_spawn_points_are_distinct_and_walkable() {
+        let map = OfficeMap::default();
+        let points: Vec<Position> = (0..40).map(|n| map.spawn_point(n)).collect();
+        for (i, point) in points.iter().enumerate() {
+            assert!(map.is_walkable(*point), "{:?} is not walkable", point);
+            assert!(!points[..i].contains(point), "{:?} handed out twice", point);
+        }
+    }
+
+    fn avatar(name: &str, x: u16, y: u16, is_self: bool) -> Avatar {
+        Avatar {
+            user_id: format!("@{}:example.org", name),
+            name: name.to_string(),
+            position: Position::new(x, y),
+            status: UserStatus::Online,
+            is_self,
+            present: true,
+        }
+    }
+
+    #[test]
+    fn test_huddle_chains_nearby_people() {
+        let mut avatars = vec![
+            avatar("me", 5, 5, true),
+            avatar("near", 7, 6, false),
+            avatar("chained", 9, 4, false),
+            avatar("far", 20, 5, false),
+        ];
+        let names = |avatars: &[Avatar]| -> Vec<String> {
+            huddle(avatars, 2).iter().map(|a| a.name.clone()).collect()
+        };
+        assert_eq!(names(&avatars), vec!["me", "near", "chained"]);
+
+        // People shown at a default spot or offline do not count
+        avatars[1].present = false;
+        assert!(names(&avatars).is_empty());
+        avatars[1].present = true;
+        avatars[1].status = UserStatus::Offline;
+        assert!(names(&avatars).is_empty());
+        assert!(huddle(&avatars, 0).is_empty());
+    }
+
+    #[test]
+    fn test_knock_animation_plays_out() {
+        use std::time::Duration;
+        assert_eq!(knock_frame(Duration::ZERO), Some("[|]"));
+        assert_eq!(knock_frame(Duration::from_millis(260)), Some("[|)"));
+        assert_eq!(knock_frame(KNOCK_FRAME_TIME * 7), Some("knock knock"));
+        assert_eq!(knock_frame(KNOCK_FRAME_TIME * 8), None);
+    }
+
+    #[test]
+    fn test_parse_floor_plan_round_trips() {
+        let text = "#######\n#.d.#.#\n#...+.#\n#######\nzone 1 1 3 2 Team desks\n";
+        let map = OfficeMap::parse(text).unwrap();
+        assert_eq!((map.width, map.height), (7, 4));
+        assert_eq!(map.tile(Position::new(2, 1)), Tile::Desk);
+        assert_eq!(map.tile(Position::new(4, 2)), Tile::Door);
+        assert_eq!(map.zone_at(Position::new(3, 2)).map(|z| z.name.as_str()), Some("Team desks"));
+        assert_eq!(map.to_text(), text);
+
+        // Walls block, doors let you through
+        assert_eq!(map.step(Position::new(3, 1), Direction::Right), Position::new(3, 1));
+        assert_eq!(map.step(Position::new(3, 2), Direction::Right), Position::new(4, 2));
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_plans() {
+        assert!(OfficeMap::parse("#x#").is_err());
+        assert!(OfficeMap::parse("###\n###").is_err());
+        assert!(OfficeMap::parse("#.#\nzone 0 0 9 9 Too big").is_err());
+        assert!(OfficeMap::parse("#.#\nzone 0 0 1").is_err());
+        assert!(OfficeMap::parse("; only a comment").is_err());
+    }
+
+    #[test]
+    fn test_editor_paints_and_names_zones() {
+        let mut editor = OfficeEditor::new(OfficeMap::blank(6, 5), Position::new(1, 1));
+        editor.paint(Tile::Desk);
+        assert!(!editor.mark_zone_corner());
+        editor.move_cursor(Direction::Right);
+        editor.move_cursor(Direction::Down);
+        assert!(editor.mark_zone_corner());
+        assert!(editor.name_pending_zone("Corner"));
+        assert!(!editor.name_pending_zone("Again"));
+
+        assert_eq!(editor.draft.tile(Position::new(1, 1)), Tile::Desk);
+        assert_eq!(editor.draft.zones, vec![Zone::from_corners("Corner", Position::new(1, 1), Position::new(2, 2))]);
+        assert_eq!(editor.remove_zone_at_cursor().as_deref(), Some("Corner"));
+        assert!(editor.draft.zones.is_empty());
+    }
+}
@@ -8,6 +8,58 @@ pub struct Room {
     pub topic: Option<String>,
     pub is_encrypted: bool,
     pub member_count: usize,
+    /// Set for 1:1 rooms tracked in `m.direct`
+    pub is_direct: bool,
+    /// MXID of the other person in a DM
+    pub dm_user_id: Option<String>,
+    /// Unread notifications, as shown in the Rooms pane badge
+    pub unread_count: u64,
+    /// Unread messages that mention us
+    pub highlight_count: u64,
+    /// `m.space` rooms are shown as office floors
+    pub is_space: bool,
+    /// Matrix IDs of the rooms directly inside a space
+    pub space_children: Vec<String>,
+    /// Floor folded in the Rooms pane
+    pub collapsed: bool,
+    /// What the room is for, which sets how nok behaves inside it
+    pub kind: Option<RoomKind>,
+    /// MXIDs of the members sitting in the room right now
+    pub occupants: Vec<String>,
+    /// MXID of our account that is in the room
+    pub account: Option<String>,
+}
+
+/// Room types with their own rules, stored in a `com.nok.room.kind`
+/// state event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoomKind {
+    /// Entering sets us Busy and holds knocks until we leave
+    Focus,
+    /// Entering sets an "In a meeting" status message
+    Meeting,
+    /// Anyone in the room can be knocked on, wherever they stand
+    Lounge,
+}
+
+impl RoomKind {
+    /// Name used in the state event and the `/roomtype` command
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RoomKind::Focus => "focus",
+            RoomKind::Meeting => "meeting",
+            RoomKind::Lounge => "lounge",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<RoomKind> {
+        match name {
+            "focus" => Some(RoomKind::Focus),
+            "meeting" => Some(RoomKind::Meeting),
+            "lounge" => Some(RoomKind::Lounge),
+            _ => None,
+        }
+    }
 }
 
 impl Room {
@@ -21,6 +73,16 @@ impl Room {
             topic: None,
             is_encrypted: false,
             member_count: 0,
+            is_direct: false,
+            dm_user_id: None,
+            unread_count: 0,
+            highlight_count: 0,
+            is_space: false,
+            space_children: Vec::new(),
+            collapsed: false,
+            kind: None,
+            occupants: Vec::new(),
+            account: None,
         }
     }
 
@@ -50,6 +112,28 @@ impl Room {
         self.is_encrypted = encrypted;
     }
 
+    /// Badge such as " (3)" or " (3 @1)", empty when there is nothing unread
+    pub fn unread_badge(&self) -> String {
+        match (self.unread_count.max(self.highlight_count), self.highlight_count) {
+            (0, _) => String::new(),
+            (unread, 0) => format!(" ({})", unread),
+            (unread, highlights) => format!(" ({} @{})", unread, highlights),
+        }
+    }
+
+    /// How many people sit in the room right now, for the Rooms pane
+    pub fn occupancy_badge(&self) -> String {
+        match self.occupants.len() {
+            0 => String::new(),
+            n => format!(" ·{} here", n),
+        }
+    }
+
+    /// Whether this space lists the room with the given Matrix ID
+    pub fn contains_room(&self, matrix_id: &str) -> bool {
+        self.is_space && self.space_children.iter().any(|child| child == matrix_id)
+    }
+
     pub fn set_member_count(&mut self, count: usize) {
         self.member_count = count;
     }
@@ -81,3 +165,58 @@ impl Room {
         format!("{}{}{}", encryption_status, self.name, member_info)
     }
 }
+
+/// A room we have been invited to but not joined yet
+#[derive(Debug, Clone)]
+pub struct RoomInvite {
+    pub room_id: String,
+    pub room_name: String,
+    pub topic: Option<String>,
+    pub inviter_id: Option<String>,
+    pub inviter_name: Option<String>,
+    /// MXID of our account that was invited
+    pub account: Option<String>,
+}
+
+impl RoomInvite {
+    /// Who sent the invite, by display name when known
+    pub fn inviter(&self) -> &str {
+        self.inviter_name.as_deref()
+            .or(self.inviter_id.as_deref())
+            .unwrap_or("someone")
+    }
+}
+
+/// An entry of the homeserver's public room directory
+#[derive(Debug, Clone)]
+pub struct PublicRoom {
+    pub room_id: String,
+    pub alias: Option<String>,
+    pub name: Option<String>,
+    pub topic: Option<String>,
+    pub member_count: u64,
+}
+
+impl PublicRoom {
+    /// Name to show, falling back to the alias and then the room ID
+    pub fn display_name(&self) -> &str {
+        self.name.as_deref()
+            .or(self.alias.as_deref())
+            .unwrap_or(&self.room_id)
+    }
+
+    /// What to pass to a join: the alias when published, else the room ID
+    pub fn join_target(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.room_id)
+    }
+}
+
+/// One page of public room directory results
+#[derive(Debug, Clone, Default)]
+pub struct PublicRoomsPage {
+    pub rooms: Vec<PublicRoom>,
+    pub next_batch: Option<String>,
+    pub prev_batch: Option<String>,
+    /// Server's estimate of matching rooms over all pages
+    pub total: Option<u64>,
+}
@@ -4,4 +4,7 @@ pub enum AppState {
     Normal,
     Input,
     Settings,
+    RoomDirectory,
+    UserDirectory,
+    Profile,
 }
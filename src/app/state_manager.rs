@@ -2,6 +2,25 @@ use crate::util::{NokError, NokResult};
 use super::matrix_state::MatrixState;
 use super::legacy_state::LegacyState;
 use super::core::{AppCore, LogState};
+use super::message::Message;
+use super::office::{OfficeMap, Position, PublishedPosition};
+use super::room::{PublicRoomsPage, RoomInvite, RoomKind};
+use super::user::{User, UserStatus};
+use crate::matrix::{DeviceInfo, MatrixClient, NokOfficePositionEventContent, SecurityStatus, Verification};
+use crate::matrix::{media, timeline};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use matrix_sdk::deserialized_responses::TimelineEvent;
+use matrix_sdk::ruma::{api::client::error::ErrorKind, OwnedEventId, OwnedRoomId, OwnedUserId};
+
+/// Number of events fetched when building a room timeline
+const TIMELINE_FETCH_LIMIT: u32 = 50;
+
+/// Number of rooms per page of the public room directory
+const DIRECTORY_PAGE_SIZE: u32 = 20;
+
+/// Maximum number of matches asked from the user directory
+const USER_SEARCH_LIMIT: u64 = 20;
 
 /// Communication mode selector
 #[derive(Debug, Clone, Copy, PartialEq, serde::Serialize, serde::Deserialize)]
@@ -11,12 +30,102 @@ pub enum CommunicationMode {
     Hybrid, // Both enabled for transition period
 }
 
+/// Status message shown while we are in a meeting room
+const MEETING_STATUS_MESSAGE: &str = "In a meeting";
+
+/// How long to wait before trying again when the presence of a room type
+/// could not be set
+const ROOM_RULES_RETRY: std::time::Duration = std::time::Duration::from_secs(30);
+
 /// Unified state manager that coordinates between Matrix and Legacy systems
 #[derive(Debug)]
 pub struct StateManager {
     mode: CommunicationMode,
     matrix: MatrixState,
     legacy: LegacyState,
+    room_rules: RoomRules,
+    /// Device verification in progress, shown in the verification modal
+    verification: Option<Verification>,
+    /// Timeline of the room shown in the Messages pane
+    timeline: Option<RoomTimeline>,
+}
+
+/// Room state the sync loop saw change since the last tick, on any of our
+/// accounts, as (room, event type). The panes re-read what they show only
+/// when something they are drawn from changed.
+#[derive(Debug, Default)]
+pub struct StateChanges(Vec<(String, String)>);
+
+impl StateChanges {
+    /// Whether any of `event_types` changed in `room_id`
+    pub fn in_room(&self, room_id: Option<&str>, event_types: &[&str]) -> bool {
+        room_id.is_some_and(|room_id| self.0.iter().any(|(changed, event_type)| {
+            changed == room_id && event_types.contains(&event_type.as_str())
+        }))
+    }
+
+    /// Whether any of `event_types` changed in any room
+    pub fn anywhere(&self, event_types: &[&str]) -> bool {
+        self.0.iter().any(|(_, event_type)| event_types.contains(&event_type.as_str()))
+    }
+}
+
+/// Events of the room shown in the Messages pane: its recent history,
+/// fetched on entering it, followed by what the sync brought in since
+#[derive(Debug)]
+struct RoomTimeline {
+    room_id: OwnedRoomId,
+    events: Vec<TimelineEvent>,
+}
+
+/// The rules of the room type we are currently in
+#[derive(Debug, Default)]
+struct RoomRules {
+    kind: Option<RoomKind>,
+    /// Account in the room, whose presence `kind` was applied to
+    account: Option<String>,
+    /// Status of each account from before a focus or meeting room changed it
+    saved_status: HashMap<String, UserStatus>,
+    /// Knocks held back while we are in a focus room, as (room, sender)
+    deferred_knocks: Vec<(String, String)>,
+    /// When to try again after the presence of a room type could not be set
+    retry_at: Option<std::time::Instant>,
+}
+
+impl RoomRules {
+    /// Presence and status message of `account` for a room of `kind`, and
+    /// the status to restore once it leaves. `own_status` is our status as
+    /// shown now.
+    fn presence_for(&self, account: Option<&str>, kind: Option<RoomKind>, own_status: &UserStatus) -> (UserStatus, Option<String>, Option<UserStatus>) {
+        let saved = account.and_then(|account| self.saved_status.get(account))
+            .cloned()
+            .unwrap_or_else(|| own_status.clone());
+        let (status, message) = match kind {
+            Some(RoomKind::Focus) => (UserStatus::Busy, None),
+            Some(RoomKind::Meeting) => (saved.clone(), Some(MEETING_STATUS_MESSAGE.to_string())),
+            Some(RoomKind::Lounge) | None => (saved.clone(), None),
+        };
+        let restore = matches!(kind, Some(RoomKind::Focus | RoomKind::Meeting)).then_some(saved);
+        (status, message, restore)
+    }
+
+    /// The account of the room we were in and the status it gets back,
+    /// when the next room belongs to another `account`: the room type no
+    /// longer applies to it
+    fn handed_back(&self, account: Option<&str>) -> Option<(String, UserStatus)> {
+        let previous = self.account.as_deref().filter(|previous| Some(*previous) != account)?;
+        self.saved_status.get(previous).map(|status| (previous.to_string(), status.clone()))
+    }
+
+    /// Pass on knocks, unless we are in a focus room: then they wait, with
+    /// any held before, until we leave it
+    fn pass_knocks(&mut self, knocks: Vec<(String, String)>) -> Vec<(String, String)> {
+        self.deferred_knocks.extend(knocks);
+        if self.kind == Some(RoomKind::Focus) {
+            return Vec::new();
+        }
+        std::mem::take(&mut self.deferred_knocks)
+    }
 }
 
 impl StateManager {
@@ -25,6 +134,9 @@ impl StateManager {
             mode: CommunicationMode::Matrix, // Default to Matrix
             matrix,
             legacy,
+            room_rules: RoomRules::default(),
+            verification: None,
+            timeline: None,
         }
     }
 
@@ -58,7 +170,8 @@ impl StateManager {
         match self.mode {
             CommunicationMode::Matrix => {
                 logs.add_debug_log("Initializing Matrix-only mode".to_string());
-                self.matrix.initialize_client().await?;
+                // Each account has its own store, opened at login
+                logs.add_debug_log("Matrix store opens at login".to_string());
             }
             CommunicationMode::Legacy => {
                 logs.add_debug_log("Initializing Legacy-only mode".to_string());
@@ -66,10 +179,7 @@ impl StateManager {
             }
             CommunicationMode::Hybrid => {
                 logs.add_debug_log("Initializing Hybrid mode".to_string());
-                // Initialize both systems
-                if let Err(e) = self.matrix.initialize_client().await {
-                    logs.add_debug_log(format!("Matrix initialization failed: {}", e));
-                }
+                // Initialize both systems; the Matrix store opens at login
                 if let Err(e) = self.legacy.connect().await {
                     logs.add_debug_log(format!("Legacy connection failed: {}", e));
                 }
@@ -144,6 +254,735 @@ impl StateManager {
         }
     }
 
+    /// Load the recent timeline of a Matrix room as displayable messages.
+    /// Only done on entering a room; `update_timeline` adds what the sync
+    /// brings in after that.
+    pub async fn load_timeline(&mut self, room_id: &str, room_name: &str, own_display_name: Option<&str>) -> NokResult<Vec<Message>> {
+        let client = self.room_client(room_id)?;
+        let room_id = parse_room_id(room_id)?;
+
+        let events = client.fetch_timeline(&room_id, TIMELINE_FETCH_LIMIT).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))?;
+        let own_user_id = client.user_id().map(|id| id.to_string());
+
+        let messages = timeline::build_messages(&events, room_name, own_user_id.as_deref(), own_display_name);
+        self.timeline = Some(RoomTimeline { room_id, events });
+        Ok(messages)
+    }
+
+    /// Whether the timeline of `room_id` is loaded
+    pub fn has_timeline_of(&self, room_id: &str) -> bool {
+        self.timeline.as_ref().is_some_and(|timeline| timeline.room_id.as_str() == room_id)
+    }
+
+    /// Add the events the sync received to the loaded timeline, and decrypt
+    /// again those that failed once their room keys arrived. Returns the
+    /// rebuilt messages of `room_id` if it is the loaded one and anything
+    /// changed (Matrix only).
+    pub async fn update_timeline(&mut self, room_id: &str, room_name: &str, own_display_name: Option<&str>) -> Option<Vec<Message>> {
+        let mut received = Vec::new();
+        let mut keys_received = Vec::new();
+        for client in self.matrix.logged_in_clients() {
+            received.extend(client.take_timeline_events());
+            keys_received.extend(client.take_room_keys_received());
+        }
+
+        let timeline = self.timeline.as_mut().filter(|timeline| timeline.room_id.as_str() == room_id)?;
+        let client = self.matrix.client_for_room(&timeline.room_id)?;
+        let received = received.into_iter()
+            .filter(|(room_id, _)| *room_id == timeline.room_id)
+            .map(|(_, event)| event);
+        let mut changed = timeline::append_events(&mut timeline.events, received);
+        if keys_received.contains(&timeline.room_id) && timeline::has_undecryptable(&timeline.events) {
+            client.retry_decryption(&timeline.room_id, &mut timeline.events).await;
+            changed = true;
+        }
+        if !changed {
+            return None;
+        }
+
+        let own_user_id = client.user_id().map(|id| id.to_string());
+        Some(timeline::build_messages(&timeline.events, room_name, own_user_id.as_deref(), own_display_name))
+    }
+
+    /// Joined members of a room (Matrix only)
+    pub async fn room_members(&self, room_id: &str) -> NokResult<Vec<User>> {
+        let client = self.room_client(room_id)?;
+        client.room_members(&parse_room_id(room_id)?).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Matrix IDs of the rooms inside a space (Matrix only)
+    pub async fn space_children(&self, space_id: &str) -> NokResult<Vec<String>> {
+        let client = self.room_client(space_id)?;
+        let children = client.space_children(&parse_room_id(space_id)?).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))?;
+        Ok(children.into_iter().map(|room_id| room_id.to_string()).collect())
+    }
+
+    /// MXID of the other person if the room is a DM (Matrix only)
+    pub fn dm_partner(&self, room_id: &str) -> Option<String> {
+        let client = self.room_client(room_id).ok()?;
+        client.dm_partner(&parse_room_id(room_id).ok()?).map(|user_id| user_id.to_string())
+    }
+
+    /// Open the DM room with a user, creating it if needed (Matrix only).
+    /// Returns the room ID.
+    pub async fn open_dm(&self, user_id: &str, logs: &mut LogState) -> NokResult<String> {
+        let client = self.user_client(user_id).await?;
+        let user_id = parse_user_id(user_id)?;
+        let (room_id, created) = client.find_or_create_dm(&user_id).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))?;
+
+        if created {
+            logs.add_debug_log(format!("Created DM room {} with {}", room_id, user_id));
+        } else {
+            logs.add_debug_log(format!("Found DM room {} with {}", room_id, user_id));
+        }
+        Ok(room_id.to_string())
+    }
+
+    /// Create a room (Matrix only). Returns the new room ID.
+    pub async fn create_room(&self, name: &str, alias: Option<&str>, private: bool, encrypted: bool, logs: &mut LogState) -> NokResult<String> {
+        let client = self.active_client()?;
+        logs.add_debug_log(format!(
+            "Creating Matrix room '{}' (alias: {:?}, private: {}, encrypted: {})",
+            name, alias, private, encrypted
+        ));
+        let room_id = client.create_room(name, alias, private, encrypted).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))?;
+        logs.add_debug_log(format!("Created room {}", room_id));
+        Ok(room_id.to_string())
+    }
+
+    /// Join a room by ID or alias (Matrix only). Returns the room ID.
+    pub async fn join_room(&self, room_id_or_alias: &str, logs: &mut LogState) -> NokResult<String> {
+        // Join from an account on the room's server when we have one
+        let client = self.active_client()?;
+        let client = self.matrix.client_for_server(room_id_or_alias).unwrap_or(client);
+        logs.add_debug_log(format!("Joining Matrix room {}", room_id_or_alias));
+        let room = client.join_room(room_id_or_alias).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))?;
+        Ok(room.room_id().to_string())
+    }
+
+    /// Leave a room (Matrix only)
+    pub async fn leave_room(&self, room_id: &str, logs: &mut LogState) -> NokResult<()> {
+        let client = self.room_client(room_id)?;
+        logs.add_debug_log(format!("Leaving Matrix room {}", room_id));
+        client.leave_room(&parse_room_id(room_id)?).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Invite a user into a room (Matrix only)
+    pub async fn invite_user(&self, room_id: &str, user_id: &str, logs: &mut LogState) -> NokResult<()> {
+        let client = self.room_client(room_id)?;
+        let user_id = parse_user_id(user_id)?;
+        logs.add_debug_log(format!("Inviting {} to room {}", user_id, room_id));
+        client.invite_user(&parse_room_id(room_id)?, &user_id).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Kick a member out of a room (Matrix only)
+    pub async fn kick_user(&self, room_id: &str, user_id: &str, reason: Option<&str>, logs: &mut LogState) -> NokResult<()> {
+        let client = self.room_client(room_id)?;
+        let user_id = parse_user_id(user_id)?;
+        logs.add_debug_log(format!("Kicking {} from room {}", user_id, room_id));
+        client.kick_user(&parse_room_id(room_id)?, &user_id, reason).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Set the topic of a room (Matrix only)
+    pub async fn set_topic(&self, room_id: &str, topic: &str, logs: &mut LogState) -> NokResult<()> {
+        let client = self.room_client(room_id)?;
+        logs.add_debug_log(format!("Setting topic of room {}", room_id));
+        client.set_topic(&parse_room_id(room_id)?, topic).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// One page of the homeserver's public room directory, filtered by a
+    /// search term (Matrix only)
+    pub async fn public_rooms(&self, search: &str, since: Option<&str>, logs: &mut LogState) -> NokResult<PublicRoomsPage> {
+        let client = self.active_client()?;
+        let search = Some(search.trim()).filter(|term| !term.is_empty());
+        logs.add_debug_log(format!("Searching room directory for {:?}", search));
+        client.public_rooms(search, since, DIRECTORY_PAGE_SIZE).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Search the homeserver's user directory (Matrix only). Returns the
+    /// matches and whether more were available.
+    pub async fn search_users(&self, query: &str, logs: &mut LogState) -> NokResult<(Vec<User>, bool)> {
+        let client = self.active_client()?;
+        logs.add_debug_log(format!("Searching user directory for '{}'", query));
+        client.search_users(query, USER_SEARCH_LIMIT).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Office map positions published in a room, by MXID (Matrix only)
+    pub async fn office_positions(&self, room_id: &str) -> NokResult<Vec<(String, PublishedPosition)>> {
+        let client = self.room_client(room_id)?;
+        let positions = client.office_positions(&parse_room_id(room_id)?).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))?;
+        Ok(positions.into_iter()
+            .map(|(user_id, content)| (user_id.to_string(), PublishedPosition {
+                position: Position::new(content.x, content.y),
+                huddle: content.huddle.map(|root| root.to_string()),
+            }))
+            .collect())
+    }
+
+    /// Publish our position on a room's office map, with the thread of
+    /// the huddle we are in (Matrix only)
+    pub async fn publish_position(&self, room_id: &str, published: &PublishedPosition, logs: &mut LogState) -> NokResult<()> {
+        let client = self.room_client(room_id)?;
+        let PublishedPosition { position, huddle } = published;
+        logs.add_debug_log(format!("Publishing office position ({}, {}) in {}", position.x, position.y, room_id));
+        let huddle = huddle.as_deref().map(parse_event_id).transpose()?;
+        let content = NokOfficePositionEventContent::new(position.x, position.y, huddle);
+        client.set_office_position(&parse_room_id(room_id)?, content).await
+            .map_err(|e| state_event_error(e, "This room does not let members share their office position"))
+    }
+
+    /// Whether we may publish our office position in a room (Matrix only)
+    pub async fn can_publish_position(&self, room_id: &str) -> NokResult<bool> {
+        let client = self.room_client(room_id)?;
+        client.can_set_office_position(&parse_room_id(room_id)?).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Knock on someone in a room, e.g. the office they share with us
+    /// (Matrix only)
+    pub async fn knock_in_room(&self, room_id: &str, target_user_id: &str, logs: &mut LogState) -> NokResult<()> {
+        let client = self.room_client(room_id)?;
+        logs.add_debug_log(format!("Sending Matrix knock to {} in {}", target_user_id, room_id));
+        client.send_knock(&parse_room_id(room_id)?, &parse_user_id(target_user_id)?).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Whether a knock on a user has a room to go to, i.e. we share one
+    /// with them (Matrix only)
+    pub async fn can_knock(&self, user_id: &str) -> NokResult<bool> {
+        let client = self.user_client(user_id).await?;
+        Ok(client.shared_room_with(&parse_user_id(user_id)?).await.is_some())
+    }
+
+    /// Knocks on us that arrived since the last call, as (room ID, sender
+    /// MXID), on any of our accounts. While we are in a focus room they
+    /// are held back, and handed out once we leave. Empty outside Matrix
+    /// mode.
+    pub fn take_knocks(&mut self) -> Vec<(String, String)> {
+        if self.active_client().is_err() {
+            return Vec::new();
+        }
+        let knocks: Vec<(String, String)> = self.matrix.logged_in_clients()
+            .flat_map(|client| client.take_knocks())
+            .map(|(room_id, sender)| (room_id.to_string(), sender.to_string()))
+            .collect();
+        self.room_rules.pass_knocks(knocks)
+    }
+
+    /// Knocks waiting for us to leave a focus room
+    pub fn deferred_knock_count(&self) -> usize {
+        self.room_rules.deferred_knocks.len()
+    }
+
+    /// How far away someone may stand and still be knocked on from the
+    /// office map: next to us, or anywhere in a lounge
+    pub fn knock_reach(&self) -> u16 {
+        match self.room_rules.kind {
+            Some(RoomKind::Lounge) => u16::MAX,
+            _ => 1,
+        }
+    }
+
+    /// Apply the rules of the type of `room_id`, the room we are now in:
+    /// a focus room sets the account in it Busy (and holds knocks), a
+    /// meeting room adds an "In a meeting" status message, and anywhere
+    /// else gets back the status the account had before. Moving to a room
+    /// of another account gives the previous one its status back.
+    /// `own_status` is our status as currently shown. Returns our new
+    /// status when the room type changed. The rules only change once the
+    /// homeserver took the new presence; after a failure they are tried
+    /// again a while later.
+    pub async fn apply_room_kind(&mut self, room_id: Option<&str>, kind: Option<RoomKind>, own_status: &UserStatus, logs: &mut LogState) -> NokResult<Option<UserStatus>> {
+        let client = match room_id {
+            Some(room_id) => self.room_client(room_id),
+            None => self.active_client(),
+        };
+        let account = client.ok().and_then(|client| client.user_id()).map(|id| id.to_string());
+        if kind == self.room_rules.kind && account == self.room_rules.account {
+            self.room_rules.retry_at = None;
+            return Ok(None);
+        }
+        if self.room_rules.retry_at.is_some_and(|at| std::time::Instant::now() < at) {
+            return Ok(None);
+        }
+        let handed_back = self.room_rules.handed_back(account.as_deref());
+        let (status, message, restore) = self.room_rules.presence_for(account.as_deref(), kind, own_status);
+
+        logs.add_debug_log(format!("Entering {} room: presence {:?}, message {:?}",
+            kind.map_or("ordinary", RoomKind::as_str), status, message));
+        let sent = self.send_room_presence(handed_back.as_ref(), account.as_deref(), &status, message).await;
+        if let Err(e) = sent {
+            self.room_rules.retry_at = Some(std::time::Instant::now() + ROOM_RULES_RETRY);
+            return Err(e);
+        }
+
+        if let Some((previous, _)) = handed_back {
+            self.room_rules.saved_status.remove(&previous);
+        }
+        if let Some(account) = &account {
+            match restore {
+                Some(saved) => self.room_rules.saved_status.insert(account.clone(), saved),
+                None => self.room_rules.saved_status.remove(account),
+            };
+        }
+        self.room_rules.kind = kind;
+        self.room_rules.account = account;
+        self.room_rules.retry_at = None;
+        Ok(Some(status))
+    }
+
+    /// Set the presence of a room type on `account`, after giving the
+    /// account of the previous room its status back. An account logged
+    /// out since has nothing to give back.
+    async fn send_room_presence(&self, handed_back: Option<&(String, UserStatus)>, account: Option<&str>, status: &UserStatus, message: Option<String>) -> NokResult<()> {
+        if let Some((previous, saved)) = handed_back {
+            if let Some(client) = self.matrix.client_for_account(previous) {
+                client.set_presence(saved.clone(), None).await
+                    .map_err(|e| NokError::MatrixSyncError(e.to_string()))?;
+            }
+        }
+        let client = account.and_then(|account| self.matrix.client_for_account(account))
+            .ok_or(NokError::MatrixClientNotInitialized)?;
+        client.set_presence(status.clone(), message).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// The type set on a room, if any (Matrix only)
+    pub async fn room_kind(&self, room_id: &str) -> NokResult<Option<RoomKind>> {
+        let client = self.room_client(room_id)?;
+        let kind = client.room_kind(&parse_room_id(room_id)?).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))?;
+        Ok(kind.as_deref().and_then(RoomKind::parse))
+    }
+
+    /// Set or clear a room's type (Matrix only)
+    pub async fn set_room_kind(&self, room_id: &str, kind: Option<RoomKind>, logs: &mut LogState) -> NokResult<()> {
+        let client = self.room_client(room_id)?;
+        logs.add_debug_log(format!("Setting room type of {} to {:?}", room_id, kind));
+        client.set_room_kind(&parse_room_id(room_id)?, kind.map(RoomKind::as_str)).await
+            .map_err(|e| state_event_error(e, "Only room moderators can change the room type"))
+    }
+
+    /// Start a huddle's thread in a room, returning its root event ID
+    /// (Matrix only)
+    pub async fn start_huddle_thread(&self, room_id: &str, notice: &str, logs: &mut LogState) -> NokResult<String> {
+        let client = self.room_client(room_id)?;
+        logs.add_debug_log(format!("Starting huddle thread in {}", room_id));
+        client.start_huddle_thread(&parse_room_id(room_id)?, notice).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))?
+            .map(|event_id| event_id.to_string())
+            .ok_or_else(|| NokError::InternalError(format!("Room {} is not known to the client", room_id)))
+    }
+
+    /// A room's office floor plan, if one was saved (Matrix only)
+    pub async fn office_plan(&self, room_id: &str) -> NokResult<Option<OfficeMap>> {
+        let client = self.room_client(room_id)?;
+        let plan = client.office_plan(&parse_room_id(room_id)?).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))?;
+        plan.map(|text| OfficeMap::parse(&text)).transpose()
+    }
+
+    /// Save a floor plan as the room's office layout (Matrix only)
+    pub async fn publish_office_plan(&self, room_id: &str, map: &OfficeMap, logs: &mut LogState) -> NokResult<()> {
+        let client = self.room_client(room_id)?;
+        logs.add_debug_log(format!("Saving {}x{} floor plan in {}", map.width, map.height, room_id));
+        client.set_office_plan(&parse_room_id(room_id)?, map.to_text()).await
+            .map_err(|e| state_event_error(e, "Only room moderators can change the floor plan"))
+    }
+
+    /// Desks claimed in a room's office, by MXID (Matrix only)
+    pub async fn office_desks(&self, room_id: &str) -> NokResult<Vec<(String, Position)>> {
+        let client = self.room_client(room_id)?;
+        let desks = client.office_desks(&parse_room_id(room_id)?).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))?;
+        Ok(desks.into_iter()
+            .map(|(user_id, x, y)| (user_id.to_string(), Position::new(x, y)))
+            .collect())
+    }
+
+    /// Claim a desk in a room's office, or give ours up (Matrix only)
+    pub async fn claim_desk(&self, room_id: &str, desk: Option<Position>, logs: &mut LogState) -> NokResult<()> {
+        let client = self.room_client(room_id)?;
+        logs.add_debug_log(format!("Setting desk in {} to {:?}", room_id, desk));
+        client.set_office_desk(&parse_room_id(room_id)?, desk.map(|d| (d.x, d.y))).await
+            .map_err(|e| state_event_error(e, "This room does not let members claim desks"))
+    }
+
+    /// The verification in progress, if any
+    pub fn verification(&self) -> Option<&Verification> {
+        self.verification.as_ref()
+    }
+
+    /// Ask to verify a user, or one of their devices (Matrix only)
+    pub async fn start_verification(&mut self, user_id: &str, device_id: Option<&str>, logs: &mut LogState) -> NokResult<()> {
+        if self.verification.as_ref().is_some_and(|v| !v.is_finished()) {
+            return Err(NokError::InvalidInput("A verification is already in progress".to_string()));
+        }
+        let client = self.user_client(user_id).await?;
+        logs.add_debug_log(format!("Requesting verification of {} (device {:?})", user_id, device_id));
+        let verification = client.request_verification(&parse_user_id(user_id)?, device_id).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))?;
+        match verification {
+            Some(verification) => {
+                self.verification = Some(verification);
+                Ok(())
+            }
+            None if device_id.is_some() => Err(NokError::InvalidInput(format!("{} has no device with that ID", user_id))),
+            None => Err(NokError::InvalidInput(format!("{} has not set up cross-signing, so cannot be verified", user_id))),
+        }
+    }
+
+    /// Pick up verification requests sent to us and move the current one
+    /// along. A request arriving while another verification is running
+    /// is cancelled.
+    pub async fn poll_verification(&mut self, logs: &mut LogState) -> NokResult<()> {
+        if self.active_client().is_err() {
+            return Ok(());
+        }
+        let mut requests = Vec::new();
+        for client in self.matrix.logged_in_clients() {
+            requests.extend(client.take_verification_requests().await);
+        }
+        for incoming in requests {
+            if self.verification.as_ref().is_some_and(|v| !v.is_finished()) {
+                logs.add_debug_log(format!("Declining verification from {}: another one is running", incoming.other_user_id()));
+                incoming.cancel().await.map_err(|e| NokError::MatrixSyncError(e.to_string()))?;
+            } else {
+                logs.add_debug_log(format!("Verification requested by {}", incoming.other_user_id()));
+                self.verification = Some(incoming);
+            }
+        }
+        if let Some(verification) = self.verification.as_mut() {
+            verification.advance().await.map_err(|e| NokError::MatrixSyncError(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Accept the verification request sent to us
+    pub async fn accept_verification(&self) -> NokResult<()> {
+        let Some(verification) = &self.verification else {
+            return Ok(());
+        };
+        verification.accept().await.map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Answer whether the emojis on both devices match
+    pub async fn confirm_verification(&self, matches: bool) -> NokResult<()> {
+        let Some(verification) = &self.verification else {
+            return Ok(());
+        };
+        let result = if matches { verification.confirm().await } else { verification.mismatch().await };
+        result.map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Cancel the verification in progress, or close a finished one
+    pub async fn cancel_verification(&mut self) -> NokResult<()> {
+        let Some(verification) = self.verification.take() else {
+            return Ok(());
+        };
+        if verification.is_finished() {
+            return Ok(());
+        }
+        verification.cancel().await.map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Whether we verified a user: `None` when they have no cross-signing
+    /// identity to verify (Matrix only)
+    pub async fn is_user_verified(&self, user_id: &str) -> NokResult<Option<bool>> {
+        let client = self.user_client(user_id).await?;
+        client.is_user_verified(&parse_user_id(user_id)?).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Where cross-signing and key backup stand for our account (Matrix only)
+    pub async fn security_status(&self) -> NokResult<SecurityStatus> {
+        let client = self.active_client()?;
+        client.recovery().status().await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Bootstrap cross-signing and turn on key backup, returning the new
+    /// recovery key. `password` is the account password the homeserver
+    /// asks for before accepting cross-signing keys (Matrix only).
+    pub async fn set_up_recovery(&self, password: &str, passphrase: Option<&str>, logs: &mut LogState) -> NokResult<String> {
+        let client = self.active_client()?;
+        let status = self.security_status().await?;
+        if let Some(reason) = status.set_up_refusal() {
+            return Err(NokError::InvalidInput(reason.to_string()));
+        }
+        logs.add_debug_log("Setting up cross-signing and key backup".to_string());
+        client.recovery().set_up(password, passphrase).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Restore cross-signing and room keys with the recovery key or its
+    /// passphrase (Matrix only)
+    pub async fn restore_recovery(&self, recovery_key: &str, logs: &mut LogState) -> NokResult<()> {
+        let client = self.active_client()?;
+        logs.add_debug_log("Restoring keys from backup".to_string());
+        client.recovery().restore(recovery_key).await
+            .map_err(|e| NokError::InvalidInput(format!("Could not restore from the recovery key: {}", e)))
+    }
+
+    /// Log the active account out of Matrix and forget everything about
+    /// its session. It first leaves `location_room`, the room we sit in,
+    /// so nobody keeps seeing it there. The local store is deleted with
+    /// `wipe_store`; otherwise it keeps the room state and cached
+    /// messages. Other accounts stay logged in.
+    pub async fn logout(&mut self, wipe_store: bool, location_room: Option<&str>, logs: &mut LogState) -> NokResult<String> {
+        logs.add_debug_log(format!("Logging out ({} the store)", if wipe_store { "wiping" } else { "keeping" }));
+        let location_room = location_room.and_then(|room_id| parse_room_id(room_id).ok());
+        if let (Some(room_id), Ok(client)) = (location_room, self.active_client()) {
+            // Rooms the account is not in are left alone
+            if let Err(e) = client.set_location(&room_id, false).await {
+                logs.add_debug_log(format!("Could not leave {} before logging out: {}", room_id, e));
+            }
+        }
+        let (user_id, store_path) = self.matrix.logout().await?;
+        self.forget_account(&user_id);
+        // We are logged out either way; a store left behind only costs space
+        if let Err(e) = MatrixState::clear_store(&store_path, wipe_store) {
+            logs.add_debug_log(format!("Failed to clean up the Matrix store: {}", e));
+        }
+        Ok(user_id)
+    }
+
+    /// Drop what belonged to an account that logged out: its room rules,
+    /// and the verification and timeline it was showing. Once no account
+    /// is left, nothing per room carries over to the next login.
+    fn forget_account(&mut self, user_id: &str) {
+        self.verification = None;
+        self.timeline = None;
+        if self.matrix.logged_in_clients().next().is_none() {
+            self.room_rules = RoomRules::default();
+            return;
+        }
+        self.room_rules.saved_status.remove(user_id);
+        // The room's account applies the rules again on the next tick
+        if self.room_rules.account.as_deref() == Some(user_id) {
+            self.room_rules.kind = None;
+            self.room_rules.account = None;
+        }
+    }
+
+    /// Our own devices, this one first (Matrix only)
+    pub async fn list_devices(&self) -> NokResult<Vec<DeviceInfo>> {
+        let client = self.active_client()?;
+        client.devices().list().await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Change the name other users and the device list show for one of
+    /// our devices (Matrix only)
+    pub async fn rename_device(&self, device_id: &str, display_name: &str, logs: &mut LogState) -> NokResult<()> {
+        let client = self.active_client()?;
+        let display_name = display_name.trim();
+        if display_name.is_empty() {
+            return Err(NokError::InvalidInput("Device name cannot be empty".to_string()));
+        }
+        logs.add_debug_log(format!("Renaming device {} to {}", device_id, display_name));
+        client.devices().rename(device_id, display_name).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Log other devices out, confirming with the account password
+    /// (Matrix only)
+    pub async fn log_out_devices(&self, device_ids: &[String], password: &str, logs: &mut LogState) -> NokResult<()> {
+        let client = self.active_client()?;
+        let current = client.device_id();
+        if device_ids.iter().any(|id| Some(id) == current.as_ref()) {
+            return Err(NokError::InvalidInput("This device cannot log itself out from the device list".to_string()));
+        }
+        logs.add_debug_log(format!("Logging out devices: {}", device_ids.join(", ")));
+        client.devices().log_out(device_ids, password).await
+            .map_err(|e| NokError::AuthenticationFailed(format!("Could not log the devices out: {}", e)))
+    }
+
+    /// Whether a room is end-to-end encrypted (Matrix only)
+    pub async fn is_room_encrypted(&self, room_id: &str) -> NokResult<bool> {
+        let client = self.room_client(room_id)?;
+        client.is_room_encrypted(&parse_room_id(room_id)?).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Room state changed since the last call, on any of our accounts
+    /// (Matrix only)
+    pub fn take_state_changes(&self) -> StateChanges {
+        if self.active_client().is_err() {
+            return StateChanges::default();
+        }
+        StateChanges(self.matrix.logged_in_clients()
+            .flat_map(|client| client.take_state_changes())
+            .map(|(room_id, event_type)| (room_id.to_string(), event_type))
+            .collect())
+    }
+
+    /// Rooms where encryption was turned on since the last call, on any
+    /// of our accounts (Matrix only)
+    pub fn take_encryption_changes(&self) -> Vec<String> {
+        if self.active_client().is_err() {
+            return Vec::new();
+        }
+        self.matrix.logged_in_clients()
+            .flat_map(|client| client.take_encryption_changes())
+            .map(|room_id| room_id.to_string())
+            .collect()
+    }
+
+    /// Turn on end-to-end encryption in a room for good (Matrix only)
+    pub async fn enable_encryption(&self, room_id: &str, logs: &mut LogState) -> NokResult<()> {
+        let client = self.room_client(room_id)?;
+        logs.add_debug_log(format!("Enabling encryption in {}", room_id));
+        client.enable_encryption(&parse_room_id(room_id)?).await
+            .map_err(|e| state_event_error(e, "Only room admins can turn on encryption"))
+    }
+
+    /// Members sitting in a room right now, as MXIDs (Matrix only)
+    pub async fn room_occupants(&self, room_id: &str) -> NokResult<Vec<String>> {
+        let client = self.room_client(room_id)?;
+        let occupants = client.room_occupants(&parse_room_id(room_id)?).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))?;
+        Ok(occupants.into_iter().map(|user_id| user_id.to_string()).collect())
+    }
+
+    /// Tell a room's members whether we are sitting in it (Matrix only)
+    pub async fn set_location(&self, room_id: &str, present: bool, logs: &mut LogState) -> NokResult<()> {
+        let client = self.room_client(room_id)?;
+        logs.add_debug_log(format!("Location in {}: {}", room_id, if present { "here" } else { "away" }));
+        client.set_location(&parse_room_id(room_id)?, present).await
+            .map_err(|e| state_event_error(e, "This room does not share who is sitting in it"))
+    }
+
+    /// Rooms any of our accounts has been invited to (Matrix only)
+    pub async fn pending_invites(&self) -> NokResult<Vec<RoomInvite>> {
+        if self.matrix.logged_in_clients().next().is_none() {
+            return Err(NokError::MatrixClientNotInitialized);
+        }
+        let mut invites = Vec::new();
+        for client in self.matrix.logged_in_clients() {
+            invites.extend(client.pending_invites().await);
+        }
+        Ok(invites)
+    }
+
+    /// Accept an invite by joining the room (Matrix only)
+    pub async fn accept_invite(&self, room_id: &str, logs: &mut LogState) -> NokResult<()> {
+        let client = self.room_client(room_id)?;
+        logs.add_debug_log(format!("Accepting invite to {}", room_id));
+        client.join_room(room_id).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Decline an invite by leaving the room (Matrix only)
+    pub async fn decline_invite(&self, room_id: &str, logs: &mut LogState) -> NokResult<()> {
+        let client = self.room_client(room_id)?;
+        logs.add_debug_log(format!("Declining invite to {}", room_id));
+        client.leave_room(&parse_room_id(room_id)?).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Unread notification and highlight counts of a room (Matrix only)
+    pub fn room_unread_counts(&self, room_id: &str) -> NokResult<(u64, u64)> {
+        let client = self.room_client(room_id)?;
+        Ok(client.unread_counts(&parse_room_id(room_id)?))
+    }
+
+    /// Send a read receipt up to `event_id`, clearing the room's badges (Matrix only)
+    pub async fn mark_read(&self, room_id: &str, event_id: &str) -> NokResult<()> {
+        let client = self.room_client(room_id)?;
+        client.mark_read(&parse_room_id(room_id)?, &parse_event_id(event_id)?).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Reply to a message (Matrix only)
+    pub async fn send_reply(&self, room_id: &str, in_reply_to: &str, message: &str, logs: &mut LogState) -> NokResult<()> {
+        let client = self.room_client(room_id)?;
+        logs.add_debug_log(format!("Sending Matrix reply to {} in room {}", in_reply_to, room_id));
+        client.send_reply(&parse_room_id(room_id)?, &parse_event_id(in_reply_to)?, message).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Send a message into a thread (Matrix only)
+    pub async fn send_thread_message(&self, room_id: &str, thread_root: &str, latest_event: &str, message: &str, logs: &mut LogState) -> NokResult<()> {
+        let client = self.room_client(room_id)?;
+        logs.add_debug_log(format!("Sending Matrix thread message under {} in room {}", thread_root, room_id));
+        client.send_thread_message(&parse_room_id(room_id)?, &parse_event_id(thread_root)?, &parse_event_id(latest_event)?, message).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Upload a local file into a room (Matrix only). Returns the attachment
+    /// kind it was sent as ("image", "audio", "video" or "file").
+    pub async fn upload_file(&self, room_id: &str, path: &Path, logs: &mut LogState) -> NokResult<&'static str> {
+        let client = self.room_client(room_id)?;
+        if !path.is_file() {
+            return Err(NokError::FileNotFound(path.display().to_string()));
+        }
+        let data = std::fs::read(path)?;
+        let content_type = media::sniff_mime(&data, path);
+        let filename = path.file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("file")
+            .to_string();
+
+        logs.add_debug_log(format!("Uploading {} ({}, {} bytes) to room {}", filename, content_type, data.len(), room_id));
+        client.send_attachment(&parse_room_id(room_id)?, &filename, &content_type, data).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))?;
+        Ok(media::attachment_kind(&content_type))
+    }
+
+    /// Download the media of a message into `dir` (Matrix only).
+    /// Returns the path the file was written to.
+    pub async fn save_attachment(&self, room_id: &str, event_id: &str, dir: &Path, logs: &mut LogState) -> NokResult<PathBuf> {
+        let client = self.room_client(room_id)?;
+        let (filename, data) = client.download_attachment(&parse_room_id(room_id)?, &parse_event_id(event_id)?).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))?
+            .ok_or_else(|| NokError::InvalidInput("Message has no attachment".to_string()))?;
+
+        std::fs::create_dir_all(dir)?;
+        let path = media::unique_download_path(dir, &filename);
+        std::fs::write(&path, data)?;
+        logs.add_debug_log(format!("Saved attachment {} to {}", event_id, path.display()));
+        Ok(path)
+    }
+
+    /// Edit one of our own messages (Matrix only)
+    pub async fn edit_message(&self, room_id: &str, event_id: &str, new_content: &str, logs: &mut LogState) -> NokResult<()> {
+        let client = self.room_client(room_id)?;
+        logs.add_debug_log(format!("Editing Matrix message {} in room {}", event_id, room_id));
+        client.edit_message(&parse_room_id(room_id)?, &parse_event_id(event_id)?, new_content).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// Redact a message or a reaction (Matrix only)
+    pub async fn redact_event(&self, room_id: &str, event_id: &str, logs: &mut LogState) -> NokResult<()> {
+        let client = self.room_client(room_id)?;
+        logs.add_debug_log(format!("Redacting Matrix event {} in room {}", event_id, room_id));
+        client.redact_event(&parse_room_id(room_id)?, &parse_event_id(event_id)?, None).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
+    /// React to a message with an emoji (Matrix only)
+    pub async fn send_reaction(&self, room_id: &str, event_id: &str, key: &str, logs: &mut LogState) -> NokResult<()> {
+        let client = self.room_client(room_id)?;
+        logs.add_debug_log(format!("Reacting {} to Matrix event {}", key, event_id));
+        client.send_reaction(&parse_room_id(room_id)?, &parse_event_id(event_id)?, key).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
+    }
+
     /// Set user presence using the appropriate protocol
     pub async fn set_presence(&self, status: &str, logs: &mut LogState) -> NokResult<()> {
         match self.mode {
@@ -251,27 +1090,50 @@ impl StateManager {
 
     // Private helper methods for protocol-specific operations
 
-    async fn send_matrix_knock(&self, target_user_id: &str) -> NokResult<()> {
-        if let Some(client) = self.matrix.get_client() {
-            // For now, send knock as a message to the main room
-            // In a full implementation, this would be a custom Matrix event
-            let rooms = client.rooms();
-            if let Some(room) = rooms.first() {
-                let room_id = room.room_id().to_owned();
-                let message = format!("🚪 *knock knock* for {}", target_user_id);
-                client.send_message(&room_id, &message).await
-                    .map_err(|e| NokError::MatrixSyncError(e.to_string()))?;
-                Ok(())
-            } else {
-                Err(NokError::InternalError("No rooms available for knock".to_string()))
-            }
-        } else {
-            Err(NokError::MatrixClientNotInitialized)
+    /// Client of the active account, for features that have no legacy
+    /// equivalent and belong to the account we act as rather than to a
+    /// room or user: its homeserver's directories, new rooms, its own
+    /// devices and keys
+    fn active_client(&self) -> NokResult<&MatrixClient> {
+        if self.mode == CommunicationMode::Legacy {
+            return Err(NokError::NotImplemented("This feature requires Matrix mode".to_string()));
         }
+        match self.matrix.get_client() {
+            Some(client) if self.matrix.is_enabled() && self.matrix.is_logged_in() => Ok(client),
+            _ => Err(NokError::MatrixClientNotInitialized),
+        }
+    }
+
+    /// Client of the account that is in a room, falling back to the
+    /// active account for rooms none of them knows yet
+    fn room_client(&self, room_id: &str) -> NokResult<&MatrixClient> {
+        let client = self.active_client()?;
+        let Ok(room_id) = parse_room_id(room_id) else {
+            return Ok(client);
+        };
+        Ok(self.matrix.client_for_room(&room_id).unwrap_or(client))
+    }
+
+    /// Client of the account to reach a user from, falling back to the
+    /// active account for people none of them knows
+    async fn user_client(&self, user_id: &str) -> NokResult<&MatrixClient> {
+        let client = self.active_client()?;
+        Ok(self.matrix.client_for_user(&parse_user_id(user_id)?).await.unwrap_or(client))
+    }
+
+    /// Knock in our DM or another room shared with the user; a knock
+    /// never creates a room by itself
+    async fn send_matrix_knock(&self, target_user_id: &str) -> NokResult<()> {
+        let client = self.user_client(target_user_id).await?;
+        let target_user_id = parse_user_id(target_user_id)?;
+        let room_id = client.shared_room_with(&target_user_id).await
+            .ok_or_else(|| NokError::InvalidInput(format!("You share no room with {}", target_user_id)))?;
+        client.send_knock(&room_id, &target_user_id).await
+            .map_err(|e| NokError::MatrixSyncError(e.to_string()))
     }
 
     async fn send_matrix_message(&self, room_id: &str, message: &str) -> NokResult<()> {
-        if let Some(client) = self.matrix.get_client() {
+        if let Ok(client) = self.room_client(room_id) {
             use matrix_sdk::ruma::OwnedRoomId;
             
             // Parse room ID
@@ -287,21 +1149,53 @@ impl StateManager {
         }
     }
 
-    async fn set_matrix_presence(&self, _status: &str) -> NokResult<()> {
-        if let Some(_client) = self.matrix.get_client() {
-            // Matrix presence setting is complex and requires server support
-            // For now, just log the status change as a placeholder
-            // In a full implementation, this would use the Matrix SDK's presence API
-            
-            // TODO: Implement actual Matrix presence setting once Matrix SDK supports it
-            // The Matrix spec supports presence, but many homeservers don't implement it
-            Ok(())
-        } else {
-            Err(NokError::MatrixClientNotInitialized)
+    /// Set the same presence on every account; they are all us
+    async fn set_matrix_presence(&self, status: &str) -> NokResult<()> {
+        if self.matrix.logged_in_clients().next().is_none() {
+            return Err(NokError::MatrixClientNotInitialized);
+        }
+        let status = match status {
+            "online" => UserStatus::Online,
+            "away" => UserStatus::Away,
+            "busy" => UserStatus::Busy,
+            "offline" => UserStatus::Offline,
+            _ => return Err(NokError::InvalidInput(format!(
+                "Unknown status '{}' (use online, away, busy or offline)", status
+            ))),
+        };
+        for client in self.matrix.logged_in_clients() {
+            client.set_presence(status.clone(), None).await
+                .map_err(|e| NokError::MatrixSyncError(e.to_string()))?;
         }
+        Ok(())
     }
 }
 
+fn parse_room_id(room_id: &str) -> NokResult<OwnedRoomId> {
+    room_id.try_into()
+        .map_err(|_| NokError::InternalError(format!("Invalid room ID: {}", room_id)))
+}
+
+fn parse_event_id(event_id: &str) -> NokResult<OwnedEventId> {
+    event_id.try_into()
+        .map_err(|_| NokError::InternalError(format!("Invalid event ID: {}", event_id)))
+}
+
+// A server refusing a state event we are not allowed to send is
+// reported as `PermissionDenied` with `message`
+fn state_event_error(e: matrix_sdk::Error, message: &str) -> NokError {
+    match e.client_api_error_kind() {
+        Some(ErrorKind::Forbidden { .. }) => NokError::PermissionDenied(message.to_string()),
+        _ => NokError::MatrixSyncError(e.to_string()),
+    }
+}
+
+// User IDs are typed by the user, so a bad one is an input error
+fn parse_user_id(user_id: &str) -> NokResult<OwnedUserId> {
+    user_id.try_into()
+        .map_err(|_| NokError::InvalidInput(format!("Invalid Matrix user ID: {}", user_id)))
+}
+
 use super::core::ConnectionStatus;
 
 /// Migration utilities for transitioning between modes
@@ -358,7 +1252,7 @@ impl ModeTransition {
     ) -> Result<(), String> {
         match (current, target) {
             (CommunicationMode::Legacy, CommunicationMode::Matrix) => {
-                if !matrix_state.is_initialized() {
+                if matrix_state.get_client().is_none() {
                     return Err("Matrix client not initialized".to_string());
                 }
                 if !matrix_state.is_logged_in() {
@@ -378,4 +1272,132 @@ impl ModeTransition {
         
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{ApiClient, WebSocketClient};
+    use crate::matrix::{MatrixConfig, OFFICE_STATE_EVENTS};
+
+    fn knock(sender: &str) -> (String, String) {
+        ("!room:example.org".to_string(), sender.to_string())
+    }
+
+    fn state_manager() -> StateManager {
+        let legacy = LegacyState::new(ApiClient::new(), WebSocketClient::new());
+        StateManager::new(MatrixState::new(MatrixConfig::default()), legacy)
+    }
+
+    #[test]
+    fn test_focus_room_holds_knocks_until_we_leave() {
+        let mut rules = RoomRules { kind: Some(RoomKind::Focus), ..RoomRules::default() };
+        assert!(rules.pass_knocks(vec![knock("@bob:example.org")]).is_empty());
+        assert!(rules.pass_knocks(vec![knock("@carol:example.org")]).is_empty());
+        assert_eq!(rules.deferred_knocks.len(), 2);
+
+        rules.kind = Some(RoomKind::Meeting);
+        let knocks = rules.pass_knocks(Vec::new());
+        assert_eq!(knocks, vec![knock("@bob:example.org"), knock("@carol:example.org")]);
+        assert!(rules.deferred_knocks.is_empty());
+        assert_eq!(rules.pass_knocks(vec![knock("@dave:example.org")]).len(), 1);
+    }
+
+    #[test]
+    fn test_room_kind_presence() {
+        let alice = Some("@alice:example.org");
+        let mut rules = RoomRules::default();
+        let (status, message, restore) = rules.presence_for(alice, Some(RoomKind::Focus), &UserStatus::Online);
+        assert_eq!(status, UserStatus::Busy);
+        assert_eq!(message, None);
+        assert_eq!(restore, Some(UserStatus::Online));
+
+        // From focus into a meeting, the status from before focus comes back
+        rules.saved_status.insert("@alice:example.org".to_string(), UserStatus::Online);
+        let (status, message, restore) = rules.presence_for(alice, Some(RoomKind::Meeting), &UserStatus::Busy);
+        assert_eq!(status, UserStatus::Online);
+        assert_eq!(message.as_deref(), Some(MEETING_STATUS_MESSAGE));
+        assert_eq!(restore, Some(UserStatus::Online));
+
+        let (status, message, restore) = rules.presence_for(alice, None, &UserStatus::Online);
+        assert_eq!(status, UserStatus::Online);
+        assert_eq!(message, None);
+        assert_eq!(restore, None);
+    }
+
+    #[test]
+    fn test_room_kind_status_is_kept_per_account() {
+        let mut rules = RoomRules {
+            kind: Some(RoomKind::Focus),
+            account: Some("@alice:example.org".to_string()),
+            ..RoomRules::default()
+        };
+        rules.saved_status.insert("@alice:example.org".to_string(), UserStatus::Away);
+
+        // Another account's room does not start from alice's saved status
+        let (status, _, restore) = rules.presence_for(Some("@alice:work.example"), Some(RoomKind::Meeting), &UserStatus::Online);
+        assert_eq!(status, UserStatus::Online);
+        assert_eq!(restore, Some(UserStatus::Online));
+
+        // and alice leaves focus by getting her own status back
+        assert_eq!(
+            rules.handed_back(Some("@alice:work.example")),
+            Some(("@alice:example.org".to_string(), UserStatus::Away))
+        );
+        // Nothing to hand back when staying on the same account
+        assert_eq!(rules.handed_back(Some("@alice:example.org")), None);
+
+        rules.saved_status.clear();
+        assert_eq!(rules.handed_back(Some("@alice:work.example")), None);
+    }
+
+    #[tokio::test]
+    async fn test_room_kind_kept_when_presence_fails() {
+        let mut manager = state_manager();
+        let mut logs = LogState::new();
+
+        // Not logged in, so the presence cannot be set
+        let result = manager.apply_room_kind(Some("!focus:example.org"), Some(RoomKind::Focus), &UserStatus::Online, &mut logs).await;
+        assert!(result.is_err());
+        assert_eq!(manager.room_rules.kind, None);
+        assert!(manager.room_rules.saved_status.is_empty());
+
+        // Not tried again straight away, so the error is not repeated every tick
+        let result = manager.apply_room_kind(Some("!focus:example.org"), Some(RoomKind::Focus), &UserStatus::Online, &mut logs).await;
+        assert!(matches!(result, Ok(None)));
+        assert_eq!(manager.room_rules.kind, None);
+    }
+
+    #[test]
+    fn test_logging_out_forgets_per_room_state() {
+        let mut manager = state_manager();
+        manager.room_rules.kind = Some(RoomKind::Focus);
+        manager.room_rules.account = Some("@alice:example.org".to_string());
+        manager.room_rules.saved_status.insert("@alice:example.org".to_string(), UserStatus::Away);
+        manager.room_rules.deferred_knocks.push(knock("@bob:example.org"));
+        manager.timeline = Some(RoomTimeline { room_id: "!room:example.org".try_into().unwrap(), events: Vec::new() });
+
+        // The last account logged out
+        manager.forget_account("@alice:example.org");
+        assert!(manager.timeline.is_none());
+        assert!(manager.verification.is_none());
+        assert_eq!(manager.room_rules.kind, None);
+        assert_eq!(manager.room_rules.account, None);
+        assert!(manager.room_rules.saved_status.is_empty());
+        assert!(manager.room_rules.deferred_knocks.is_empty());
+    }
+
+    #[test]
+    fn test_state_changes_by_room_and_type() {
+        let changes = StateChanges(vec![
+            ("!office:example.org".to_string(), "com.nok.office.position".to_string()),
+            ("!other:example.org".to_string(), "m.room.member".to_string()),
+        ]);
+
+        assert!(changes.in_room(Some("!office:example.org"), &OFFICE_STATE_EVENTS));
+        assert!(!changes.in_room(Some("!other:example.org"), &OFFICE_STATE_EVENTS));
+        assert!(!changes.in_room(None, &OFFICE_STATE_EVENTS));
+        assert!(changes.anywhere(&["m.room.member"]));
+        assert!(!changes.anywhere(&["com.nok.location"]));
+        assert!(!StateChanges::default().anywhere(&OFFICE_STATE_EVENTS));
+    }
+}
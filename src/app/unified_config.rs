@@ -2,7 +2,7 @@ use std::path::{Path, PathBuf};
 use std::fs;
 use serde::{Deserialize, Serialize};
 use crate::util::{NokError, NokResult};
-use crate::matrix::MatrixConfig;
+use crate::matrix::{default_device_name, discovery, store, MatrixConfig};
 use super::state_manager::CommunicationMode;
 
 /// Unified configuration that encompasses all settings
@@ -38,6 +38,24 @@ pub struct AppConfig {
     pub auto_start_mode: bool,
     pub enable_sounds: bool,
     pub enable_notifications: bool,
+    /// Where `/save` writes attachments; defaults to ~/Downloads/nok
+    #[serde(default)]
+    pub download_dir: Option<String>,
+    /// Play a chime when someone mentions us (`enable_sounds` covers knocks)
+    #[serde(default = "default_true")]
+    pub mention_sound: bool,
+    /// How close, in map cells, avatars must stand to huddle; 0 turns
+    /// huddles off
+    #[serde(default = "default_huddle_distance")]
+    pub huddle_distance: u16,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_huddle_distance() -> u16 {
+    2
 }
 
 /// User-specific configuration
@@ -57,12 +75,40 @@ pub struct MatrixConfigExt {
     pub server_name: String,
     pub device_name: String,
     pub store_path: String,
+    /// Whether the store at `store_path` was created with a passphrase;
+    /// stores from older versions were not
+    #[serde(default)]
+    pub store_encrypted: bool,
+    /// File the store passphrase is read from, created on first use;
+    /// defaults to `store.key` in the data directory
+    #[serde(default)]
+    pub store_key_file: Option<String>,
+    /// Ask for the store passphrase on the login screen instead of
+    /// reading the key file
+    #[serde(default)]
+    pub ask_store_passphrase: bool,
+    /// Accounts that logged in from this machine, most recent first, for
+    /// the account switcher on the login screen
+    #[serde(default)]
+    pub accounts: Vec<SavedAccount>,
     pub auto_login: bool,
     pub enable_encryption: bool,
     pub sync_timeout_ms: u64,
     pub presence_enabled: bool,
 }
 
+/// An account that logged in from this machine
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedAccount {
+    pub user_id: String,
+    /// Device the account logs in as from here; `None` after it logged out
+    #[serde(default)]
+    pub device_id: Option<String>,
+    /// Homeserver found for the account at its first login
+    #[serde(default)]
+    pub homeserver_url: Option<String>,
+}
+
 /// Legacy WebSocket configuration
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LegacyConfig {
@@ -127,6 +173,9 @@ impl Default for AppConfig {
             auto_start_mode: true,
             enable_sounds: true,
             enable_notifications: true,
+            download_dir: None,
+            mention_sound: true,
+            huddle_distance: default_huddle_distance(),
         }
     }
 }
@@ -149,10 +198,15 @@ impl Default for UserConfig {
 impl Default for MatrixConfigExt {
     fn default() -> Self {
         Self {
-            homeserver_url: "http://localhost:6167".to_string(),
-            server_name: "nok.local".to_string(),
-            device_name: "nok-client".to_string(),
-            store_path: "/tmp/nok_matrix_store".to_string(),
+            // Found by discovery at the first login
+            homeserver_url: String::new(),
+            server_name: String::new(),
+            device_name: default_device_name(),
+            store_path: store::default_store_path().to_string_lossy().into_owned(),
+            store_encrypted: true,
+            store_key_file: None,
+            ask_store_passphrase: false,
+            accounts: Vec::new(),
             auto_login: false,
             enable_encryption: true,
             sync_timeout_ms: 30000,
@@ -212,18 +266,21 @@ impl Default for NetworkConfig {
 }
 
 impl UnifiedConfig {
-    /// Load configuration from file, create default if not exists
-    pub fn load() -> Self {
+    /// Load configuration from file, create default if not exists. Also
+    /// returns what went wrong on the way, for the app to show once it
+    /// owns the terminal.
+    pub fn load() -> (Self, Vec<String>) {
         let config_path = Self::get_config_path();
+        let mut notices = Vec::new();
         
-        if config_path.exists() {
+        let config = if config_path.exists() {
             match Self::load_from_file(&config_path) {
                 Ok(config) => {
                     // Migrate any old config format if needed
-                    Self::migrate_if_needed(config)
+                    Self::migrate_if_needed(config, &mut notices)
                 }
                 Err(e) => {
-                    eprintln!("Failed to load config: {}, using defaults", e);
+                    notices.push(format!("Failed to load config: {}, using defaults", e));
                     Self::default()
                 }
             }
@@ -231,10 +288,11 @@ impl UnifiedConfig {
             // Create default config and save it
             let config = Self::default();
             if let Err(e) = config.save() {
-                eprintln!("Failed to save default config: {}", e);
+                notices.push(format!("Failed to save default config: {}", e));
             }
             config
-        }
+        };
+        (config, notices)
     }
 
     /// Save configuration to file
@@ -266,6 +324,17 @@ impl UnifiedConfig {
         }
     }
 
+    /// Directory `/save` downloads attachments into
+    pub fn download_dir(&self) -> PathBuf {
+        if let Some(dir) = &self.app.download_dir {
+            PathBuf::from(dir)
+        } else if let Some(download_dir) = dirs::download_dir() {
+            download_dir.join("nok")
+        } else {
+            PathBuf::from("nok_downloads")
+        }
+    }
+
     /// Load configuration from a specific file
     fn load_from_file(path: &Path) -> NokResult<Self> {
         let content = fs::read_to_string(path)
@@ -276,7 +345,7 @@ impl UnifiedConfig {
     }
 
     /// Migrate from older config formats if needed
-    fn migrate_if_needed(mut config: Self) -> Self {
+    fn migrate_if_needed(mut config: Self, notices: &mut Vec<String>) -> Self {
         // Update version
         config.app.version = env!("CARGO_PKG_VERSION").to_string();
         
@@ -285,10 +354,122 @@ impl UnifiedConfig {
         // if config.app.version < "0.2.0" {
         //     // Migrate from older format
         // }
+
+        // Every machine used to log in as "nok-client"
+        if config.matrix.device_name == "nok-client" {
+            config.matrix.device_name = default_device_name();
+            if let Err(e) = config.save() {
+                notices.push(format!("Failed to save config: {}", e));
+            }
+        }
+
+        if config.encrypt_missing_store() {
+            if let Err(e) = config.save() {
+                notices.push(format!("Failed to save config: {}", e));
+            }
+        }
         
         config
     }
 
+    /// Move on to an encrypted store when the unencrypted one of an older
+    /// version is already gone, e.g. `/tmp` was cleared by a reboot. There
+    /// is nothing left to keep, and the next store would otherwise be
+    /// created unencrypted again without anyone being asked. Returns
+    /// whether the config changed.
+    fn encrypt_missing_store(&mut self) -> bool {
+        if self.matrix.store_encrypted || Path::new(&self.matrix.store_path).exists() {
+            return false;
+        }
+        self.start_encrypted_store();
+        true
+    }
+
+    /// Point the config at a new encrypted store, out of `/tmp`. The
+    /// device keys are not carried over, so saved accounts log in as new
+    /// devices.
+    fn start_encrypted_store(&mut self) {
+        if self.matrix.store_path == store::LEGACY_STORE_PATH {
+            self.matrix.store_path = store::default_store_path().to_string_lossy().into_owned();
+        }
+        self.matrix.store_encrypted = true;
+        for account in &mut self.matrix.accounts {
+            account.device_id = None;
+        }
+    }
+
+    /// Store written without a passphrase by an older version, if it is
+    /// still on disk. It cannot be encrypted in place, so the user picks
+    /// between keeping it and `encrypt_store`.
+    pub fn unencrypted_store(&self) -> Option<PathBuf> {
+        let store = PathBuf::from(&self.matrix.store_path);
+        (!self.matrix.store_encrypted && store.exists()).then_some(store)
+    }
+
+    /// Switch to an encrypted store, shredding the unencrypted one. The
+    /// device keys go with it, so saved accounts log in as new devices
+    /// and get their history back from key backup.
+    pub fn encrypt_store(&mut self) -> NokResult<()> {
+        store::shred_store(Path::new(&self.matrix.store_path))
+            .map_err(NokError::FileSystemError)?;
+        self.start_encrypted_store();
+        self.save()
+    }
+
+    /// Passphrase for the state store, from the key file. `None` when the
+    /// store is unencrypted or the passphrase is asked for at login.
+    pub fn store_passphrase(&self) -> NokResult<Option<String>> {
+        if !self.matrix.store_encrypted || self.matrix.ask_store_passphrase {
+            return Ok(None);
+        }
+        let key_file = match &self.matrix.store_key_file {
+            Some(path) => PathBuf::from(path),
+            None => store::default_key_file(),
+        };
+        store::load_or_create_key(&key_file)
+            .map(Some)
+            .map_err(NokError::FileSystemError)
+    }
+
+    /// Device an account last logged in as from this machine
+    pub fn saved_device_id(&self, user_id: &str) -> Option<String> {
+        self.matrix.accounts.iter()
+            .find(|account| account.user_id == user_id)
+            .and_then(|account| account.device_id.clone())
+    }
+
+    /// Homeserver an account logged in on before
+    pub fn saved_homeserver(&self, user_id: &str) -> Option<String> {
+        self.matrix.accounts.iter()
+            .find(|account| account.user_id == user_id)
+            .and_then(|account| account.homeserver_url.clone())
+    }
+
+    /// Put an account that just logged in first in the account switcher.
+    /// Its server becomes the one bare usernames log in on.
+    pub fn remember_account(&mut self, user_id: &str, device_id: Option<String>, homeserver_url: &str) -> NokResult<()> {
+        if let Some(server_name) = discovery::server_name_of(user_id) {
+            self.matrix.server_name = server_name.to_string();
+            self.matrix.homeserver_url = homeserver_url.to_string();
+        }
+        self.matrix.accounts.retain(|account| account.user_id != user_id);
+        self.matrix.accounts.insert(0, SavedAccount {
+            user_id: user_id.to_string(),
+            device_id,
+            homeserver_url: Some(homeserver_url.to_string()),
+        });
+        self.save()
+    }
+
+    /// Keep a logged out account in the switcher, but without its device,
+    /// which the homeserver deleted
+    pub fn forget_device(&mut self, user_id: &str) -> NokResult<()> {
+        for account in self.matrix.accounts.iter_mut().filter(|account| account.user_id == user_id) {
+            account.device_id = None;
+        }
+        self.save()
+    }
+
     /// Convert to Matrix SDK configuration
     pub fn to_matrix_config(&self) -> MatrixConfig {
         MatrixConfig {
@@ -297,6 +478,8 @@ impl UnifiedConfig {
             device_name: self.matrix.device_name.clone(),
             state_store_path: self.matrix.store_path.clone(),
             store_path: self.matrix.store_path.clone(),
+            store_passphrase: None,
+            device_id: None,
         }
     }
 
@@ -312,12 +495,9 @@ impl UnifiedConfig {
     pub fn validate(&self) -> Result<(), Vec<String>> {
         let mut errors = Vec::new();
 
-        // Validate Matrix config
-        if self.matrix.homeserver_url.is_empty() {
-            errors.push("Matrix homeserver URL cannot be empty".to_string());
-        }
-        if self.matrix.server_name.is_empty() {
-            errors.push("Matrix server name cannot be empty".to_string());
+        // Validate Matrix config; both are empty until the first login
+        if self.matrix.homeserver_url.is_empty() != self.matrix.server_name.is_empty() {
+            errors.push("Matrix homeserver URL and server name must be set together".to_string());
         }
 
         // Validate user config
@@ -451,4 +631,58 @@ impl ConfigMigration {
         
         Ok(backup_path)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Config as written by a version without the `store_encrypted` key
+    fn upgraded_config(store_path: &str) -> UnifiedConfig {
+        let mut value = serde_json::to_value(UnifiedConfig::default()).unwrap();
+        let matrix = value["matrix"].as_object_mut().unwrap();
+        matrix.remove("store_encrypted");
+        matrix.insert("store_path".to_string(), store_path.into());
+        matrix.insert("accounts".to_string(), serde_json::json!([
+            { "user_id": "@alice:nok.local", "device_id": "OLDDEVICE" }
+        ]));
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_upgrade_without_a_store_encrypts_the_next_one() {
+        let gone = std::env::temp_dir().join(format!("nok_gone_store_{}", std::process::id()));
+        let mut config = upgraded_config(&gone.to_string_lossy());
+        assert!(!config.matrix.store_encrypted);
+
+        assert!(config.encrypt_missing_store());
+        assert!(config.matrix.store_encrypted);
+        assert_eq!(config.matrix.store_path, gone.to_string_lossy());
+        assert_eq!(config.saved_device_id("@alice:nok.local"), None);
+        assert!(config.unencrypted_store().is_none());
+        // Nothing more to do on the next start
+        assert!(!config.encrypt_missing_store());
+    }
+
+    #[test]
+    fn test_upgrade_keeps_a_store_that_is_still_there() {
+        let kept = std::env::temp_dir().join(format!("nok_kept_store_{}", std::process::id()));
+        fs::create_dir_all(&kept).unwrap();
+        let mut config = upgraded_config(&kept.to_string_lossy());
+
+        // Left for the user to choose between keeping and encrypting it
+        assert!(!config.encrypt_missing_store());
+        assert_eq!(config.unencrypted_store(), Some(kept.clone()));
+        assert_eq!(config.saved_device_id("@alice:nok.local").as_deref(), Some("OLDDEVICE"));
+        fs::remove_dir_all(&kept).unwrap();
+    }
+
+    #[test]
+    fn test_encrypted_store_moves_out_of_tmp() {
+        let mut config = upgraded_config(store::LEGACY_STORE_PATH);
+        config.start_encrypted_store();
+
+        assert!(config.matrix.store_encrypted);
+        assert_eq!(config.matrix.store_path, store::default_store_path().to_string_lossy());
+    }
+}
@@ -28,3 +28,17 @@ pub fn play_knock_sound() -> Result<(), String> {
     sink.sleep_until_end(); // すべての音が再生されるまで待機
     Ok(())
 }
+
+/// メンション通知音（ノック音とは別の上昇する2音チャイム）
+pub fn play_mention_sound() -> Result<(), String> {
+    let (_stream, stream_handle) = OutputStream::try_default()
+        .map_err(|e| format!("Failed to get output stream: {}", e))?;
+    let sink = Sink::try_new(&stream_handle)
+        .map_err(|e| format!("Failed to create sink: {}", e))?;
+
+    sink.append(SineWave::new(880.0).take_duration(Duration::from_millis(120)).amplify(0.25));
+    sink.append(SineWave::new(1320.0).take_duration(Duration::from_millis(180)).amplify(0.25));
+
+    sink.sleep_until_end();
+    Ok(())
+}
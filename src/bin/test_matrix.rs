@@ -18,6 +18,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         device_name: "test-client".to_string(),
         state_store_path: state_store_path.clone(),
         store_path: state_store_path,
+        store_passphrase: None,
+        device_id: None,
     };
 
     // Matrixクライアント作成
@@ -61,7 +63,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // ルーム一覧表示
     println!("\n🏠 Current rooms:");
-    let rooms = client.rooms();
+    let rooms = client.joined_rooms();
     if rooms.is_empty() {
         println!("   No rooms joined yet");
     } else {
@@ -164,7 +166,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 }
             }
             "4" => {
-                let updated_rooms = client.rooms();
+                let updated_rooms = client.joined_rooms();
                 println!("🏠 Current rooms ({}):", updated_rooms.len());
                 for room in &updated_rooms {
                     if let Ok(name) = room.display_name().await {
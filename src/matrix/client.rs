@@ -1,16 +1,61 @@
-use std::sync::Arc;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
 use tokio::sync::RwLock;
 
 use matrix_sdk::{
+    attachment::AttachmentConfig,
+    RoomMemberships,
     config::SyncSettings,
+    encryption::{identities::RequestVerificationError, BackupDownloadStrategy, EncryptionSettings},
+    deserialized_responses::{RawSyncOrStrippedState, TimelineEvent, TimelineEventKind},
+    room::MessagesOptions,
     Client, Room,
     ruma::{
-        events::room::message::{RoomMessageEventContent, SyncRoomMessageEvent},
-        UserId, OwnedUserId, OwnedRoomId, RoomOrAliasId,
+        api::client::{
+            directory::get_public_rooms_filtered::v3::Request as PublicRoomsRequest,
+            presence::get_presence,
+            receipt::create_receipt::v3::ReceiptType,
+            room::{create_room::v3::{Request as CreateRoomRequest, RoomPreset}, Visibility},
+            space::get_hierarchy,
+        },
+        events::{
+            InitialStateEvent,
+            key::verification::request::ToDeviceKeyVerificationRequestEvent,
+            receipt::ReceiptThread,
+            reaction::ReactionEventContent,
+            relation::{Annotation, Thread},
+            room::encryption::RoomEncryptionEventContent,
+            room::member::MembershipState,
+            room::message::{
+                AddMentions, ForwardThread, MessageType, Relation, ReplacementMetadata,
+                RoomMessageEventContent, SyncRoomMessageEvent,
+            },
+            AnyStrippedStateEvent, AnySyncMessageLikeEvent, AnySyncStateEvent, AnySyncTimelineEvent, OriginalSyncMessageLikeEvent, OriginalSyncStateEvent,
+            StateEventType, StaticEventContent, SyncMessageLikeEvent, SyncStateEvent,
+        },
+        directory::Filter,
+        push::Action,
+        serde::Raw,
+        EventId, UInt, UserId, OwnedEventId, OwnedUserId, OwnedRoomId, RoomOrAliasId,
     },
 };
+use futures_util::{future::join_all, StreamExt};
+use mime::Mime;
 
-use crate::matrix::{MatrixConfig, NokKnockEventContent};
+use crate::app::{PublicRoom, PublicRoomsPage, RoomInvite};
+use crate::app::user::{User, UserStatus};
+use crate::matrix::{
+    DeviceManager, MatrixConfig, NokKnockEventContent, NokLocationEventContent, NokOfficeDeskEventContent,
+    NokOfficePlanEventContent, NokOfficePositionEventContent, NokRoomKindEventContent, PresenceManager, RecoveryManager,
+    Verification, store,
+};
+
+/// Knocks older than this when they arrive (e.g. replayed by the first
+/// sync after starting up) are not announced
+const KNOCK_MAX_AGE: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Verification requests older than this are stale (as the spec says)
+const VERIFICATION_REQUEST_MAX_AGE: std::time::Duration = std::time::Duration::from_secs(600);
 
 /// Matrix client wrapper for nok application
 #[derive(Clone)]
@@ -18,6 +63,18 @@ pub struct MatrixClient {
     inner: Client,
     config: MatrixConfig,
     sync_handle: Arc<RwLock<Option<tokio::task::JoinHandle<()>>>>,
+    /// Knocks on us picked up by the sync loop: room and sender
+    knocks: Arc<Mutex<Vec<(OwnedRoomId, OwnedUserId)>>>,
+    /// Rooms where the sync loop saw encryption being turned on
+    encryption_enabled: Arc<Mutex<Vec<OwnedRoomId>>>,
+    /// Verification requests sent to us: sender and flow ID
+    verification_requests: Arc<Mutex<Vec<(OwnedUserId, String)>>>,
+    /// Timeline events picked up by the sync loop, with their room
+    timeline_events: Arc<Mutex<Vec<(OwnedRoomId, TimelineEvent)>>>,
+    /// Rooms we received room keys for, e.g. from the key backup
+    room_keys_received: Arc<Mutex<Vec<OwnedRoomId>>>,
+    /// State events picked up by the sync loop, as (room, event type)
+    state_changes: Arc<Mutex<Vec<(OwnedRoomId, String)>>>,
 }
 
 impl std::fmt::Debug for MatrixClient {
@@ -32,34 +89,275 @@ impl std::fmt::Debug for MatrixClient {
 impl MatrixClient {
     /// Create a new Matrix client
     pub async fn new(config: MatrixConfig) -> Result<Self, Box<dyn std::error::Error>> {
+        // The store holds our E2EE keys; keep other users out of it
+        store::create_private_dir(Path::new(&config.state_store_path))?;
         let client = Client::builder()
             .homeserver_url(&config.homeserver_url)
-            .sqlite_store(&config.state_store_path, None)
+            .sqlite_store(&config.state_store_path, config.store_passphrase.as_deref())
+            // Room keys come down from the backup as soon as the recovery
+            // key is entered, so old messages decrypt straight away
+            .with_encryption_settings(EncryptionSettings {
+                backup_download_strategy: BackupDownloadStrategy::OneShot,
+                ..Default::default()
+            })
             .build()
             .await?;
 
+        let knocks = Arc::new(Mutex::new(Vec::new()));
+        let received = knocks.clone();
+        client.add_event_handler(move |event: OriginalSyncMessageLikeEvent<NokKnockEventContent>, room: Room, client: Client| {
+            let received = received.clone();
+            async move {
+                let recent = event.origin_server_ts.to_system_time()
+                    .and_then(|sent| sent.elapsed().ok())
+                    .is_some_and(|age| age < KNOCK_MAX_AGE);
+                let for_us = client.user_id()
+                    .is_some_and(|own_id| event.content.target_user == own_id && event.sender != own_id);
+                if recent && for_us {
+                    if let Ok(mut knocks) = received.lock() {
+                        knocks.push((room.room_id().to_owned(), event.sender));
+                    }
+                }
+            }
+        });
+
+        let encryption_enabled = Arc::new(Mutex::new(Vec::new()));
+        let enabled = encryption_enabled.clone();
+        client.add_event_handler(move |_: OriginalSyncStateEvent<RoomEncryptionEventContent>, room: Room| {
+            let enabled = enabled.clone();
+            async move {
+                if let Ok(mut enabled) = enabled.lock() {
+                    enabled.push(room.room_id().to_owned());
+                }
+            }
+        });
+
+        // Requests come to-device from our own devices and as room
+        // messages from other users
+        let verification_requests = Arc::new(Mutex::new(Vec::new()));
+        let requested = verification_requests.clone();
+        client.add_event_handler(move |event: ToDeviceKeyVerificationRequestEvent| {
+            let requested = requested.clone();
+            async move {
+                if let Ok(mut requests) = requested.lock() {
+                    requests.push((event.sender, event.content.transaction_id.to_string()));
+                }
+            }
+        });
+        let requested = verification_requests.clone();
+        client.add_event_handler(move |event: OriginalSyncMessageLikeEvent<RoomMessageEventContent>, client: Client| {
+            let requested = requested.clone();
+            async move {
+                let MessageType::VerificationRequest(request) = &event.content.msgtype else {
+                    return;
+                };
+                let recent = event.origin_server_ts.to_system_time()
+                    .and_then(|sent| sent.elapsed().ok())
+                    .is_some_and(|age| age < VERIFICATION_REQUEST_MAX_AGE);
+                if recent && client.user_id() == Some(&*request.to) {
+                    if let Ok(mut requests) = requested.lock() {
+                        requests.push((event.sender, event.event_id.to_string()));
+                    }
+                }
+            }
+        });
+
+        let timeline_events = Arc::new(Mutex::new(Vec::new()));
+        let received = timeline_events.clone();
+        client.add_event_handler(move |raw: Raw<AnySyncTimelineEvent>, room: Room, push_actions: Vec<Action>| {
+            let received = received.clone();
+            async move {
+                // Handlers get events already decrypted; one still
+                // encrypted failed, and trying again tells us why
+                let encrypted = raw.get_field::<String>("type").ok().flatten().as_deref() == Some("m.room.encrypted");
+                let event = if encrypted {
+                    room.decrypt_event(raw.cast_ref()).await
+                        .unwrap_or_else(|_| TimelineEvent::new(raw))
+                } else {
+                    TimelineEvent::new_with_push_actions(raw, push_actions)
+                };
+                if let Ok(mut events) = received.lock() {
+                    events.push((room.room_id().to_owned(), event));
+                }
+            }
+        });
+
+        // Invites come as stripped state of the room we are invited to
+        let state_changes = Arc::new(Mutex::new(Vec::new()));
+        let changed = state_changes.clone();
+        client.add_event_handler(move |raw: Raw<AnySyncStateEvent>, room: Room| {
+            let changed = changed.clone();
+            async move {
+                if let (Ok(Some(event_type)), Ok(mut changes)) = (raw.get_field::<String>("type"), changed.lock()) {
+                    changes.push((room.room_id().to_owned(), event_type));
+                }
+            }
+        });
+        let changed = state_changes.clone();
+        client.add_event_handler(move |raw: Raw<AnyStrippedStateEvent>, room: Room| {
+            let changed = changed.clone();
+            async move {
+                if let (Ok(Some(event_type)), Ok(mut changes)) = (raw.get_field::<String>("type"), changed.lock()) {
+                    changes.push((room.room_id().to_owned(), event_type));
+                }
+            }
+        });
+
         Ok(Self {
             inner: client,
             config,
             sync_handle: Arc::new(RwLock::new(None)),
+            knocks,
+            encryption_enabled,
+            verification_requests,
+            timeline_events,
+            room_keys_received: Arc::new(Mutex::new(Vec::new())),
+            state_changes,
         })
     }
 
+    /// Knocks on us received since the last call, as (room, sender)
+    pub fn take_knocks(&self) -> Vec<(OwnedRoomId, OwnedUserId)> {
+        self.knocks.lock()
+            .map(|mut knocks| std::mem::take(&mut *knocks))
+            .unwrap_or_default()
+    }
+
+    /// Timeline events received since the last call, as (room, event)
+    pub fn take_timeline_events(&self) -> Vec<(OwnedRoomId, TimelineEvent)> {
+        self.timeline_events.lock()
+            .map(|mut events| std::mem::take(&mut *events))
+            .unwrap_or_default()
+    }
+
+    /// State events received since the last call, as (room, event type)
+    pub fn take_state_changes(&self) -> Vec<(OwnedRoomId, String)> {
+        self.state_changes.lock()
+            .map(|mut changes| std::mem::take(&mut *changes))
+            .unwrap_or_default()
+    }
+
+    /// Rooms we received room keys for since the last call
+    pub fn take_room_keys_received(&self) -> Vec<OwnedRoomId> {
+        self.room_keys_received.lock()
+            .map(|mut rooms| std::mem::take(&mut *rooms))
+            .unwrap_or_default()
+    }
+
+    /// Decrypt again the events of a room that failed to decrypt, e.g.
+    /// once their room keys arrived
+    pub async fn retry_decryption(&self, room_id: &OwnedRoomId, events: &mut [TimelineEvent]) {
+        let Some(room) = self.inner.get_room(room_id) else {
+            return;
+        };
+        for event in events.iter_mut() {
+            if let TimelineEventKind::UnableToDecrypt { event: raw, .. } = &event.kind {
+                if let Ok(decrypted) = room.decrypt_event(raw.cast_ref()).await {
+                    *event = decrypted;
+                }
+            }
+        }
+    }
+
+    /// Rooms that had `m.room.encryption` set since the last call
+    pub fn take_encryption_changes(&self) -> Vec<OwnedRoomId> {
+        self.encryption_enabled.lock()
+            .map(|mut enabled| std::mem::take(&mut *enabled))
+            .unwrap_or_default()
+    }
+
+    /// Verification requests sent to us since the last call that are still
+    /// open
+    pub async fn take_verification_requests(&self) -> Vec<Verification> {
+        let requests = self.verification_requests.lock()
+            .map(|mut requests| std::mem::take(&mut *requests))
+            .unwrap_or_default();
+        let mut verifications = Vec::new();
+        for (sender, flow_id) in requests {
+            if let Some(request) = self.inner.encryption().get_verification_request(&sender, &flow_id).await {
+                if !request.is_done() && !request.is_cancelled() {
+                    verifications.push(Verification::new(request));
+                }
+            }
+        }
+        verifications
+    }
+
+    /// Ask to verify another user, or with `device_id` one particular
+    /// device. `None` when they have no cross-signing identity or the
+    /// device is unknown.
+    pub async fn request_verification(&self, user_id: &UserId, device_id: Option<&str>) -> Result<Option<Verification>, RequestVerificationError> {
+        let encryption = self.inner.encryption();
+        let request = match device_id {
+            Some(device_id) => match encryption.get_device(user_id, device_id.into()).await.map_err(matrix_sdk::Error::from)? {
+                Some(device) => device.request_verification().await?,
+                None => return Ok(None),
+            },
+            None => match encryption.request_user_identity(user_id).await? {
+                Some(identity) => identity.request_verification().await?,
+                None => return Ok(None),
+            },
+        };
+        Ok(Some(Verification::new(request)))
+    }
+
+    /// Whether we verified a user's cross-signing identity; `None` when
+    /// they have none
+    pub async fn is_user_verified(&self, user_id: &UserId) -> Result<Option<bool>, matrix_sdk::Error> {
+        let identity = self.inner.encryption().request_user_identity(user_id).await?;
+        Ok(identity.map(|identity| identity.is_verified()))
+    }
+
+    /// Cross-signing and key backup setup for our account
+    pub fn recovery(&self) -> RecoveryManager {
+        RecoveryManager::new(self.inner.clone())
+    }
+
+    pub fn devices(&self) -> DeviceManager {
+        DeviceManager::new(self.inner.clone())
+    }
+
+    /// Whether a room is end-to-end encrypted, asking the homeserver when
+    /// the sync has not told us yet
+    pub async fn is_room_encrypted(&self, room_id: &OwnedRoomId) -> Result<bool, matrix_sdk::Error> {
+        let Some(room) = self.inner.get_room(room_id) else {
+            return Ok(false);
+        };
+        Ok(room.latest_encryption_state().await?.is_encrypted())
+    }
+
+    /// Turn on end-to-end encryption in a room. This cannot be undone.
+    pub async fn enable_encryption(&self, room_id: &OwnedRoomId) -> Result<(), matrix_sdk::Error> {
+        let Some(room) = self.inner.get_room(room_id) else {
+            return Ok(());
+        };
+        room.enable_encryption().await
+    }
+
     /// Login with username and password
     pub async fn login(&self, username: &str, password: &str) -> Result<(), matrix_sdk::Error> {
-        let user_id_str = format!("@{}:{}", username, self.config.server_name);
-        let user_id = UserId::parse(&user_id_str)?;
+        let user_id = UserId::parse(self.config.user_id_for(username))?;
 
-        self.inner
+        let mut login = self.inner
             .matrix_auth()
             .login_username(&user_id, password)
-            .send()
-            .await?;
+            .initial_device_display_name(&self.config.device_name);
+        if let Some(device_id) = &self.config.device_id {
+            login = login.device_id(device_id);
+        }
+        login.send().await?;
 
         println!("Logged in as: {}", self.inner.user_id().unwrap());
         Ok(())
     }
 
+    /// Log this device out. The homeserver invalidates its access token
+    /// and deletes the device along with its keys.
+    pub async fn logout(&self) -> Result<(), matrix_sdk::Error> {
+        self.inner.matrix_auth().logout().await?;
+        Ok(())
+    }
+
     /// Start syncing with the homeserver
     pub async fn start_sync(&self) -> Result<(), matrix_sdk::Error> {
         // Check if sync is already running
@@ -68,9 +366,27 @@ impl MatrixClient {
         }
 
         let client = self.inner.clone();
+        let room_keys_received = self.room_keys_received.clone();
         let handle = tokio::spawn(async move {
-            if let Err(e) = client.sync(SyncSettings::default()).await {
-                eprintln!("Sync error: {}", e);
+            // Room keys can arrive after the events they decrypt; this
+            // runs as long as the sync does
+            let watch_room_keys = async {
+                if let Some(mut room_keys) = client.encryption().room_keys_received_stream().await {
+                    while let Some(keys) = room_keys.next().await {
+                        if let (Ok(keys), Ok(mut rooms)) = (keys, room_keys_received.lock()) {
+                            rooms.extend(keys.into_iter().map(|key| key.room_id));
+                        }
+                    }
+                }
+                std::future::pending::<()>().await
+            };
+            tokio::select! {
+                result = client.sync(SyncSettings::default()) => {
+                    if let Err(e) = result {
+                        eprintln!("Sync error: {}", e);
+                    }
+                }
+                _ = watch_room_keys => {}
             }
         });
 
@@ -100,10 +416,108 @@ impl MatrixClient {
         Ok(())
     }
 
+    /// Create a room and join it. `alias` is the localpart of the room's
+    /// canonical alias; private rooms are invite-only and unpublished.
+    pub async fn create_room(
+        &self,
+        name: &str,
+        alias: Option<&str>,
+        private: bool,
+        encrypted: bool,
+    ) -> Result<OwnedRoomId, matrix_sdk::Error> {
+        let mut request = CreateRoomRequest::new();
+        request.name = Some(name.to_string());
+        request.room_alias_name = alias.map(ToOwned::to_owned);
+        if private {
+            request.preset = Some(RoomPreset::PrivateChat);
+            request.visibility = Visibility::Private;
+        } else {
+            request.preset = Some(RoomPreset::PublicChat);
+            request.visibility = Visibility::Public;
+        }
+        if encrypted {
+            let encryption = InitialStateEvent::new(RoomEncryptionEventContent::with_recommended_defaults());
+            request.initial_state.push(encryption.to_raw_any());
+        }
+        // Members publish their own office position and desk as state,
+        // which needs less power than other state. The server's defaults for the other
+        // event types are repeated since this map replaces them.
+        let power_levels = serde_json::json!({
+            "events": {
+                "m.room.name": 50,
+                "m.room.avatar": 50,
+                "m.room.canonical_alias": 50,
+                "m.room.power_levels": 100,
+                "m.room.history_visibility": 100,
+                "m.room.encryption": 100,
+                "m.room.tombstone": 100,
+                "m.room.server_acl": 100,
+                "com.nok.office.position": 0,
+                "com.nok.office.desk": 0,
+                "com.nok.location": 0,
+            }
+        });
+        request.power_level_content_override = Some(Raw::new(&power_levels)?.cast());
+
+        let room = self.inner.create_room(request).await?;
+        Ok(room.room_id().to_owned())
+    }
+
+    /// Search the homeserver's public room directory. `since` is a
+    /// pagination token from a previous page.
+    pub async fn public_rooms(&self, search: Option<&str>, since: Option<&str>, limit: u32) -> Result<PublicRoomsPage, matrix_sdk::Error> {
+        let mut filter = Filter::new();
+        filter.generic_search_term = search.map(ToOwned::to_owned);
+        let mut request = PublicRoomsRequest::new();
+        request.filter = filter;
+        request.since = since.map(ToOwned::to_owned);
+        request.limit = Some(UInt::from(limit));
+
+        let response = self.inner.public_rooms_filtered(request).await?;
+        Ok(PublicRoomsPage {
+            rooms: response.chunk.into_iter()
+                .map(|chunk| PublicRoom {
+                    room_id: chunk.room_id.to_string(),
+                    alias: chunk.canonical_alias.map(|alias| alias.to_string()),
+                    name: chunk.name,
+                    topic: chunk.topic,
+                    member_count: chunk.num_joined_members.into(),
+                })
+                .collect(),
+            next_batch: response.next_batch,
+            prev_batch: response.prev_batch,
+            total: response.total_room_count_estimate.map(Into::into),
+        })
+    }
+
+    /// Invite a user into a room
+    pub async fn invite_user(&self, room_id: &OwnedRoomId, user_id: &UserId) -> Result<(), matrix_sdk::Error> {
+        if let Some(room) = self.inner.get_room(room_id) {
+            room.invite_user_by_id(user_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Remove a member from a room
+    pub async fn kick_user(&self, room_id: &OwnedRoomId, user_id: &UserId, reason: Option<&str>) -> Result<(), matrix_sdk::Error> {
+        if let Some(room) = self.inner.get_room(room_id) {
+            room.kick_user(user_id, reason).await?;
+        }
+        Ok(())
+    }
+
+    /// Change the topic of a room
+    pub async fn set_topic(&self, room_id: &OwnedRoomId, topic: &str) -> Result<(), matrix_sdk::Error> {
+        if let Some(room) = self.inner.get_room(room_id) {
+            room.set_room_topic(topic).await?;
+        }
+        Ok(())
+    }
+
     /// Send a text message to a room
     pub async fn send_message(&self, room_id: &OwnedRoomId, content: &str) -> Result<(), matrix_sdk::Error> {
         if let Some(room) = self.inner.get_room(room_id) {
-            let content = RoomMessageEventContent::text_plain(content);
+            let content = RoomMessageEventContent::text_markdown(content);
             room.send(content).await?;
         }
         Ok(())
@@ -112,24 +526,438 @@ impl MatrixClient {
     /// Send a knock event to a user
     pub async fn send_knock(&self, room_id: &OwnedRoomId, target_user: &OwnedUserId) -> Result<(), matrix_sdk::Error> {
         if let Some(room) = self.inner.get_room(room_id) {
-            let _knock_content = NokKnockEventContent {
-                target_user: target_user.clone(),
-                timestamp: chrono::Utc::now().timestamp_millis(),
-            };
+            room.send(NokKnockEventContent::new(target_user.clone())).await?;
+        }
+        Ok(())
+    }
 
-            // Send as custom event - this will be implemented in events.rs
-            // For now, send as a regular message with special format
-            let message = format!("🚪 *knock knock* for {}", target_user);
-            let content = RoomMessageEventContent::text_plain(&message);
+    /// Fetch the most recent timeline events of a room, oldest first
+    pub async fn fetch_timeline(&self, room_id: &OwnedRoomId, limit: u32) -> Result<Vec<TimelineEvent>, matrix_sdk::Error> {
+        let Some(room) = self.inner.get_room(room_id) else {
+            return Ok(Vec::new());
+        };
+
+        let mut options = MessagesOptions::backward();
+        options.limit = UInt::from(limit);
+        let mut events = room.messages(options).await?.chunk;
+        events.reverse();
+        Ok(events)
+    }
+
+    /// Send a text message as a reply (`m.in_reply_to`) to another event
+    pub async fn send_reply(&self, room_id: &OwnedRoomId, in_reply_to: &EventId, content: &str) -> Result<(), matrix_sdk::Error> {
+        if let Some(room) = self.inner.get_room(room_id) {
+            let original = room.event(in_reply_to, None).await?;
+            let content = RoomMessageEventContent::text_markdown(content).make_reply_to_raw(
+                original.raw(),
+                in_reply_to.to_owned(),
+                room_id,
+                ForwardThread::Yes,
+                AddMentions::Yes,
+            );
             room.send(content).await?;
         }
         Ok(())
     }
 
+    /// Send a message into a thread (`m.thread`), falling back to a reply to
+    /// the latest thread event for clients without thread support
+    pub async fn send_thread_message(&self, room_id: &OwnedRoomId, thread_root: &EventId, latest_event: &EventId, content: &str) -> Result<(), matrix_sdk::Error> {
+        if let Some(room) = self.inner.get_room(room_id) {
+            let mut content = RoomMessageEventContent::text_markdown(content);
+            content.relates_to = Some(Relation::Thread(Thread::plain(
+                thread_root.to_owned(),
+                latest_event.to_owned(),
+            )));
+            room.send(content).await?;
+        }
+        Ok(())
+    }
 
-    /// Get all joined rooms
-    pub fn rooms(&self) -> Vec<Room> {
-        self.inner.rooms()
+    /// Replace the body of one of our own messages (`m.replace`)
+    pub async fn edit_message(&self, room_id: &OwnedRoomId, event_id: &EventId, new_content: &str) -> Result<(), matrix_sdk::Error> {
+        if let Some(room) = self.inner.get_room(room_id) {
+            let content = RoomMessageEventContent::text_markdown(new_content)
+                .make_replacement(ReplacementMetadata::new(event_id.to_owned(), None), None);
+            room.send(content).await?;
+        }
+        Ok(())
+    }
+
+    /// Redact an event (message or reaction)
+    pub async fn redact_event(&self, room_id: &OwnedRoomId, event_id: &EventId, reason: Option<&str>) -> Result<(), matrix_sdk::Error> {
+        if let Some(room) = self.inner.get_room(room_id) {
+            room.redact(event_id, reason, None).await?;
+        }
+        Ok(())
+    }
+
+    /// Annotate an event with an emoji reaction (`m.annotation`)
+    pub async fn send_reaction(&self, room_id: &OwnedRoomId, event_id: &EventId, key: &str) -> Result<(), matrix_sdk::Error> {
+        if let Some(room) = self.inner.get_room(room_id) {
+            let content = ReactionEventContent::new(Annotation::new(event_id.to_owned(), key.to_string()));
+            room.send(content).await?;
+        }
+        Ok(())
+    }
+
+    /// Upload a file and send it as `m.file`, `m.image`, `m.audio` or `m.video`
+    /// depending on its MIME type. The SDK encrypts the media in E2EE rooms.
+    pub async fn send_attachment(&self, room_id: &OwnedRoomId, filename: &str, content_type: &Mime, data: Vec<u8>) -> Result<(), matrix_sdk::Error> {
+        if let Some(room) = self.inner.get_room(room_id) {
+            room.send_attachment(filename, content_type, data, AttachmentConfig::new()).await?;
+        }
+        Ok(())
+    }
+
+    /// Download the media of a message, decrypting it for encrypted rooms.
+    /// Returns the filename and content, or `None` if the event has no media.
+    pub async fn download_attachment(&self, room_id: &OwnedRoomId, event_id: &EventId) -> Result<Option<(String, Vec<u8>)>, matrix_sdk::Error> {
+        let Some(room) = self.inner.get_room(room_id) else {
+            return Ok(None);
+        };
+        let event = room.event(event_id, None).await?;
+        let Ok(AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomMessage(
+            SyncMessageLikeEvent::Original(ev),
+        ))) = event.raw().deserialize() else {
+            return Ok(None);
+        };
+
+        let media = self.inner.media();
+        let (filename, data) = match &ev.content.msgtype {
+            MessageType::File(c) => (c.filename(), media.get_file(c, true).await?),
+            MessageType::Image(c) => (c.filename(), media.get_file(c, true).await?),
+            MessageType::Audio(c) => (c.filename(), media.get_file(c, true).await?),
+            MessageType::Video(c) => (c.filename(), media.get_file(c, true).await?),
+            _ => return Ok(None),
+        };
+        Ok(data.map(|data| (filename.to_string(), data)))
+    }
+
+    /// Unread (notification, highlight) counts of a room. Takes the larger of
+    /// the server's counts and the client-side ones, which are more accurate
+    /// in encrypted rooms.
+    pub fn unread_counts(&self, room_id: &OwnedRoomId) -> (u64, u64) {
+        let Some(room) = self.inner.get_room(room_id) else {
+            return (0, 0);
+        };
+        let server = room.unread_notification_counts();
+        (
+            server.notification_count.max(room.num_unread_notifications()),
+            server.highlight_count.max(room.num_unread_mentions()),
+        )
+    }
+
+    /// Send a read receipt for an event
+    pub async fn mark_read(&self, room_id: &OwnedRoomId, event_id: &EventId) -> Result<(), matrix_sdk::Error> {
+        if let Some(room) = self.inner.get_room(room_id) {
+            room.send_single_receipt(ReceiptType::Read, ReceiptThread::Unthreaded, event_id.to_owned()).await?;
+        }
+        Ok(())
+    }
+
+    /// Joined members of a room with their display names and synced presence
+    pub async fn room_members(&self, room_id: &OwnedRoomId) -> Result<Vec<User>, matrix_sdk::Error> {
+        let Some(room) = self.inner.get_room(room_id) else {
+            return Ok(Vec::new());
+        };
+
+        let mut users = Vec::new();
+        for member in room.members(RoomMemberships::JOIN).await? {
+            let mut user = User::from_matrix_id(member.user_id().to_string());
+            user.name = member.name().to_string();
+            user.status = self.synced_presence(member.user_id()).await.unwrap_or(UserStatus::Offline);
+            users.push(user);
+        }
+        Ok(users)
+    }
+
+    /// Presence of a user as received through sync, if we share a room
+    async fn synced_presence(&self, user_id: &UserId) -> Option<UserStatus> {
+        let raw = self.inner.state_store().get_presence_event(user_id).await.ok()??;
+        let event = raw.deserialize().ok()?;
+        Some(PresenceManager::presence_to_user_status(&event.content.presence))
+    }
+
+    /// Presence of any user, asking the homeserver when sync has none.
+    /// Servers may refuse this for users we share no room with.
+    async fn fetch_presence(&self, user_id: &UserId) -> UserStatus {
+        if let Some(status) = self.synced_presence(user_id).await {
+            return status;
+        }
+        match self.inner.send(get_presence::v3::Request::new(user_id.to_owned())).await {
+            Ok(response) => PresenceManager::presence_to_user_status(&response.presence),
+            Err(_) => UserStatus::Offline,
+        }
+    }
+
+    /// Search the homeserver's user directory. Returns the matches and
+    /// whether the server cut the result list short.
+    pub async fn search_users(&self, query: &str, limit: u64) -> Result<(Vec<User>, bool), matrix_sdk::Error> {
+        let response = self.inner.search_users(query, limit).await?;
+
+        let statuses = join_all(response.results.iter().map(|result| self.fetch_presence(&result.user_id))).await;
+        let users = response.results.into_iter()
+            .zip(statuses)
+            .map(|(result, status)| {
+                let mut user = User::from_matrix_id(result.user_id.to_string());
+                if let Some(display_name) = result.display_name {
+                    user.name = display_name;
+                }
+                user.status = status;
+                user
+            })
+            .collect();
+        Ok((users, response.limited))
+    }
+
+    /// The other person of a 1:1 room tracked in `m.direct`, if it is one
+    pub fn dm_partner(&self, room_id: &OwnedRoomId) -> Option<OwnedUserId> {
+        let room = self.inner.get_room(room_id)?;
+        let targets = room.direct_targets();
+        if targets.len() != 1 {
+            return None;
+        }
+        targets.iter().next()?.as_user_id().map(ToOwned::to_owned)
+    }
+
+    /// Whether we have a DM room with a user
+    pub fn has_dm_with(&self, user_id: &UserId) -> bool {
+        self.inner.get_dm_room(user_id).is_some()
+    }
+
+    /// Room we can knock on a user in without creating one: our DM with
+    /// them, else any joined room they are a member of
+    pub async fn shared_room_with(&self, user_id: &UserId) -> Option<OwnedRoomId> {
+        if let Some(room) = self.inner.get_dm_room(user_id) {
+            return Some(room.room_id().to_owned());
+        }
+        for room in self.inner.joined_rooms() {
+            let member = room.get_member_no_sync(user_id).await.ok().flatten();
+            if member.is_some_and(|member| *member.membership() == MembershipState::Join) {
+                return Some(room.room_id().to_owned());
+            }
+        }
+        None
+    }
+
+    /// Find our DM room with a user, creating it (and its `m.direct` entry)
+    /// if there is none yet. Returns the room ID and whether it was created.
+    pub async fn find_or_create_dm(&self, user_id: &UserId) -> Result<(OwnedRoomId, bool), matrix_sdk::Error> {
+        if let Some(room) = self.inner.get_dm_room(user_id) {
+            return Ok((room.room_id().to_owned(), false));
+        }
+        let room = self.inner.create_dm(user_id).await?;
+        Ok((room.room_id().to_owned(), true))
+    }
+
+    /// Office map positions published in a room, by user
+    pub async fn office_positions(&self, room_id: &OwnedRoomId) -> Result<Vec<(OwnedUserId, NokOfficePositionEventContent)>, matrix_sdk::Error> {
+        let Some(room) = self.inner.get_room(room_id) else {
+            return Ok(Vec::new());
+        };
+
+        let mut positions = Vec::new();
+        for raw in room.get_state_events_static::<NokOfficePositionEventContent>().await? {
+            let RawSyncOrStrippedState::Sync(raw) = raw else {
+                continue;
+            };
+            if let Ok(SyncStateEvent::Original(event)) = raw.deserialize() {
+                positions.push((event.state_key, event.content));
+            }
+        }
+        Ok(positions)
+    }
+
+    /// Publish our own position on a room's office map
+    pub async fn set_office_position(&self, room_id: &OwnedRoomId, position: NokOfficePositionEventContent) -> Result<(), matrix_sdk::Error> {
+        let (Some(room), Some(user_id)) = (self.inner.get_room(room_id), self.inner.user_id()) else {
+            return Ok(());
+        };
+        room.send_state_event_for_key(user_id, position).await?;
+        Ok(())
+    }
+
+    /// Whether the room's power levels let us publish our office position.
+    /// Rooms nok creates allow it; elsewhere members need the level of
+    /// `com.nok.office.position` lowered.
+    pub async fn can_set_office_position(&self, room_id: &OwnedRoomId) -> Result<bool, matrix_sdk::Error> {
+        let (Some(room), Some(user_id)) = (self.inner.get_room(room_id), self.inner.user_id()) else {
+            return Ok(false);
+        };
+        let event_type = StateEventType::from(NokOfficePositionEventContent::TYPE);
+        room.can_user_send_state(user_id, event_type).await
+    }
+
+    /// Post the notice a huddle's thread hangs off, returning its event ID
+    pub async fn start_huddle_thread(&self, room_id: &OwnedRoomId, notice: &str) -> Result<Option<OwnedEventId>, matrix_sdk::Error> {
+        let Some(room) = self.inner.get_room(room_id) else {
+            return Ok(None);
+        };
+        let response = room.send(RoomMessageEventContent::notice_plain(notice)).await?;
+        Ok(Some(response.event_id))
+    }
+
+    /// The room type set on a room, as its name
+    pub async fn room_kind(&self, room_id: &OwnedRoomId) -> Result<Option<String>, matrix_sdk::Error> {
+        let Some(room) = self.inner.get_room(room_id) else {
+            return Ok(None);
+        };
+        let Some(RawSyncOrStrippedState::Sync(raw)) = room.get_state_event_static::<NokRoomKindEventContent>().await? else {
+            return Ok(None);
+        };
+        match raw.deserialize() {
+            Ok(SyncStateEvent::Original(event)) => Ok(event.content.kind),
+            _ => Ok(None),
+        }
+    }
+
+    /// Set or clear a room's type
+    pub async fn set_room_kind(&self, room_id: &OwnedRoomId, kind: Option<&str>) -> Result<(), matrix_sdk::Error> {
+        let Some(room) = self.inner.get_room(room_id) else {
+            return Ok(());
+        };
+        room.send_state_event(NokRoomKindEventContent { kind: kind.map(str::to_string) }).await?;
+        Ok(())
+    }
+
+    /// Set our own presence, with an optional status message
+    pub async fn set_presence(&self, status: UserStatus, status_msg: Option<String>) -> Result<(), matrix_sdk::Error> {
+        PresenceManager::new(self.inner.clone()).set_presence(status, status_msg).await
+    }
+
+    /// The office floor plan stored in a room, as text
+    pub async fn office_plan(&self, room_id: &OwnedRoomId) -> Result<Option<String>, matrix_sdk::Error> {
+        let Some(room) = self.inner.get_room(room_id) else {
+            return Ok(None);
+        };
+        let Some(RawSyncOrStrippedState::Sync(raw)) = room.get_state_event_static::<NokOfficePlanEventContent>().await? else {
+            return Ok(None);
+        };
+        match raw.deserialize() {
+            Ok(SyncStateEvent::Original(event)) => Ok(Some(event.content.plan)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Replace a room's office floor plan
+    pub async fn set_office_plan(&self, room_id: &OwnedRoomId, plan: String) -> Result<(), matrix_sdk::Error> {
+        let Some(room) = self.inner.get_room(room_id) else {
+            return Ok(());
+        };
+        room.send_state_event(NokOfficePlanEventContent { plan }).await?;
+        Ok(())
+    }
+
+    /// Desks claimed in a room's office, by user
+    pub async fn office_desks(&self, room_id: &OwnedRoomId) -> Result<Vec<(OwnedUserId, u16, u16)>, matrix_sdk::Error> {
+        let Some(room) = self.inner.get_room(room_id) else {
+            return Ok(Vec::new());
+        };
+
+        let mut desks = Vec::new();
+        for raw in room.get_state_events_static::<NokOfficeDeskEventContent>().await? {
+            let RawSyncOrStrippedState::Sync(raw) = raw else {
+                continue;
+            };
+            if let Ok(SyncStateEvent::Original(event)) = raw.deserialize() {
+                if let (Some(x), Some(y)) = (event.content.x, event.content.y) {
+                    desks.push((event.state_key, x, y));
+                }
+            }
+        }
+        Ok(desks)
+    }
+
+    /// Users currently sitting in a room, as published by their clients
+    pub async fn room_occupants(&self, room_id: &OwnedRoomId) -> Result<Vec<OwnedUserId>, matrix_sdk::Error> {
+        let Some(room) = self.inner.get_room(room_id) else {
+            return Ok(Vec::new());
+        };
+
+        let mut occupants = Vec::new();
+        for raw in room.get_state_events_static::<NokLocationEventContent>().await? {
+            let RawSyncOrStrippedState::Sync(raw) = raw else {
+                continue;
+            };
+            if let Ok(SyncStateEvent::Original(event)) = raw.deserialize() {
+                if event.content.present {
+                    occupants.push(event.state_key);
+                }
+            }
+        }
+        Ok(occupants)
+    }
+
+    /// Say whether we are sitting in a room
+    pub async fn set_location(&self, room_id: &OwnedRoomId, present: bool) -> Result<(), matrix_sdk::Error> {
+        let (Some(room), Some(user_id)) = (self.inner.get_room(room_id), self.inner.user_id()) else {
+            return Ok(());
+        };
+        room.send_state_event_for_key(user_id, NokLocationEventContent { present }).await?;
+        Ok(())
+    }
+
+    /// Claim a desk in a room's office, or give ours up with `None`
+    pub async fn set_office_desk(&self, room_id: &OwnedRoomId, desk: Option<(u16, u16)>) -> Result<(), matrix_sdk::Error> {
+        let (Some(room), Some(user_id)) = (self.inner.get_room(room_id), self.inner.user_id()) else {
+            return Ok(());
+        };
+        let content = NokOfficeDeskEventContent {
+            x: desk.map(|(x, _)| x),
+            y: desk.map(|(_, y)| y),
+        };
+        room.send_state_event_for_key(user_id, content).await?;
+        Ok(())
+    }
+
+    /// Rooms we are invited to, with who invited us
+    pub async fn pending_invites(&self) -> Vec<RoomInvite> {
+        let mut invites = Vec::new();
+        for room in self.inner.invited_rooms() {
+            let room_name = match room.display_name().await {
+                Ok(name) => name.to_string(),
+                Err(_) => room.room_id().to_string(),
+            };
+            // Stripped state may lack the inviter's member event
+            let inviter = room.invite_details().await.ok().and_then(|invite| invite.inviter);
+            invites.push(RoomInvite {
+                room_id: room.room_id().to_string(),
+                room_name,
+                topic: room.topic(),
+                inviter_id: inviter.as_ref().map(|member| member.user_id().to_string()),
+                inviter_name: inviter.as_ref().map(|member| member.name().to_string()),
+                account: self.user_id().map(|user_id| user_id.to_string()),
+            });
+        }
+        invites
+    }
+
+    /// Rooms directly inside a space, read from the space hierarchy API
+    pub async fn space_children(&self, space_id: &OwnedRoomId) -> Result<Vec<OwnedRoomId>, matrix_sdk::Error> {
+        let mut children = Vec::new();
+        let mut from = None;
+        loop {
+            let mut request = get_hierarchy::v1::Request::new(space_id.clone());
+            request.max_depth = Some(UInt::from(1u32));
+            request.from = from;
+            let response = self.inner.send(request).await?;
+
+            // The space itself is the first entry of the hierarchy
+            children.extend(response.rooms.into_iter()
+                .map(|chunk| chunk.room_id)
+                .filter(|room_id| room_id != space_id));
+            match response.next_batch {
+                Some(token) => from = Some(token),
+                None => break,
+            }
+        }
+        Ok(children)
+    }
+
+    /// Get the rooms we are currently a member of
+    pub fn joined_rooms(&self) -> Vec<Room> {
+        self.inner.joined_rooms()
     }
 
     /// Get a specific room by ID
@@ -152,6 +980,21 @@ impl MatrixClient {
         self.inner.user_id().map(|user_id| user_id.to_owned())
     }
 
+    /// The device this session logged in as
+    pub fn device_id(&self) -> Option<String> {
+        self.inner.device_id().map(|device_id| device_id.to_string())
+    }
+
+    /// Homeserver this client talks to
+    pub fn homeserver_url(&self) -> &str {
+        &self.config.homeserver_url
+    }
+
+    /// Directory of this client's store
+    pub fn state_store_path(&self) -> &str {
+        &self.config.state_store_path
+    }
+
     /// Get the underlying Matrix SDK client
     pub fn inner(&self) -> &Client {
         &self.inner
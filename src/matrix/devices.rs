@@ -0,0 +1,117 @@
+use matrix_sdk::{
+    ruma::{
+        api::client::uiaa::{AuthData, Password, UserIdentifier},
+        DeviceId, OwnedDeviceId,
+    },
+    Client, HttpError,
+};
+
+/// One of our own sessions, as listed on the settings screen
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceInfo {
+    pub device_id: String,
+    pub display_name: Option<String>,
+    pub last_seen_ip: Option<String>,
+    pub last_seen: Option<chrono::DateTime<chrono::Utc>>,
+    /// Cross-signed by our identity (always true for this device once
+    /// cross-signing is set up)
+    pub verified: bool,
+    /// The session nok is running in
+    pub is_current: bool,
+}
+
+/// This device first, then the most recently seen; never seen last
+fn sort_devices(devices: &mut [DeviceInfo]) {
+    devices.sort_by(|a, b| b.is_current.cmp(&a.is_current).then(b.last_seen.cmp(&a.last_seen)));
+}
+
+/// Lists, renames and logs out our own devices
+pub struct DeviceManager {
+    client: Client,
+}
+
+impl DeviceManager {
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Our devices, this one first, then the most recently seen
+    pub async fn list(&self) -> Result<Vec<DeviceInfo>, matrix_sdk::Error> {
+        let response = self.client.devices().await?;
+        let current = self.client.device_id();
+        let encryption = self.client.encryption();
+        let mut devices = Vec::with_capacity(response.devices.len());
+        for device in response.devices {
+            let verified = match self.client.user_id() {
+                Some(user_id) => encryption.get_device(user_id, &device.device_id).await
+                    .map_err(matrix_sdk::Error::from)?
+                    .is_some_and(|crypto_device| crypto_device.is_verified()),
+                None => false,
+            };
+            devices.push(DeviceInfo {
+                is_current: current == Some(device.device_id.as_ref()),
+                device_id: device.device_id.to_string(),
+                display_name: device.display_name,
+                last_seen_ip: device.last_seen_ip,
+                last_seen: device.last_seen_ts
+                    .and_then(|ts| chrono::DateTime::from_timestamp_millis(ts.get().into())),
+                verified,
+            });
+        }
+        sort_devices(&mut devices);
+        Ok(devices)
+    }
+
+    pub async fn rename(&self, device_id: &str, display_name: &str) -> Result<(), HttpError> {
+        self.client.rename_device(<&DeviceId>::from(device_id), display_name).await?;
+        Ok(())
+    }
+
+    /// Log the given devices out. The homeserver wants the account
+    /// password before it lets us.
+    pub async fn log_out(&self, device_ids: &[String], password: &str) -> Result<(), HttpError> {
+        let devices: Vec<OwnedDeviceId> = device_ids.iter().map(|id| id.as_str().into()).collect();
+        if let Err(e) = self.client.delete_devices(&devices, None).await {
+            let (Some(uiaa), Some(user_id)) = (e.as_uiaa_response(), self.client.user_id()) else {
+                return Err(e);
+            };
+            let mut auth = Password::new(
+                UserIdentifier::UserIdOrLocalpart(user_id.to_string()),
+                password.to_owned(),
+            );
+            auth.session = uiaa.session.clone();
+            self.client.delete_devices(&devices, Some(AuthData::Password(auth))).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(device_id: &str, is_current: bool, last_seen: Option<i64>) -> DeviceInfo {
+        DeviceInfo {
+            device_id: device_id.to_string(),
+            display_name: None,
+            last_seen_ip: None,
+            last_seen: last_seen.and_then(chrono::DateTime::from_timestamp_millis),
+            verified: false,
+            is_current,
+        }
+    }
+
+    #[test]
+    fn test_sort_devices() {
+        let mut devices = vec![
+            device("NEVER", false, None),
+            device("OLD", false, Some(1_000)),
+            device("HERE", true, Some(500)),
+            device("RECENT", false, Some(2_000)),
+        ];
+        sort_devices(&mut devices);
+
+        let order: Vec<&str> = devices.iter().map(|d| d.device_id.as_str()).collect();
+        assert_eq!(order, vec!["HERE", "RECENT", "OLD", "NEVER"]);
+    }
+}
@@ -0,0 +1,47 @@
+use matrix_sdk::Client;
+
+use crate::util::{NokError, NokResult};
+
+/// Server name of a full Matrix ID, e.g. "example.org" for
+/// "@alice:example.org"; `None` for a bare username
+pub fn server_name_of(user_id: &str) -> Option<&str> {
+    user_id.strip_prefix('@')?
+        .split_once(':')
+        .map(|(_, server)| server)
+        .filter(|server| !server.is_empty())
+}
+
+/// Find the homeserver of a server name: the one its
+/// `/.well-known/matrix/client` points to, or the server name itself when
+/// it has none. The homeserver must answer `/_matrix/client/versions`.
+pub async fn discover_homeserver(server_name: &str) -> NokResult<String> {
+    // A client without a store is enough to ask
+    let client = Client::builder()
+        .server_name_or_homeserver_url(server_name)
+        .build()
+        .await
+        .map_err(|e| NokError::ConnectionFailed(format!("No homeserver found for {}: {}", server_name, e)))?;
+
+    let homeserver = client.homeserver();
+    let versions = client.server_versions().await
+        .map_err(|e| NokError::ConnectionFailed(format!("{} is not a Matrix homeserver: {}", homeserver, e)))?;
+    if versions.is_empty() {
+        return Err(NokError::ConnectionFailed(format!("{} supports no Matrix version", homeserver)));
+    }
+
+    Ok(homeserver.as_str().trim_end_matches('/').to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_name_of() {
+        assert_eq!(server_name_of("@alice:example.org"), Some("example.org"));
+        assert_eq!(server_name_of("@alice:example.org:8448"), Some("example.org:8448"));
+        assert_eq!(server_name_of("alice"), None);
+        assert_eq!(server_name_of("@alice"), None);
+        assert_eq!(server_name_of("@alice:"), None);
+    }
+}
@@ -1,9 +1,18 @@
 use matrix_sdk::ruma::{
-    events::macros::EventContent,
-    OwnedUserId,
+    events::{macros::EventContent, EmptyStateKey},
+    OwnedEventId, OwnedUserId,
 };
 use serde::{Deserialize, Serialize};
 
+/// Room state the office map of a room is drawn from: its plan, where
+/// everyone stands and sits, and whether we may publish our position
+pub const OFFICE_STATE_EVENTS: [&str; 4] = [
+    "com.nok.office.plan",
+    "com.nok.office.position",
+    "com.nok.office.desk",
+    "m.room.power_levels",
+];
+
 /// Custom event content for nok knock functionality
 #[derive(Clone, Debug, Deserialize, Serialize, EventContent)]
 #[ruma_event(type = "com.nok.knock", kind = MessageLike)]
@@ -41,4 +50,59 @@ impl NokKnockEventContent {
 
         format!("🚪 {} knocked on {}'s door at {}", sender, self.target_user, time)
     }
-}
\ No newline at end of file
+}
+/// Where a user stands on a room's office map. Published as room state
+/// with the user's MXID as state key, so everyone in the room sees the
+/// latest position of everyone else.
+#[derive(Clone, Debug, Deserialize, Serialize, EventContent)]
+#[ruma_event(type = "com.nok.office.position", kind = State, state_key_type = OwnedUserId)]
+pub struct NokOfficePositionEventContent {
+    pub x: u16,
+    pub y: u16,
+    /// Root of the thread the user's huddle talks in, while in one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub huddle: Option<OwnedEventId>,
+}
+
+impl NokOfficePositionEventContent {
+    pub fn new(x: u16, y: u16, huddle: Option<OwnedEventId>) -> Self {
+        Self { x, y, huddle }
+    }
+}
+
+/// A room's office floor plan, in the text format of `app::OfficeMap`.
+/// Changing it needs the usual power level for room state.
+#[derive(Clone, Debug, Deserialize, Serialize, EventContent)]
+#[ruma_event(type = "com.nok.office.plan", kind = State, state_key_type = EmptyStateKey)]
+pub struct NokOfficePlanEventContent {
+    pub plan: String,
+}
+
+/// What a room is for ("focus", "meeting" or "lounge"); sent without a
+/// kind to make it an ordinary room again
+#[derive(Clone, Debug, Deserialize, Serialize, EventContent)]
+#[ruma_event(type = "com.nok.room.kind", kind = State, state_key_type = EmptyStateKey)]
+pub struct NokRoomKindEventContent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+}
+
+/// Whether a user is sitting in a room right now, keyed by their MXID.
+/// Unlike membership this follows the room a client has open: it is set
+/// on entering a room and cleared on leaving it.
+#[derive(Clone, Debug, Deserialize, Serialize, EventContent)]
+#[ruma_event(type = "com.nok.location", kind = State, state_key_type = OwnedUserId)]
+pub struct NokLocationEventContent {
+    pub present: bool,
+}
+
+/// The desk a user claimed on a room's office map, keyed by their MXID.
+/// Sent without coordinates to give the desk up.
+#[derive(Clone, Debug, Deserialize, Serialize, EventContent)]
+#[ruma_event(type = "com.nok.office.desk", kind = State, state_key_type = OwnedUserId)]
+pub struct NokOfficeDeskEventContent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub x: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub y: Option<u16>,
+}
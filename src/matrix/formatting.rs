@@ -0,0 +1,289 @@
+use crate::app::{RichLine, RichSpan, TextStyle};
+
+/// Render an HTML `formatted_body` into styled lines for the Messages pane.
+/// Unknown tags are ignored and their text kept; reply fallbacks
+/// (`<mx-reply>`) are dropped.
+pub fn html_to_rich_text(html: &str) -> Vec<RichLine> {
+    let mut renderer = HtmlRenderer::default();
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        if let Some(tag_body) = rest.strip_prefix('<') {
+            match tag_body.find('>') {
+                Some(end) => {
+                    renderer.tag(&tag_body[..end]);
+                    rest = &tag_body[end + 1..];
+                }
+                None => {
+                    renderer.text(&decode_entities(rest));
+                    break;
+                }
+            }
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            renderer.text(&decode_entities(&rest[..end]));
+            rest = &rest[end..];
+        }
+    }
+
+    renderer.finish()
+}
+
+#[derive(Default)]
+struct HtmlRenderer {
+    lines: Vec<RichLine>,
+    current: RichLine,
+    bold: u32,
+    italic: u32,
+    strike: u32,
+    code: u32,
+    pre: u32,
+    quote: u32,
+    links: Vec<(String, String)>, // (href, text seen so far)
+    lists: Vec<Option<u32>>,      // None for <ul>, next number for <ol>
+    skip: u32,
+}
+
+impl HtmlRenderer {
+    fn style(&self) -> TextStyle {
+        TextStyle {
+            bold: self.bold > 0,
+            italic: self.italic > 0,
+            strike: self.strike > 0,
+            code: self.code > 0 && self.pre == 0,
+            code_block: self.pre > 0,
+            link: !self.links.is_empty(),
+            quote: self.quote > 0,
+        }
+    }
+
+    fn tag(&mut self, raw: &str) {
+        let closing = raw.starts_with('/');
+        let raw = raw.trim_start_matches('/').trim_end_matches('/');
+        let name = raw.split_whitespace().next().unwrap_or("").to_ascii_lowercase();
+
+        if name == "mx-reply" {
+            if closing {
+                self.skip = self.skip.saturating_sub(1);
+            } else {
+                self.skip += 1;
+            }
+            return;
+        }
+        if self.skip > 0 {
+            return;
+        }
+
+        let counter = match name.as_str() {
+            "b" | "strong" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => Some(&mut self.bold),
+            "i" | "em" => Some(&mut self.italic),
+            "s" | "del" | "strike" => Some(&mut self.strike),
+            "code" => Some(&mut self.code),
+            "pre" => Some(&mut self.pre),
+            "blockquote" => Some(&mut self.quote),
+            _ => None,
+        };
+        if let Some(counter) = counter {
+            *counter = if closing { counter.saturating_sub(1) } else { *counter + 1 };
+        }
+
+        match name.as_str() {
+            "br" => self.break_line(),
+            "p" | "div" | "pre" | "blockquote" | "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "tr" => {
+                self.ensure_new_line();
+            }
+            "ul" | "ol" if closing => {
+                self.lists.pop();
+                self.ensure_new_line();
+            }
+            "ul" => {
+                self.ensure_new_line();
+                self.lists.push(None);
+            }
+            "ol" => {
+                self.ensure_new_line();
+                let start = attribute(raw, "start").and_then(|s| s.parse().ok()).unwrap_or(1);
+                self.lists.push(Some(start));
+            }
+            "li" if closing => self.ensure_new_line(),
+            "li" => {
+                self.ensure_new_line();
+                let depth = self.lists.len().max(1);
+                let bullet = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "• ".to_string(),
+                };
+                let indent = "  ".repeat(depth - 1);
+                self.push(format!("{}{}", indent, bullet), TextStyle::default());
+            }
+            "a" if closing => {
+                if let Some((href, text)) = self.links.pop() {
+                    // Mentions and bare URLs already show their target
+                    if !href.is_empty() && href != text && !href.starts_with("https://matrix.to/") {
+                        self.push(format!(" ({})", href), TextStyle { link: true, ..TextStyle::default() });
+                    }
+                }
+            }
+            "a" => {
+                let href = attribute(raw, "href").unwrap_or_default();
+                self.links.push((href, String::new()));
+            }
+            "img" if !closing => {
+                let alt = attribute(raw, "alt").unwrap_or_else(|| "image".to_string());
+                self.text(&format!("[{}]", alt));
+            }
+            _ => {}
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        if self.skip > 0 {
+            return;
+        }
+
+        if self.pre > 0 {
+            let mut parts = text.split('\n').peekable();
+            while let Some(part) = parts.next() {
+                if !part.is_empty() {
+                    self.push(part.to_string(), self.style());
+                }
+                if parts.peek().is_some() {
+                    self.break_line();
+                }
+            }
+            return;
+        }
+
+        // HTML whitespace collapses outside <pre>
+        let mut collapsed = String::new();
+        for c in text.chars() {
+            let c = if c.is_whitespace() { ' ' } else { c };
+            if !(c == ' ' && collapsed.ends_with(' ')) {
+                collapsed.push(c);
+            }
+        }
+        let line_is_empty = self.current.iter().all(|span| span.text.trim().is_empty());
+        let collapsed = if line_is_empty { collapsed.trim_start().to_string() } else { collapsed };
+        if collapsed.is_empty() {
+            return;
+        }
+
+        if let Some((_, link_text)) = self.links.last_mut() {
+            link_text.push_str(&collapsed);
+        }
+        self.push(collapsed, self.style());
+    }
+
+    fn push(&mut self, text: String, style: TextStyle) {
+        if self.current.is_empty() && self.quote > 0 {
+            self.current.push(RichSpan {
+                text: "│ ".to_string(),
+                style: TextStyle { quote: true, ..TextStyle::default() },
+            });
+        }
+        match self.current.last_mut() {
+            Some(last) if last.style == style => last.text.push_str(&text),
+            _ => self.current.push(RichSpan { text, style }),
+        }
+    }
+
+    fn break_line(&mut self) {
+        self.lines.push(std::mem::take(&mut self.current));
+    }
+
+    fn ensure_new_line(&mut self) {
+        if !self.current.is_empty() {
+            self.break_line();
+        }
+    }
+
+    fn finish(mut self) -> Vec<RichLine> {
+        self.ensure_new_line();
+        while self.lines.last().is_some_and(|line| line.is_empty()) {
+            self.lines.pop();
+        }
+        self.lines
+    }
+}
+
+/// Value of `name="..."` (or single-quoted) inside a tag
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let start = tag.find(&format!("{}=", name))? + name.len() + 1;
+    let value = &tag[start..];
+    let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+    let end = value[1..].find(quote)?;
+    Some(decode_entities(&value[1..end + 1]))
+}
+
+fn decode_entities(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+
+    let mut decoded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest.find(';').filter(|&end| end <= 10).map(|end| (&rest[1..end], end));
+        let replacement = entity.and_then(|(name, _)| match name {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ => name.strip_prefix("#x").or_else(|| name.strip_prefix("#X"))
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| name.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        });
+        match (replacement, entity) {
+            (Some(c), Some((_, end))) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plain(lines: &[RichLine]) -> Vec<String> {
+        lines.iter()
+            .map(|line| line.iter().map(|span| span.text.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_html_to_rich_text_styles() {
+        let lines = html_to_rich_text("<mx-reply><blockquote>old</blockquote></mx-reply>Hi <b>there</b> &amp; <code>x</code>");
+
+        assert_eq!(plain(&lines), vec!["Hi there & x"]);
+        assert!(lines[0].iter().any(|s| s.text == "there" && s.style.bold));
+        assert!(lines[0].iter().any(|s| s.text == "x" && s.style.code));
+    }
+
+    #[test]
+    fn test_html_to_rich_text_blocks() {
+        let html = "<p>Steps:</p>\n<ol>\n<li>build</li>\n<li><a href=\"https://example.org\">docs</a></li>\n</ol>\n<pre><code>fn main() {\n}\n</code></pre>";
+        let lines = html_to_rich_text(html);
+
+        assert_eq!(
+            plain(&lines),
+            vec!["Steps:", "1. build", "2. docs (https://example.org)", "fn main() {", "}"]
+        );
+        assert!(lines[3][0].style.code_block);
+    }
+}
@@ -0,0 +1,134 @@
+use std::path::{Path, PathBuf};
+
+use mime::Mime;
+
+/// Detect the MIME type of a file from its leading bytes, falling back to
+/// the file extension and finally to `application/octet-stream`.
+pub fn sniff_mime(data: &[u8], path: &Path) -> Mime {
+    sniff_magic(data)
+        .or_else(|| mime_from_extension(path))
+        .and_then(|essence| essence.parse().ok())
+        .unwrap_or(mime::APPLICATION_OCTET_STREAM)
+}
+
+fn sniff_magic(data: &[u8]) -> Option<&'static str> {
+    let essence = if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if data.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        "image/jpeg"
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        "image/gif"
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        "image/webp"
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WAVE") {
+        "audio/wav"
+    } else if data.starts_with(b"OggS") {
+        "audio/ogg"
+    } else if data.starts_with(b"fLaC") {
+        "audio/flac"
+    } else if data.starts_with(b"ID3") || data.starts_with(&[0xFF, 0xFB]) {
+        "audio/mpeg"
+    } else if data.get(4..8) == Some(b"ftyp") {
+        "video/mp4"
+    } else if data.starts_with(b"%PDF-") {
+        "application/pdf"
+    } else if data.starts_with(b"PK\x03\x04") {
+        "application/zip"
+    } else {
+        return None;
+    };
+    Some(essence)
+}
+
+fn mime_from_extension(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    let essence = match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        "mp3" => "audio/mpeg",
+        "ogg" | "oga" | "opus" => "audio/ogg",
+        "wav" => "audio/wav",
+        "flac" => "audio/flac",
+        "m4a" => "audio/mp4",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "txt" | "md" | "log" => "text/plain",
+        "json" => "application/json",
+        "pdf" => "application/pdf",
+        _ => return None,
+    };
+    Some(essence)
+}
+
+/// Label shown in the timeline for a media message
+pub fn attachment_kind(mime: &Mime) -> &'static str {
+    match mime.type_() {
+        mime::IMAGE => "image",
+        mime::AUDIO => "audio",
+        mime::VIDEO => "video",
+        _ => "file",
+    }
+}
+
+/// Pick a path inside `dir` that does not overwrite an existing file
+/// ("report.pdf", "report (1).pdf", ...).
+pub fn unique_download_path(dir: &Path, filename: &str) -> PathBuf {
+    // Never let a sender-chosen name escape the download directory
+    let filename = Path::new(filename)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .filter(|name| !name.is_empty())
+        .unwrap_or("download");
+
+    let candidate = dir.join(filename);
+    if !candidate.exists() {
+        return candidate;
+    }
+
+    let stem = Path::new(filename).file_stem().and_then(|s| s.to_str()).unwrap_or(filename);
+    let extension = Path::new(filename).extension().and_then(|e| e.to_str());
+    (1..)
+        .map(|n| match extension {
+            Some(ext) => dir.join(format!("{} ({}).{}", stem, n, ext)),
+            None => dir.join(format!("{} ({})", stem, n)),
+        })
+        .find(|path| !path.exists())
+        .expect("unbounded range always yields a free name")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff_mime_prefers_content_over_extension() {
+        let png = b"\x89PNG\r\n\x1a\n rest of file";
+        assert_eq!(sniff_mime(png, Path::new("photo.txt")).essence_str(), "image/png");
+        assert_eq!(sniff_mime(b"ID3\x03", Path::new("song")).essence_str(), "audio/mpeg");
+        assert_eq!(sniff_mime(b"hello", Path::new("notes.md")).essence_str(), "text/plain");
+        assert_eq!(sniff_mime(b"\x00\x01", Path::new("blob")), mime::APPLICATION_OCTET_STREAM);
+    }
+
+    #[test]
+    fn test_attachment_kind() {
+        assert_eq!(attachment_kind(&mime::IMAGE_PNG), "image");
+        assert_eq!(attachment_kind(&"audio/ogg".parse().unwrap()), "audio");
+        assert_eq!(attachment_kind(&mime::APPLICATION_PDF), "file");
+    }
+
+    #[test]
+    fn test_unique_download_path() {
+        let dir = std::env::temp_dir().join(format!("nok_media_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        assert_eq!(unique_download_path(&dir, "../../etc/passwd"), dir.join("passwd"));
+
+        std::fs::write(dir.join("report.pdf"), b"x").unwrap();
+        assert_eq!(unique_download_path(&dir, "report.pdf"), dir.join("report (1).pdf"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
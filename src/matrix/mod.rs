@@ -1,10 +1,24 @@
 pub mod client;
+pub mod devices;
+pub mod discovery;
 pub mod events;
+pub mod formatting;
+pub mod media;
 pub mod presence;
+pub mod recovery;
+pub mod store;
+pub mod timeline;
+pub mod verification;
 
 pub use client::MatrixClient;
-pub use events::NokKnockEventContent;
+pub use devices::{DeviceInfo, DeviceManager};
+pub use events::{
+    NokKnockEventContent, NokLocationEventContent, NokOfficeDeskEventContent, NokOfficePlanEventContent,
+    NokOfficePositionEventContent, NokRoomKindEventContent, OFFICE_STATE_EVENTS,
+};
 pub use presence::PresenceManager;
+pub use recovery::{CrossSigningState, RecoveryManager, RecoveryStatus, SecurityStatus};
+pub use verification::{Verification, VerificationPhase};
 
 /// Matrix User ID type alias for nok
 pub type NokUserId = matrix_sdk::ruma::UserId;
@@ -12,12 +26,21 @@ pub type NokUserId = matrix_sdk::ruma::UserId;
 /// Matrix Room ID type alias for nok
 pub type NokRoomId = matrix_sdk::ruma::RoomId;
 
+/// Name new logins give their device, e.g. "nok on laptop", so the
+/// sessions of different machines can be told apart
+pub fn default_device_name() -> String {
+    let host = whoami::fallible::hostname().unwrap_or_else(|_| whoami::devicename());
+    format!("nok on {}", host)
+}
+
 /// Matrix configuration for nok client
 #[derive(Debug, Clone)]
 pub struct MatrixConfig {
-    /// Homeserver URL (e.g., "http://localhost:8008")
+    /// Homeserver URL (e.g., "https://matrix.example.org"); empty until
+    /// found by discovery at the first login
     pub homeserver_url: String,
-    /// Local server name (e.g., "nok.local")
+    /// Server name bare usernames log in on (e.g., "example.org"); empty
+    /// until the first login with a full Matrix ID
     pub server_name: String,
     /// Device name for this client
     pub device_name: String,
@@ -25,17 +48,37 @@ pub struct MatrixConfig {
     pub state_store_path: String,
     /// Store path (alias for state_store_path for compatibility)
     pub store_path: String,
+    /// Passphrase the state store is encrypted with; `None` leaves it in
+    /// plain text
+    pub store_passphrase: Option<String>,
+    /// Device to log in as, so an account logging in again from this
+    /// machine keeps its device and keys; `None` gets a new device
+    pub device_id: Option<String>,
+}
+
+impl MatrixConfig {
+    /// Full Matrix ID for what was typed at login: a full MXID as it is,
+    /// a bare username on our server
+    pub fn user_id_for(&self, username: &str) -> String {
+        let username = username.trim();
+        if discovery::server_name_of(username).is_some() {
+            return username.to_string();
+        }
+        format!("@{}:{}", username.trim_start_matches('@'), self.server_name)
+    }
 }
 
 impl Default for MatrixConfig {
     fn default() -> Self {
         let store_path = "matrix_state.db".to_string();
         Self {
-            homeserver_url: "http://nok.local:6167".to_string(),
-            server_name: "nok.local".to_string(),
-            device_name: "nok-client".to_string(),
+            homeserver_url: String::new(),
+            server_name: String::new(),
+            device_name: default_device_name(),
             state_store_path: store_path.clone(),
             store_path,
+            store_passphrase: None,
+            device_id: None,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,144 @@
+use matrix_sdk::{
+    encryption::recovery::{RecoveryError, RecoveryState},
+    ruma::api::client::uiaa::{AuthData, Password, UserIdentifier},
+    Client,
+};
+
+/// Whether this device can sign with the account's cross-signing keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrossSigningState {
+    Ready,
+    /// The account has cross-signing, but its keys are not on this device
+    /// yet; restoring from the recovery key brings them over
+    KeysMissing,
+    NotSetUp,
+}
+
+/// Server-side key backup protected by the recovery key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryStatus {
+    /// Not known until the first sync
+    Unknown,
+    Enabled,
+    Disabled,
+    /// Set up on the account, but this device has not been given the
+    /// recovery key
+    Incomplete,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecurityStatus {
+    pub cross_signing: CrossSigningState,
+    pub recovery: RecoveryStatus,
+}
+
+impl SecurityStatus {
+    /// Why recovery must not be set up from here, if it must not: an
+    /// identity others may have verified is restored, never replaced, and
+    /// a working backup is left alone
+    pub fn set_up_refusal(&self) -> Option<&'static str> {
+        if self.cross_signing == CrossSigningState::KeysMissing {
+            return Some("This account already has cross-signing; restore it with your recovery key instead");
+        }
+        if self.recovery == RecoveryStatus::Enabled {
+            return Some("Key backup is already set up");
+        }
+        None
+    }
+}
+
+/// Sets up cross-signing and key backup, and restores them on a new login
+pub struct RecoveryManager {
+    client: Client,
+}
+
+impl RecoveryManager {
+    pub fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    pub async fn status(&self) -> Result<SecurityStatus, matrix_sdk::Error> {
+        let encryption = self.client.encryption();
+        let keys_here = encryption.cross_signing_status().await.is_some_and(|status| status.is_complete());
+        let cross_signing = if keys_here {
+            CrossSigningState::Ready
+        } else {
+            let on_server = match self.client.user_id() {
+                Some(user_id) => encryption.request_user_identity(user_id).await?.is_some(),
+                None => false,
+            };
+            if on_server { CrossSigningState::KeysMissing } else { CrossSigningState::NotSetUp }
+        };
+        let recovery = match encryption.recovery().state() {
+            RecoveryState::Enabled => RecoveryStatus::Enabled,
+            RecoveryState::Disabled => RecoveryStatus::Disabled,
+            RecoveryState::Incomplete => RecoveryStatus::Incomplete,
+            RecoveryState::Unknown => RecoveryStatus::Unknown,
+        };
+        Ok(SecurityStatus { cross_signing, recovery })
+    }
+
+    /// Create the cross-signing keys if the account has none, then turn on
+    /// key backup and secret storage. The homeserver wants the account
+    /// password before it accepts new cross-signing keys. Returns the
+    /// recovery key, which is only ever shown this once.
+    pub async fn set_up(&self, password: &str, passphrase: Option<&str>) -> Result<String, RecoveryError> {
+        let encryption = self.client.encryption();
+        let keys_here = encryption.cross_signing_status().await.is_some_and(|status| status.is_complete());
+        if !keys_here {
+            if let Err(e) = encryption.bootstrap_cross_signing(None).await {
+                let (Some(uiaa), Some(user_id)) = (e.as_uiaa_response(), self.client.user_id()) else {
+                    return Err(e.into());
+                };
+                let mut auth = Password::new(
+                    UserIdentifier::UserIdOrLocalpart(user_id.to_string()),
+                    password.to_owned(),
+                );
+                auth.session = uiaa.session.clone();
+                encryption.bootstrap_cross_signing(Some(AuthData::Password(auth))).await?;
+            }
+        }
+
+        let recovery = encryption.recovery();
+        let enable = recovery.enable().wait_for_backups_to_upload();
+        match passphrase {
+            Some(passphrase) => enable.with_passphrase(passphrase).await,
+            None => enable.await,
+        }
+    }
+
+    /// Unlock secret storage with the recovery key (or its passphrase),
+    /// importing the cross-signing keys and the backup key. Room keys are
+    /// then downloaded from the backup.
+    pub async fn restore(&self, recovery_key: &str) -> Result<(), RecoveryError> {
+        self.client.encryption().recovery().recover(recovery_key).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(cross_signing: CrossSigningState, recovery: RecoveryStatus) -> SecurityStatus {
+        SecurityStatus { cross_signing, recovery }
+    }
+
+    #[test]
+    fn test_set_up_is_refused_when_it_would_replace_something() {
+        assert!(status(CrossSigningState::KeysMissing, RecoveryStatus::Disabled).set_up_refusal().is_some());
+        assert!(status(CrossSigningState::KeysMissing, RecoveryStatus::Incomplete).set_up_refusal().is_some());
+        assert_eq!(
+            status(CrossSigningState::Ready, RecoveryStatus::Enabled).set_up_refusal(),
+            Some("Key backup is already set up")
+        );
+    }
+
+    #[test]
+    fn test_set_up_goes_ahead_on_fresh_or_partial_accounts() {
+        assert_eq!(status(CrossSigningState::NotSetUp, RecoveryStatus::Disabled).set_up_refusal(), None);
+        assert_eq!(status(CrossSigningState::NotSetUp, RecoveryStatus::Unknown).set_up_refusal(), None);
+        // Cross-signing is here already; only the backup is added
+        assert_eq!(status(CrossSigningState::Ready, RecoveryStatus::Disabled).set_up_refusal(), None);
+        assert_eq!(status(CrossSigningState::Ready, RecoveryStatus::Incomplete).set_up_refusal(), None);
+    }
+}
@@ -0,0 +1,186 @@
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use rand::{distributions::Alphanumeric, Rng};
+
+/// Where older versions kept the state store: world-readable, and gone
+/// after a reboot
+pub const LEGACY_STORE_PATH: &str = "/tmp/nok_matrix_store";
+
+/// File name start of the SQLite crypto store and its journal files
+const CRYPTO_STORE_PREFIX: &str = "matrix-sdk-crypto";
+
+/// Length of the passphrase written to a new key file
+const KEY_LENGTH: usize = 48;
+
+/// Per-user data directory for nok, e.g. `~/.local/share/nok`
+pub fn data_dir() -> PathBuf {
+    match dirs::data_dir() {
+        Some(dir) => dir.join("nok"),
+        None => PathBuf::from(".nok"),
+    }
+}
+
+pub fn default_store_path() -> PathBuf {
+    data_dir().join("matrix_store")
+}
+
+pub fn default_key_file() -> PathBuf {
+    data_dir().join("store.key")
+}
+
+/// Store of one account under `base`. Each account keeps its own, as a
+/// store only ever holds the encryption keys of a single device.
+pub fn account_store_path(base: &Path, user_id: &str) -> PathBuf {
+    let dir: String = user_id.trim_start_matches('@')
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+        .collect();
+    base.join(dir)
+}
+
+/// Delete a store and everything in it
+pub fn remove_store(path: &Path) -> io::Result<()> {
+    match fs::remove_dir_all(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Delete only the encryption keys from a store, keeping the room state
+/// and cached events. The keys belong to a device that no longer exists
+/// once it logged out.
+pub fn remove_crypto_store(path: &Path) -> io::Result<()> {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with(CRYPTO_STORE_PREFIX) {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Create `path` (and its parents) readable by this user only
+pub fn create_private_dir(path: &Path) -> io::Result<()> {
+    fs::create_dir_all(path)?;
+    restrict(path, 0o700)
+}
+
+/// Read the store passphrase from `path`, writing a random one there
+/// first if the file does not exist yet
+pub fn load_or_create_key(path: &Path) -> io::Result<String> {
+    match fs::read_to_string(path) {
+        Ok(key) => {
+            let key = key.trim().to_string();
+            if key.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is empty", path.display())));
+            }
+            Ok(key)
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            if let Some(parent) = path.parent() {
+                create_private_dir(parent)?;
+            }
+            let key: String = rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(KEY_LENGTH)
+                .map(char::from)
+                .collect();
+            fs::write(path, &key)?;
+            restrict(path, 0o600)?;
+            Ok(key)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Delete a store that was written without a passphrase, overwriting
+/// its files first so the plaintext keys are not left behind in freed
+/// disk blocks. Copy-on-write filesystems and SSDs may still keep old
+/// copies of them.
+pub fn shred_store(path: &Path) -> io::Result<()> {
+    overwrite_files(path)?;
+    remove_store(path)
+}
+
+fn overwrite_files(dir: &Path) -> io::Result<()> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            overwrite_files(&entry.path())?;
+            continue;
+        }
+        let len = entry.metadata()?.len();
+        let mut file = fs::OpenOptions::new().write(true).open(entry.path())?;
+        io::copy(&mut io::repeat(0).take(len), &mut file)?;
+        file.sync_all()?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn restrict(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn restrict(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_account_store_path() {
+        let base = Path::new("/data/matrix_store");
+        assert_eq!(account_store_path(base, "@alice:nok.local"), base.join("alice_nok.local"));
+        assert_eq!(account_store_path(base, "@bob/../x:example.org:8448"), base.join("bob_.._x_example.org_8448"));
+    }
+
+    #[test]
+    fn test_remove_crypto_store_keeps_state() {
+        let dir = std::env::temp_dir().join(format!("nok_store_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["matrix-sdk-state.sqlite3", "matrix-sdk-crypto.sqlite3", "matrix-sdk-crypto.sqlite3-wal"] {
+            fs::write(dir.join(name), b"x").unwrap();
+        }
+
+        remove_crypto_store(&dir).unwrap();
+        assert!(dir.join("matrix-sdk-state.sqlite3").exists());
+        assert!(!dir.join("matrix-sdk-crypto.sqlite3").exists());
+        assert!(!dir.join("matrix-sdk-crypto.sqlite3-wal").exists());
+
+        remove_store(&dir).unwrap();
+        assert!(!dir.exists());
+        remove_store(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_shred_store_overwrites_before_deleting() {
+        let dir = std::env::temp_dir().join(format!("nok_shred_test_{}", std::process::id()));
+        fs::create_dir_all(dir.join("alice_nok.local")).unwrap();
+        let key_file = dir.join("alice_nok.local").join("matrix-sdk-crypto.sqlite3");
+        fs::write(&key_file, b"secret keys").unwrap();
+
+        overwrite_files(&dir).unwrap();
+        assert_eq!(fs::read(&key_file).unwrap(), vec![0; 11]);
+
+        shred_store(&dir).unwrap();
+        assert!(!dir.exists());
+        shred_store(&dir).unwrap();
+    }
+}
@@ -0,0 +1,566 @@
+use std::collections::HashMap;
+
+use matrix_sdk::{
+    deserialized_responses::{TimelineEvent, TimelineEventKind, UnableToDecryptReason},
+    ruma::events::{
+        room::{
+            message::{MessageFormat, MessageType, Relation},
+            redaction::SyncRoomRedactionEvent,
+        },
+        AnySyncMessageLikeEvent, AnySyncTimelineEvent, SyncMessageLikeEvent,
+    },
+};
+
+use crate::app::{Attachment, Message, ReplyPreview, RichLine};
+use crate::matrix::{formatting, NokKnockEventContent};
+use crate::app::user::extract_username_from_matrix_id;
+
+/// Maximum length of the quoted excerpt shown above a reply
+const REPLY_PREVIEW_LEN: usize = 60;
+
+/// Builds the Messages pane list from raw timeline events.
+///
+/// Events must be in chronological order. Relations (`m.replace`,
+/// `m.annotation`, redactions) are folded into the message they target
+/// instead of being shown as separate lines. Messages are highlighted when
+/// push rules say so; events that came without push actions fall back to
+/// intentional mentions and our full MXID or display name in the body.
+/// Events we could not decrypt are kept as "unable to decrypt" lines.
+pub fn build_messages(events: &[TimelineEvent], room_name: &str, own_user_id: Option<&str>, own_display_name: Option<&str>) -> Vec<Message> {
+    let mut messages: Vec<Message> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    // reaction event ID -> (target event ID, key, sent by us)
+    let mut reactions: HashMap<String, (String, String, bool)> = HashMap::new();
+
+    for event in events {
+        let push_highlight = event.push_actions.as_ref()
+            .map(|actions| actions.iter().any(|action| action.is_highlight()));
+        // Knocks are our own event type, which the typed enum cannot carry
+        if event.raw().get_field::<String>("type").ok().flatten().as_deref() == Some("com.nok.knock") {
+            if let Ok(SyncMessageLikeEvent::Original(ev)) = event.raw().deserialize_as::<SyncMessageLikeEvent<NokKnockEventContent>>() {
+                let mut message = new_message(ev.sender.as_str(), ev.event_id.as_str(), ev.origin_server_ts.as_secs().into(), room_name);
+                message.content = format!("🚪 *knock knock* for {}", extract_username_from_matrix_id(ev.content.target_user.as_str()));
+                message.message_type = "knock".to_string();
+                message.highlighted = own_user_id == Some(ev.content.target_user.as_str());
+                index.insert(ev.event_id.to_string(), messages.len());
+                messages.push(message);
+            }
+            continue;
+        }
+        if let TimelineEventKind::UnableToDecrypt { utd_info, .. } = &event.kind {
+            if let Ok(AnySyncTimelineEvent::MessageLike(AnySyncMessageLikeEvent::RoomEncrypted(SyncMessageLikeEvent::Original(ev)))) = event.raw().deserialize() {
+                let event_id = ev.event_id.to_string();
+                let mut message = new_message(ev.sender.as_str(), &event_id, ev.origin_server_ts.as_secs().into(), room_name);
+                message.content = format!("🔒 Unable to decrypt: {}", utd_reason(&utd_info.reason));
+                message.message_type = "undecryptable".to_string();
+                index.insert(event_id, messages.len());
+                messages.push(message);
+            }
+            continue;
+        }
+        let Ok(AnySyncTimelineEvent::MessageLike(event)) = event.raw().deserialize() else {
+            continue;
+        };
+
+        match event {
+            AnySyncMessageLikeEvent::RoomMessage(SyncMessageLikeEvent::Original(ev)) => {
+                let sender_id = ev.sender.to_string();
+                let event_id = ev.event_id.to_string();
+                let highlighted = own_user_id.is_some_and(|own_id| {
+                    own_id != sender_id
+                        && push_highlight.unwrap_or_else(|| {
+                            ev.content.mentions.as_ref().is_some_and(|m| m.user_ids.iter().any(|u| u == own_id))
+                                || mentions_user(ev.content.body(), own_id, own_display_name)
+                        })
+                });
+
+                match &ev.content.relates_to {
+                    Some(Relation::Replacement(replacement)) => {
+                        let target = replacement.event_id.to_string();
+                        if let Some(&idx) = index.get(&target) {
+                            // Only the original sender may edit a message
+                            if messages[idx].is_from(&sender_id) {
+                                messages[idx].content = replacement.new_content.msgtype.body().to_string();
+                                messages[idx].formatted = formatted_of(&replacement.new_content.msgtype);
+                                messages[idx].edited = true;
+                            }
+                        }
+                        continue;
+                    }
+                    Some(Relation::Thread(thread)) => {
+                        let root = thread.event_id.to_string();
+                        let mut message = new_message(&sender_id, &event_id, ev.origin_server_ts.as_secs().into(), room_name);
+                        message.content = strip_reply_fallback(ev.content.body()).to_string();
+                        // A real reply inside the thread, not just the fallback for older clients
+                        if let (false, Some(in_reply_to)) = (thread.is_falling_back, &thread.in_reply_to) {
+                            message.reply_to = Some(reply_preview(&messages, &index, in_reply_to.event_id.as_str()));
+                        }
+                        if let Some(&idx) = index.get(&root) {
+                            messages[idx].thread_replies += 1;
+                        }
+                        message.thread_root = Some(root);
+                        message.attachment = attachment_of(&ev.content.msgtype);
+                        message.formatted = formatted_of(&ev.content.msgtype);
+                        message.highlighted = highlighted;
+                        index.insert(event_id, messages.len());
+                        messages.push(message);
+                    }
+                    Some(Relation::Reply { in_reply_to }) => {
+                        let mut message = new_message(&sender_id, &event_id, ev.origin_server_ts.as_secs().into(), room_name);
+                        message.content = strip_reply_fallback(ev.content.body()).to_string();
+                        message.reply_to = Some(reply_preview(&messages, &index, in_reply_to.event_id.as_str()));
+                        message.attachment = attachment_of(&ev.content.msgtype);
+                        message.formatted = formatted_of(&ev.content.msgtype);
+                        message.highlighted = highlighted;
+                        index.insert(event_id, messages.len());
+                        messages.push(message);
+                    }
+                    _ => {
+                        let mut message = new_message(&sender_id, &event_id, ev.origin_server_ts.as_secs().into(), room_name);
+                        message.content = ev.content.body().to_string();
+                        message.attachment = attachment_of(&ev.content.msgtype);
+                        message.formatted = formatted_of(&ev.content.msgtype);
+                        message.highlighted = highlighted;
+                        index.insert(event_id, messages.len());
+                        messages.push(message);
+                    }
+                }
+            }
+            // Still encrypted but never put through decryption
+            AnySyncMessageLikeEvent::RoomEncrypted(SyncMessageLikeEvent::Original(ev)) => {
+                let event_id = ev.event_id.to_string();
+                let mut message = new_message(ev.sender.as_str(), &event_id, ev.origin_server_ts.as_secs().into(), room_name);
+                message.content = "🔒 Unable to decrypt: no keys for this room on this device".to_string();
+                message.message_type = "undecryptable".to_string();
+                index.insert(event_id, messages.len());
+                messages.push(message);
+            }
+            AnySyncMessageLikeEvent::RoomEncrypted(SyncMessageLikeEvent::Redacted(ev)) => {
+                let event_id = ev.event_id.to_string();
+                let mut message = new_message(ev.sender.as_str(), &event_id, ev.origin_server_ts.as_secs().into(), room_name);
+                message.redacted = true;
+                index.insert(event_id, messages.len());
+                messages.push(message);
+            }
+            AnySyncMessageLikeEvent::RoomMessage(SyncMessageLikeEvent::Redacted(ev)) => {
+                let event_id = ev.event_id.to_string();
+                let mut message = new_message(ev.sender.as_str(), &event_id, ev.origin_server_ts.as_secs().into(), room_name);
+                message.redacted = true;
+                index.insert(event_id, messages.len());
+                messages.push(message);
+            }
+            AnySyncMessageLikeEvent::Reaction(SyncMessageLikeEvent::Original(ev)) => {
+                let target = ev.content.relates_to.event_id.to_string();
+                let key = ev.content.relates_to.key.clone();
+                let is_own = own_user_id == Some(ev.sender.as_str());
+                if let Some(&idx) = index.get(&target) {
+                    let own_event_id = is_own.then(|| ev.event_id.to_string());
+                    messages[idx].add_reaction(&key, own_event_id);
+                    reactions.insert(ev.event_id.to_string(), (target, key, is_own));
+                }
+            }
+            AnySyncMessageLikeEvent::RoomRedaction(SyncRoomRedactionEvent::Original(ev)) => {
+                let Some(redacts) = ev.redacts.or(ev.content.redacts) else {
+                    continue;
+                };
+                let redacts = redacts.to_string();
+
+                if let Some((target, key, is_own)) = reactions.remove(&redacts) {
+                    if let Some(&idx) = index.get(&target) {
+                        messages[idx].remove_reaction_event(&key, is_own);
+                    }
+                } else if let Some(&idx) = index.get(&redacts) {
+                    if let Some(root) = messages[idx].thread_root.clone() {
+                        if let Some(&root_idx) = index.get(&root) {
+                            messages[root_idx].thread_replies = messages[root_idx].thread_replies.saturating_sub(1);
+                        }
+                    }
+                    messages[idx].redacted = true;
+                    messages[idx].content.clear();
+                    messages[idx].reactions.clear();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    messages
+}
+
+/// Why a message could not be decrypted, in words
+fn utd_reason(reason: &UnableToDecryptReason) -> String {
+    match reason {
+        UnableToDecryptReason::MissingMegolmSession { withheld_code: Some(code) } => {
+            format!("the sender withheld the key ({})", code)
+        }
+        UnableToDecryptReason::MissingMegolmSession { withheld_code: None } => {
+            "the room key has not arrived yet".to_string()
+        }
+        UnableToDecryptReason::UnknownMegolmMessageIndex => "the room key we hold starts after this message".to_string(),
+        UnableToDecryptReason::MegolmDecryptionFailure => "the room key did not match".to_string(),
+        UnableToDecryptReason::MalformedEncryptedEvent => "the encrypted event is malformed".to_string(),
+        UnableToDecryptReason::PayloadDeserializationFailure => "the decrypted content is unreadable".to_string(),
+        UnableToDecryptReason::MismatchedIdentityKeys => "the room key came from an unexpected device".to_string(),
+        UnableToDecryptReason::SenderIdentityNotTrusted(_) => "the sender's identity is not trusted".to_string(),
+        UnableToDecryptReason::Unknown => "unknown reason".to_string(),
+    }
+}
+
+fn new_message(sender_id: &str, event_id: &str, timestamp: u64, room_name: &str) -> Message {
+    let mut message = Message::new(
+        extract_username_from_matrix_id(sender_id),
+        String::new(),
+        room_name.to_string(),
+    );
+    message.id = Some(event_id.to_string());
+    message.sender_id = Some(sender_id.to_string());
+    message.timestamp = timestamp;
+    message
+}
+
+/// Filename and size of a media message, `None` for text
+fn attachment_of(msgtype: &MessageType) -> Option<Attachment> {
+    let (kind, filename, size) = match msgtype {
+        MessageType::File(c) => ("file", c.filename(), c.info.as_ref().and_then(|i| i.size)),
+        MessageType::Image(c) => ("image", c.filename(), c.info.as_ref().and_then(|i| i.size)),
+        MessageType::Audio(c) => ("audio", c.filename(), c.info.as_ref().and_then(|i| i.size)),
+        MessageType::Video(c) => ("video", c.filename(), c.info.as_ref().and_then(|i| i.size)),
+        _ => return None,
+    };
+    Some(Attachment {
+        kind: kind.to_string(),
+        filename: filename.to_string(),
+        size: size.map(u64::from),
+    })
+}
+
+/// Whether a message body mentions us by full MXID or display name. A bare
+/// localpart does not count: short ones like "al" or "me" are everyday words.
+pub fn mentions_user(body: &str, user_id: &str, display_name: Option<&str>) -> bool {
+    let body = body.to_lowercase();
+
+    let mut names = vec![user_id.to_lowercase()];
+    if let Some(display_name) = display_name {
+        names.push(display_name.to_lowercase());
+    }
+
+    names.iter()
+        .filter(|name| !name.trim().is_empty())
+        .any(|name| contains_word(&body, name))
+}
+
+/// Substring match that does not count hits inside longer words
+fn contains_word(haystack: &str, word: &str) -> bool {
+    haystack.match_indices(word).any(|(start, _)| {
+        let before = haystack[..start].chars().next_back();
+        let after = haystack[start + word.len()..].chars().next();
+        !before.is_some_and(char::is_alphanumeric) && !after.is_some_and(char::is_alphanumeric)
+    })
+}
+
+/// Styled lines for messages that carry an HTML `formatted_body`
+fn formatted_of(msgtype: &MessageType) -> Option<Vec<RichLine>> {
+    let formatted = match msgtype {
+        MessageType::Text(c) => c.formatted.as_ref(),
+        MessageType::Notice(c) => c.formatted.as_ref(),
+        MessageType::Emote(c) => c.formatted.as_ref(),
+        _ => None,
+    }?;
+    if formatted.format != MessageFormat::Html {
+        return None;
+    }
+    let lines = formatting::html_to_rich_text(&formatted.body);
+    (!lines.is_empty()).then_some(lines)
+}
+
+fn reply_preview(messages: &[Message], index: &HashMap<String, usize>, event_id: &str) -> ReplyPreview {
+    match index.get(event_id).map(|&idx| &messages[idx]) {
+        Some(original) => ReplyPreview {
+            event_id: event_id.to_string(),
+            sender: original.sender.clone(),
+            content: truncate(&original.display_content(), REPLY_PREVIEW_LEN),
+        },
+        // The original is older than the loaded window
+        None => ReplyPreview {
+            event_id: event_id.to_string(),
+            sender: "?".to_string(),
+            content: "(message not loaded)".to_string(),
+        },
+    }
+}
+
+/// Remove the "> <@user:server> quoted" fallback other clients prepend to replies
+pub fn strip_reply_fallback(body: &str) -> &str {
+    if !body.starts_with("> ") {
+        return body;
+    }
+
+    let mut rest = body;
+    while let Some(line_end) = rest.find('\n') {
+        if rest.starts_with("> ") || rest.starts_with(">\n") {
+            rest = &rest[line_end + 1..];
+        } else {
+            break;
+        }
+    }
+    rest.trim_start_matches('\n')
+}
+
+/// Add the events the sync delivered to a loaded timeline, skipping those
+/// it holds already. Returns whether anything was added.
+pub fn append_events(events: &mut Vec<TimelineEvent>, received: impl IntoIterator<Item = TimelineEvent>) -> bool {
+    let mut added = false;
+    for event in received {
+        let event_id = event.event_id();
+        if event_id.is_some() && events.iter().any(|known| known.event_id() == event_id) {
+            continue;
+        }
+        events.push(event);
+        added = true;
+    }
+    added
+}
+
+/// Whether any of the events failed to decrypt
+pub fn has_undecryptable(events: &[TimelineEvent]) -> bool {
+    events.iter().any(|event| matches!(event.kind, TimelineEventKind::UnableToDecrypt { .. }))
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        text.to_string()
+    } else {
+        let cut: String = text.chars().take(max_chars).collect();
+        format!("{}…", cut)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use matrix_sdk::deserialized_responses::UnableToDecryptInfo;
+    use matrix_sdk::ruma::serde::Raw;
+    use serde_json::json;
+
+    fn event(value: serde_json::Value) -> TimelineEvent {
+        TimelineEvent::new(Raw::new(&value).unwrap().cast())
+    }
+
+    fn text(event_id: &str, sender: &str, body: &str) -> serde_json::Value {
+        json!({
+            "type": "m.room.message",
+            "event_id": event_id,
+            "sender": sender,
+            "origin_server_ts": 1_700_000_000_000u64,
+            "content": { "msgtype": "m.text", "body": body }
+        })
+    }
+
+    #[test]
+    fn test_relations_are_folded_into_targets() {
+        let mut reply = text("$2", "@bob:nok.local", "> <@alice:nok.local> hello\n\nhi alice");
+        reply["content"]["m.relates_to"] = json!({ "m.in_reply_to": { "event_id": "$1" } });
+
+        let mut edit = text("$3", "@alice:nok.local", "* hello everyone");
+        edit["content"]["m.new_content"] = json!({ "msgtype": "m.text", "body": "hello everyone" });
+        edit["content"]["m.relates_to"] = json!({ "rel_type": "m.replace", "event_id": "$1" });
+
+        let reaction = json!({
+            "type": "m.reaction",
+            "event_id": "$4",
+            "sender": "@alice:nok.local",
+            "origin_server_ts": 1_700_000_000_000u64,
+            "content": { "m.relates_to": { "rel_type": "m.annotation", "event_id": "$2", "key": "👍" } }
+        });
+
+        let events = vec![
+            event(text("$1", "@alice:nok.local", "hello")),
+            event(reply),
+            event(edit),
+            event(reaction),
+        ];
+        let messages = build_messages(&events, "General", Some("@alice:nok.local"), None);
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].display_content(), "hello everyone (edited)");
+        assert_eq!(messages[1].content, "hi alice");
+        assert_eq!(messages[1].reply_to.as_ref().unwrap().sender, "alice");
+        assert_eq!(messages[1].reactions_summary(), "👍 1");
+        assert_eq!(messages[1].own_reaction("👍"), Some("$4"));
+    }
+
+    #[test]
+    fn test_redaction_marks_message_deleted() {
+        let redaction = json!({
+            "type": "m.room.redaction",
+            "event_id": "$2",
+            "sender": "@alice:nok.local",
+            "origin_server_ts": 1_700_000_000_000u64,
+            "redacts": "$1",
+            "content": {}
+        });
+
+        let events = vec![event(text("$1", "@alice:nok.local", "oops")), event(redaction)];
+        let messages = build_messages(&events, "General", None, None);
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].display_content(), "(deleted)");
+    }
+
+    #[test]
+    fn test_thread_replies_are_counted_on_root() {
+        let mut first = text("$2", "@bob:nok.local", "in thread");
+        first["content"]["m.relates_to"] = json!({
+            "rel_type": "m.thread",
+            "event_id": "$1",
+            "is_falling_back": true,
+            "m.in_reply_to": { "event_id": "$1" }
+        });
+        let mut second = text("$3", "@alice:nok.local", "> <@bob:nok.local> in thread\n\nagreed");
+        second["content"]["m.relates_to"] = json!({
+            "rel_type": "m.thread",
+            "event_id": "$1",
+            "is_falling_back": false,
+            "m.in_reply_to": { "event_id": "$2" }
+        });
+
+        let events = vec![event(text("$1", "@alice:nok.local", "root")), event(first), event(second)];
+        let messages = build_messages(&events, "General", None, None);
+
+        assert_eq!(messages[0].thread_replies, 2);
+        assert_eq!(messages[1].thread_root.as_deref(), Some("$1"));
+        assert!(messages[1].reply_to.is_none());
+        assert_eq!(messages[2].content, "agreed");
+        assert_eq!(messages[2].reply_to.as_ref().unwrap().event_id, "$2");
+        assert!(messages.iter().all(|m| m.in_thread("$1")));
+    }
+
+    #[test]
+    fn test_media_messages_carry_attachment() {
+        let image = json!({
+            "type": "m.room.message",
+            "event_id": "$1",
+            "sender": "@alice:nok.local",
+            "origin_server_ts": 1_700_000_000_000u64,
+            "content": {
+                "msgtype": "m.image",
+                "body": "cat.png",
+                "url": "mxc://nok.local/abc",
+                "info": { "mimetype": "image/png", "size": 2048 }
+            }
+        });
+
+        let messages = build_messages(&[event(image)], "General", None, None);
+
+        assert_eq!(messages[0].display_content(), "[image] cat.png (2 KB)");
+    }
+
+    #[test]
+    fn test_mentions_are_highlighted() {
+        let events = vec![
+            event(text("$1", "@bob:nok.local", "ping Alice Smith, can you look?")),
+            event(text("$2", "@bob:nok.local", "talking about malice, not you")),
+            event(text("$3", "@alice:nok.local", "alice here")),
+        ];
+        let messages = build_messages(&events, "General", Some("@alice:nok.local"), Some("Alice Smith"));
+
+        assert!(messages[0].highlighted);
+        assert!(!messages[1].highlighted);
+        assert!(!messages[2].highlighted, "our own messages never highlight");
+    }
+
+    #[test]
+    fn test_knock_events_are_shown() {
+        let events = vec![event(json!({
+            "type": "com.nok.knock",
+            "event_id": "$k",
+            "sender": "@alice:nok.local",
+            "origin_server_ts": 1_700_000_000_000u64,
+            "content": { "target_user": "@bob:nok.local", "timestamp": 1_700_000_000_000i64 }
+        }))];
+
+        let messages = build_messages(&events, "room", Some("@bob:nok.local"), None);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, "🚪 *knock knock* for bob");
+        assert_eq!(messages[0].message_type, "knock");
+        assert!(messages[0].highlighted);
+    }
+
+    #[test]
+    fn test_undecryptable_messages_are_shown_with_reason() {
+        let encrypted = json!({
+            "type": "m.room.encrypted",
+            "event_id": "$e",
+            "sender": "@alice:nok.local",
+            "origin_server_ts": 1_700_000_000_000u64,
+            "content": {
+                "algorithm": "m.megolm.v1.aes-sha2",
+                "ciphertext": "AwgAEn",
+                "sender_key": "sk",
+                "device_id": "DEV",
+                "session_id": "sess"
+            }
+        });
+        let utd_info = UnableToDecryptInfo {
+            session_id: Some("sess".to_string()),
+            reason: UnableToDecryptReason::MissingMegolmSession { withheld_code: None },
+        };
+        let events = vec![
+            TimelineEvent::new_utd_event(Raw::new(&encrypted).unwrap().cast(), utd_info),
+            event(encrypted.clone()),
+        ];
+
+        let messages = build_messages(&events, "room", None, None);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].content, "🔒 Unable to decrypt: the room key has not arrived yet");
+        assert_eq!(messages[0].message_type, "undecryptable");
+        assert_eq!(messages[1].message_type, "undecryptable");
+        assert_eq!(messages[0].sender_id.as_deref(), Some("@alice:nok.local"));
+    }
+
+    #[test]
+    fn test_mentions_user() {
+        assert!(mentions_user("cc @alice:nok.local", "@alice:nok.local", None));
+        assert!(mentions_user("Alice: lunch?", "@alice:nok.local", Some("Alice")));
+        assert!(!mentions_user("alicewonder", "@alice:nok.local", Some("Alice")));
+        // Localparts alone are not mentions
+        assert!(!mentions_user("alice: lunch?", "@alice:nok.local", None));
+        assert!(!mentions_user("tell me later", "@me:nok.local", None));
+    }
+
+    #[test]
+    fn test_push_actions_decide_highlights() {
+        use matrix_sdk::ruma::push::{Action, Tweak};
+
+        let mut quiet = event(text("$1", "@bob:nok.local", "ping Alice Smith"));
+        quiet.push_actions = Some(vec![Action::Notify]);
+        let mut loud = event(text("$2", "@bob:nok.local", "anyone around?"));
+        loud.push_actions = Some(vec![Action::Notify, Action::SetTweak(Tweak::Highlight(true))]);
+
+        let messages = build_messages(&[quiet, loud], "General", Some("@alice:nok.local"), Some("Alice Smith"));
+        assert!(!messages[0].highlighted, "push rules said not to highlight");
+        assert!(messages[1].highlighted);
+    }
+
+    #[test]
+    fn test_strip_reply_fallback() {
+        assert_eq!(strip_reply_fallback("plain"), "plain");
+        assert_eq!(strip_reply_fallback("> <@a:b> one\n> two\n\nanswer"), "answer");
+    }
+
+    #[test]
+    fn test_append_events_skips_known_events() {
+        let mut events = vec![event(text("$1", "@alice:nok.local", "hi"))];
+        let received = vec![
+            event(text("$1", "@alice:nok.local", "hi")),
+            event(text("$2", "@bob:nok.local", "hello")),
+        ];
+
+        assert!(append_events(&mut events, received));
+        assert!(!append_events(&mut events, vec![event(text("$2", "@bob:nok.local", "hello"))]));
+
+        let messages = build_messages(&events, "room", None, None);
+        assert_eq!(messages.iter().map(|m| m.content.as_str()).collect::<Vec<_>>(), vec!["hi", "hello"]);
+        assert!(!has_undecryptable(&events));
+    }
+}
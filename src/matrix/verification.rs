@@ -0,0 +1,175 @@
+use matrix_sdk::encryption::verification::{
+    SasState, SasVerification, VerificationRequest, VerificationRequestState,
+};
+
+/// Where an interactive (SAS emoji) verification stands, as shown in the
+/// verification modal
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerificationPhase {
+    /// We asked; waiting for the other side to accept
+    Requested,
+    /// They asked us; waiting for us to accept or decline
+    Incoming,
+    /// Both sides agreed; the emojis are being worked out
+    Starting,
+    /// Emojis to compare with the other device, as (symbol, name)
+    Emojis(Vec<(String, String)>),
+    /// We said they match; waiting for the other side to say so too
+    Confirmed,
+    Done,
+    Cancelled(String),
+}
+
+/// Phase of a request that has not turned into an emoji verification yet;
+/// `we_started` tells our own pending request from one sent to us
+fn request_phase(state: VerificationRequestState, we_started: bool) -> VerificationPhase {
+    match state {
+        VerificationRequestState::Created { .. } => VerificationPhase::Requested,
+        VerificationRequestState::Requested { .. } if we_started => VerificationPhase::Requested,
+        VerificationRequestState::Requested { .. } => VerificationPhase::Incoming,
+        VerificationRequestState::Ready { .. } | VerificationRequestState::Transitioned { .. } => VerificationPhase::Starting,
+        VerificationRequestState::Done => VerificationPhase::Done,
+        VerificationRequestState::Cancelled(info) => VerificationPhase::Cancelled(info.reason().to_string()),
+    }
+}
+
+/// Phase of an emoji verification
+fn sas_phase(state: SasState) -> VerificationPhase {
+    match state {
+        SasState::KeysExchanged { emojis: Some(emojis), .. } => VerificationPhase::Emojis(
+            emojis.emojis.iter()
+                .map(|emoji| (emoji.symbol.to_string(), emoji.description.to_string()))
+                .collect(),
+        ),
+        SasState::Confirmed => VerificationPhase::Confirmed,
+        SasState::Done { .. } => VerificationPhase::Done,
+        SasState::Cancelled(info) => VerificationPhase::Cancelled(info.reason().to_string()),
+        _ => VerificationPhase::Starting,
+    }
+}
+
+/// One verification flow with another user or device: the request and,
+/// once it has turned into one, the SAS (emoji) verification
+#[derive(Debug, Clone)]
+pub struct Verification {
+    request: VerificationRequest,
+    sas: Option<SasVerification>,
+}
+
+impl Verification {
+    pub fn new(request: VerificationRequest) -> Self {
+        Self { request, sas: None }
+    }
+
+    /// MXID of the user on the other side
+    pub fn other_user_id(&self) -> String {
+        self.request.other_user_id().to_string()
+    }
+
+    pub fn is_self_verification(&self) -> bool {
+        self.request.is_self_verification()
+    }
+
+    pub fn phase(&self) -> VerificationPhase {
+        match &self.sas {
+            Some(sas) => sas_phase(sas.state()),
+            None => request_phase(self.request.state(), self.request.we_started()),
+        }
+    }
+
+    /// Over once it is done or cancelled
+    pub fn is_finished(&self) -> bool {
+        matches!(self.phase(), VerificationPhase::Done | VerificationPhase::Cancelled(_))
+    }
+
+    /// Move the flow along without user input: the side that sent the
+    /// request starts the emoji verification once the other accepted, and
+    /// the other side accepts it when it arrives
+    pub async fn advance(&mut self) -> Result<(), matrix_sdk::Error> {
+        if self.sas.is_some() {
+            return Ok(());
+        }
+        match self.request.state() {
+            VerificationRequestState::Ready { .. } if self.request.we_started() => {
+                self.sas = self.request.start_sas().await?;
+            }
+            VerificationRequestState::Transitioned { verification } => {
+                if let Some(sas) = verification.sas() {
+                    if !sas.we_started() {
+                        sas.accept().await?;
+                    }
+                    self.sas = Some(sas);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Accept a request the other side sent us
+    pub async fn accept(&self) -> Result<(), matrix_sdk::Error> {
+        self.request.accept().await
+    }
+
+    /// The emojis match
+    pub async fn confirm(&self) -> Result<(), matrix_sdk::Error> {
+        match &self.sas {
+            Some(sas) => sas.confirm().await,
+            None => Ok(()),
+        }
+    }
+
+    /// The emojis differ: cancel, telling the other side why
+    pub async fn mismatch(&self) -> Result<(), matrix_sdk::Error> {
+        match &self.sas {
+            Some(sas) => sas.mismatch().await,
+            None => self.request.cancel().await,
+        }
+    }
+
+    pub async fn cancel(&self) -> Result<(), matrix_sdk::Error> {
+        match &self.sas {
+            Some(sas) => sas.cancel().await,
+            None => self.request.cancel().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use matrix_sdk::encryption::verification::{Emoji, EmojiShortAuthString};
+
+    #[test]
+    fn test_request_phase_of_our_request() {
+        let created = || VerificationRequestState::Created { our_methods: Vec::new() };
+
+        assert_eq!(request_phase(created(), true), VerificationPhase::Requested);
+        assert_eq!(request_phase(VerificationRequestState::Done, true), VerificationPhase::Done);
+        assert_eq!(request_phase(VerificationRequestState::Done, false), VerificationPhase::Done);
+    }
+
+    #[test]
+    fn test_sas_phase_follows_the_emoji_flow() {
+        let dog = Emoji { symbol: "🐶", description: "Dog" };
+        let keys_exchanged = SasState::KeysExchanged {
+            emojis: Some(EmojiShortAuthString { indices: [0; 7], emojis: std::array::from_fn(|_| dog.clone()) }),
+            decimals: (1000, 2000, 3000),
+        };
+
+        assert_eq!(
+            sas_phase(keys_exchanged),
+            VerificationPhase::Emojis(vec![("🐶".to_string(), "Dog".to_string()); 7])
+        );
+        // Decimals only: nothing to compare in the modal yet
+        assert_eq!(
+            sas_phase(SasState::KeysExchanged { emojis: None, decimals: (1000, 2000, 3000) }),
+            VerificationPhase::Starting
+        );
+        assert_eq!(sas_phase(SasState::Confirmed), VerificationPhase::Confirmed);
+        assert_eq!(
+            sas_phase(SasState::Done { verified_devices: Vec::new(), verified_identities: Vec::new() }),
+            VerificationPhase::Done
+        );
+    }
+}
@@ -51,6 +51,8 @@ impl MigrationCommand {
             device_name: "migration-client".to_string(),
             state_store_path: "migration_matrix_state.db".to_string(),
             store_path: "migration_matrix_state.db".to_string(),
+            store_passphrase: None,
+            device_id: None,
         };
 
         let matrix_client = MatrixClient::new(matrix_config).await?;
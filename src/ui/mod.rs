@@ -6,14 +6,15 @@ use crossterm::{
 };
 use ratatui::{
     backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     style::{Color, Modifier, Style},
-    widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap},
+    text::{Line, Span},
+    widgets::{Block, Borders, Clear, List, ListItem, ListState, Paragraph, Wrap},
     Frame, Terminal,
 };
 use tokio::time;
 
-use crate::app::{App, NewApp, AppState, PaneIdentifier, LoginField, CorePaneIdentifier, MatrixLoginField};
+use crate::app::{App, NewApp, AppState, PaneIdentifier, LoginField, CorePaneIdentifier, MatrixLoginField, ComposeMode, RoomPaneEntry, TextStyle};
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum TabView {
@@ -42,6 +43,10 @@ pub fn ui_new(f: &mut Frame, app: &mut NewApp) {
     match app.core.state {
         AppState::Login => {
             render_login_new(f, app);
+            // The store question is asked before logging in
+            if let Some(confirmation) = &app.ui.confirm {
+                render_confirmation(f, confirmation);
+            }
             return;
         }
         AppState::Settings => {
@@ -51,6 +56,454 @@ pub fn ui_new(f: &mut Frame, app: &mut NewApp) {
         _ => {}
     }
     render_main_ui_new(f, app);
+
+    match app.core.state {
+        AppState::RoomDirectory => render_room_directory_new(f, app),
+        AppState::UserDirectory => render_user_directory_new(f, app),
+        AppState::Profile => render_profile(f, app),
+        _ => {}
+    }
+    if let Some(verification) = app.state_manager.verification() {
+        render_verification(f, verification);
+    }
+    if let Some(confirmation) = &app.ui.confirm {
+        render_confirmation(f, confirmation);
+    }
+}
+
+/// Profile overlay of the user picked in the Users pane or with `/profile`
+fn render_profile(f: &mut Frame, app: &NewApp) {
+    let Some(profile) = &app.ui.profile else {
+        return;
+    };
+    let area = centered_rect(50, 40, f.size());
+    f.render_widget(Clear, area);
+    let block = Block::default()
+        .title(format!("Profile - {}", profile.name))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan));
+
+    let (verified, verified_style) = match profile.verified {
+        Some(true) => ("✓ Verified", Style::default().fg(Color::Green)),
+        Some(false) => ("⚠ Not verified", Style::default().fg(Color::Yellow)),
+        None => ("No cross-signing identity", Style::default().fg(Color::DarkGray)),
+    };
+    let mut lines = vec![
+        Line::from(profile.user_id.clone()),
+        Line::from(""),
+        Line::styled(verified, verified_style),
+    ];
+    if let Some(note) = &profile.last_verification {
+        lines.push(Line::styled(note.clone(), Style::default().fg(Color::DarkGray)));
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::styled("v: Verify with emojis   Esc: Close", Style::default().fg(Color::DarkGray)));
+    f.render_widget(Paragraph::new(lines).block(block).wrap(Wrap { trim: true }), area);
+}
+
+/// Verification modal: the request, then the seven emojis to compare
+fn render_verification(f: &mut Frame, verification: &crate::matrix::Verification) {
+    use crate::matrix::VerificationPhase;
+
+    let area = centered_rect(60, 40, f.size());
+    f.render_widget(Clear, area);
+    let title = if verification.is_self_verification() {
+        "Verify your other device".to_string()
+    } else {
+        format!("Verify {}", verification.other_user_id())
+    };
+    let block = Block::default()
+        .title(title)
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Magenta));
+
+    let hint = Style::default().fg(Color::DarkGray);
+    let lines = match verification.phase() {
+        VerificationPhase::Requested => vec![
+            Line::from("Waiting for the other device to accept…"),
+            Line::from(""),
+            Line::styled("Esc: Cancel", hint),
+        ],
+        VerificationPhase::Incoming => vec![
+            Line::from(format!("{} wants to verify with you.", verification.other_user_id())),
+            Line::from(""),
+            Line::styled("y: Accept   n: Decline", hint),
+        ],
+        VerificationPhase::Starting => vec![
+            Line::from("Exchanging keys…"),
+            Line::from(""),
+            Line::styled("Esc: Cancel", hint),
+        ],
+        VerificationPhase::Emojis(emojis) => vec![
+            Line::from("Check that these emojis appear, in this order, on the other device:"),
+            Line::from(""),
+            Line::from(emojis.iter().map(|(symbol, _)| format!("{:^12}", symbol)).collect::<String>()),
+            Line::styled(emojis.iter().map(|(_, name)| format!("{:^12}", name)).collect::<String>(), Style::default().fg(Color::Cyan)),
+            Line::from(""),
+            Line::styled("y: They match   n: They don't match   Esc: Cancel", hint),
+        ],
+        VerificationPhase::Confirmed => vec![
+            Line::from("Waiting for the other device to confirm…"),
+            Line::from(""),
+            Line::styled("Esc: Cancel", hint),
+        ],
+        VerificationPhase::Done => vec![
+            Line::styled("✓ Verified", Style::default().fg(Color::Green)),
+            Line::from(""),
+            Line::styled("Any key: Close", hint),
+        ],
+        VerificationPhase::Cancelled(reason) => vec![
+            Line::styled(format!("Verification cancelled: {}", reason), Style::default().fg(Color::Red)),
+            Line::from(""),
+            Line::styled("Any key: Close", hint),
+        ],
+    };
+    f.render_widget(Paragraph::new(lines).block(block).wrap(Wrap { trim: false }), area);
+}
+
+/// Yes/no question drawn over everything else
+fn render_confirmation(f: &mut Frame, confirmation: &crate::app::Confirmation) {
+    let area = centered_rect(50, 30, f.size());
+    f.render_widget(Clear, area);
+    let block = Block::default()
+        .title("Are you sure?")
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Yellow));
+    let text = format!("{}\n\ny: Yes   n/Esc: No", confirmation.prompt);
+    f.render_widget(Paragraph::new(text).block(block).wrap(Wrap { trim: true }), area);
+}
+
+/// Draw the office map with everyone on it, scrolled so our own avatar
+/// (or the editor's cursor) stays inside `area`. Avatars are an `@` in
+/// their presence colour with their name beside it; `desks` holds the
+/// claimed desks, `true` for ours, `huddle` who we are huddling with and
+/// `knocks` who is knocking on us (with when the knock arrived).
+fn office_map_lines(
+    map: &crate::app::OfficeMap,
+    avatars: &[crate::app::Avatar],
+    desks: &std::collections::HashMap<crate::app::Position, bool>,
+    huddle: &[String],
+    knocks: &[(String, std::time::Instant)],
+    editor: Option<&crate::app::OfficeEditor>,
+    area: Rect,
+) -> Vec<Line<'static>> {
+    use crate::app::{Position, Tile, Zone};
+
+    let wood = Color::Rgb(150, 110, 60);
+    let mut cells: Vec<Vec<(String, Style)>> = (0..map.height)
+        .map(|y| (0..map.width)
+            .map(|x| {
+                let pos = Position::new(x, y);
+                let (glyph, color) = match map.tile(pos) {
+                    Tile::Wall => ("█", Color::DarkGray),
+                    Tile::Door => ("▒", wood),
+                    Tile::Desk => match desks.get(&pos) {
+                        Some(true) => ("▭", Color::Green),
+                        Some(false) => ("▭", Color::Cyan),
+                        None => ("▭", wood),
+                    },
+                    Tile::Floor if map.zone_at(pos).is_some() => ("·", Color::Rgb(70, 70, 130)),
+                    Tile::Floor => ("·", Color::Rgb(60, 60, 60)),
+                };
+                (glyph.to_string(), Style::default().fg(color))
+            })
+            .collect())
+        .collect();
+
+    // Zone names run along the zone's top row, over bare floor only
+    for zone in &map.zones {
+        let label_style = Style::default().fg(Color::Rgb(110, 110, 200)).add_modifier(Modifier::ITALIC);
+        for (offset, ch) in zone.name.chars().take(usize::from(zone.width)).enumerate() {
+            let pos = Position::new(zone.x + offset as u16, zone.y);
+            if map.tile(pos) == Tile::Floor {
+                cells[usize::from(pos.y)][usize::from(pos.x)] = (ch.to_string(), label_style);
+            }
+        }
+    }
+
+    // A dashed boundary around the huddle, drawn on the floor around it
+    let huddle_cells: Vec<Position> = avatars.iter()
+        .filter(|avatar| !huddle.is_empty() && (avatar.is_self || huddle.contains(&avatar.user_id)))
+        .map(|avatar| avatar.position)
+        .collect();
+    if let (Some(min_x), Some(max_x), Some(min_y), Some(max_y)) = (
+        huddle_cells.iter().map(|p| p.x).min(),
+        huddle_cells.iter().map(|p| p.x).max(),
+        huddle_cells.iter().map(|p| p.y).min(),
+        huddle_cells.iter().map(|p| p.y).max(),
+    ) {
+        let (left, top) = (min_x.saturating_sub(1), min_y.saturating_sub(1));
+        let (right, bottom) = (max_x + 1, max_y + 1);
+        let border_style = Style::default().fg(Color::Magenta);
+        for y in top..=bottom {
+            for x in left..=right {
+                let glyph = match (x == left, x == right, y == top, y == bottom) {
+                    (true, _, true, _) => "╭",
+                    (_, true, true, _) => "╮",
+                    (true, _, _, true) => "╰",
+                    (_, true, _, true) => "╯",
+                    (_, _, true, _) | (_, _, _, true) => "┄",
+                    (true, _, _, _) | (_, true, _, _) => "┆",
+                    _ => continue,
+                };
+                if map.tile(Position::new(x, y)) == Tile::Floor {
+                    cells[usize::from(y)][usize::from(x)] = (glyph.to_string(), border_style);
+                }
+            }
+        }
+    }
+
+    let presence_color = |avatar: &crate::app::Avatar| match avatar.status {
+        crate::app::user::UserStatus::Online => Color::Green,
+        crate::app::user::UserStatus::Away => Color::Yellow,
+        crate::app::user::UserStatus::Busy => Color::Red,
+        crate::app::user::UserStatus::Offline => Color::Gray,
+    };
+
+    // Labels first, so a name never hides someone standing next to it.
+    // Each runs to the right until it would hit the wall.
+    for avatar in avatars {
+        let Some(row) = cells.get_mut(usize::from(avatar.position.y)) else {
+            continue;
+        };
+        let label_style = Style::default().fg(presence_color(avatar));
+        for (offset, ch) in avatar.name.chars().take(10).enumerate() {
+            let cx = usize::from(avatar.position.x) + 1 + offset;
+            if cx + 1 >= row.len() {
+                break;
+            }
+            row[cx] = (ch.to_string(), label_style);
+        }
+    }
+    // A knock plays out as a little door just above the knocker
+    for (user_id, at) in knocks {
+        let (Some(frame), Some(avatar)) = (
+            crate::app::knock_frame(at.elapsed()),
+            avatars.iter().find(|avatar| &avatar.user_id == user_id),
+        ) else {
+            continue;
+        };
+        let knock_style = Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD);
+        let y = avatar.position.y.saturating_sub(1);
+        for (offset, ch) in frame.chars().enumerate() {
+            let x = avatar.position.x.saturating_sub(1) + offset as u16;
+            if map.is_walkable(Position::new(x, y)) {
+                cells[usize::from(y)][usize::from(x)] = (ch.to_string(), knock_style);
+            }
+        }
+    }
+
+    // Our own avatar goes last so it is always on top
+    let mut ordered: Vec<&crate::app::Avatar> = avatars.iter().collect();
+    ordered.sort_by_key(|avatar| avatar.is_self);
+    for avatar in ordered {
+        let mut style = Style::default().fg(presence_color(avatar));
+        if avatar.is_self {
+            style = style.add_modifier(Modifier::BOLD | Modifier::REVERSED);
+        }
+        if let Some(cell) = cells.get_mut(usize::from(avatar.position.y))
+            .and_then(|row| row.get_mut(usize::from(avatar.position.x)))
+        {
+            *cell = ("@".to_string(), style);
+        }
+    }
+
+    // The zone being marked out is shaded and the cursor highlighted
+    if let Some(editor) = editor {
+        let marked = editor.pending_zone
+            .or(editor.zone_anchor.map(|anchor| (anchor, editor.cursor)))
+            .map(|(a, b)| Zone::from_corners("", a, b));
+        for (y, row) in cells.iter_mut().enumerate() {
+            for (x, (_, style)) in row.iter_mut().enumerate() {
+                let pos = Position::new(x as u16, y as u16);
+                if marked.as_ref().is_some_and(|zone| zone.contains(pos)) {
+                    *style = style.bg(Color::Rgb(40, 40, 90));
+                }
+                if pos == editor.cursor {
+                    *style = Style::default().fg(Color::Yellow).add_modifier(Modifier::REVERSED);
+                }
+            }
+        }
+    }
+
+    // Scroll to keep ourselves (or the cursor) in view on small terminals
+    let own = editor.map(|editor| editor.cursor)
+        .or_else(|| avatars.iter().find(|avatar| avatar.is_self).map(|avatar| avatar.position));
+    let scroll = |own: u16, len: u16, view: u16| -> usize {
+        if len <= view {
+            0
+        } else {
+            usize::from(own.saturating_sub(view / 2).min(len - view))
+        }
+    };
+    let offset_x = own.map(|p| scroll(p.x, map.width, area.width)).unwrap_or(0);
+    let offset_y = own.map(|p| scroll(p.y, map.height, area.height)).unwrap_or(0);
+
+    cells.into_iter()
+        .skip(offset_y)
+        .map(|row| Line::from(row.into_iter()
+            .skip(offset_x)
+            .map(|(text, style)| Span::styled(text, style))
+            .collect::<Vec<_>>()))
+        .collect()
+}
+
+/// A rectangle of the given percentage size centered in `area`
+fn centered_rect(percent_x: u16, percent_y: u16, area: Rect) -> Rect {
+    let vertical = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Percentage((100 - percent_y) / 2),
+            Constraint::Percentage(percent_y),
+            Constraint::Percentage((100 - percent_y) / 2),
+        ])
+        .split(area);
+    Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage((100 - percent_x) / 2),
+            Constraint::Percentage(percent_x),
+            Constraint::Percentage((100 - percent_x) / 2),
+        ])
+        .split(vertical[1])[1]
+}
+
+/// User directory overlay (`/who`) drawn over the main UI
+fn render_user_directory_new(f: &mut Frame, app: &NewApp) {
+    let directory = &app.ui.user_directory;
+    let area = centered_rect(70, 70, f.size());
+    f.render_widget(Clear, area);
+
+    let more = if directory.limited { " (more on the server, refine the search)" } else { "" };
+    let block = Block::default()
+        .title(format!("User directory - {} found{}", directory.results.len(), more))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan));
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1), // Search
+            Constraint::Min(0),    // Results
+            Constraint::Length(1), // Keys
+        ])
+        .split(inner);
+
+    let search_style = if directory.results_focused {
+        Style::default().fg(Color::DarkGray)
+    } else {
+        Style::default()
+    };
+    let cursor = if directory.results_focused { "" } else { "_" };
+    let searching = if directory.query_changed_at.is_some() { " …" } else { "" };
+    f.render_widget(
+        Paragraph::new(format!("Search: {}{}{}", directory.query, cursor, searching)).style(search_style),
+        chunks[0],
+    );
+
+    let items: Vec<ListItem> = directory.results.iter().map(|user| {
+        let (status_char, status_color) = match user.status {
+            crate::app::user::UserStatus::Online => ("●", Color::Green),
+            crate::app::user::UserStatus::Away => ("○", Color::Yellow),
+            crate::app::user::UserStatus::Busy => ("◆", Color::Red),
+            crate::app::user::UserStatus::Offline => ("◇", Color::Gray),
+        };
+        ListItem::new(Line::from(vec![
+            Span::styled(format!("{} ", status_char), Style::default().fg(status_color)),
+            Span::styled(user.name.clone(), Style::default().add_modifier(Modifier::BOLD)),
+            Span::styled(format!("  {}", user.matrix_id.as_deref().unwrap_or_default()), Style::default().fg(Color::DarkGray)),
+        ]))
+    }).collect();
+
+    if items.is_empty() {
+        let hint = if directory.query.trim().is_empty() { "Type a name or user ID" } else { "No users found" };
+        f.render_widget(Paragraph::new(hint).style(Style::default().fg(Color::DarkGray)), chunks[1]);
+    } else {
+        let list = List::new(items)
+            .highlight_style(Style::default().fg(Color::Black).bg(Color::Cyan))
+            .highlight_symbol("> ");
+        let mut state = ListState::default();
+        if directory.results_focused {
+            state.select(Some(directory.selected));
+        }
+        f.render_stateful_widget(list, chunks[1], &mut state);
+    }
+
+    let keys = if directory.results_focused {
+        "k knock  m/Enter DM  i invite to current room  ↑↓ select  Tab search  Esc close"
+    } else {
+        "Type to search  Enter/Tab results  Esc close"
+    };
+    f.render_widget(Paragraph::new(keys).style(Style::default().fg(Color::DarkGray)), chunks[2]);
+}
+
+/// Public room directory overlay drawn over the main UI
+fn render_room_directory_new(f: &mut Frame, app: &NewApp) {
+    let directory = &app.ui.room_directory;
+    let area = centered_rect(80, 80, f.size());
+    f.render_widget(Clear, area);
+
+    let total = directory.page.total
+        .map(|total| format!(", ~{} rooms", total))
+        .unwrap_or_default();
+    let block = Block::default()
+        .title(format!("Room directory - page {}{}", directory.page_number + 1, total))
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::Cyan));
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1), // Search
+            Constraint::Min(0),    // Results
+            Constraint::Length(1), // Keys
+        ])
+        .split(inner);
+
+    let searching = if directory.query_changed_at.is_some() { " …" } else { "" };
+    f.render_widget(
+        Paragraph::new(format!("Search: {}_{}", directory.query, searching)),
+        chunks[0],
+    );
+
+    let items: Vec<ListItem> = directory.page.rooms.iter().map(|room| {
+        let mut header = vec![
+            Span::styled(room.display_name().to_string(), Style::default().add_modifier(Modifier::BOLD)),
+            Span::styled(format!("  {} members", room.member_count), Style::default().fg(Color::DarkGray)),
+        ];
+        if let Some(alias) = room.alias.as_deref().filter(|alias| Some(*alias) != room.name.as_deref()) {
+            header.push(Span::styled(format!("  {}", alias), Style::default().fg(Color::Cyan)));
+        }
+        let mut lines = vec![Line::from(header)];
+        if let Some(topic) = room.topic.as_deref().filter(|topic| !topic.is_empty()) {
+            lines.push(Line::styled(format!("  {}", topic), Style::default().fg(Color::Gray)));
+        }
+        ListItem::new(lines)
+    }).collect();
+
+    if items.is_empty() {
+        f.render_widget(
+            Paragraph::new("No public rooms found").style(Style::default().fg(Color::DarkGray)),
+            chunks[1],
+        );
+    } else {
+        let list = List::new(items)
+            .highlight_style(Style::default().fg(Color::Black).bg(Color::Cyan))
+            .highlight_symbol("> ");
+        let mut state = ListState::default();
+        state.select(Some(directory.selected));
+        f.render_stateful_widget(list, chunks[1], &mut state);
+    }
+
+    f.render_widget(
+        Paragraph::new("Type to search  ↑↓ select  ←→ page  Enter join  Esc close")
+            .style(Style::default().fg(Color::DarkGray)),
+        chunks[2],
+    );
 }
 
 fn render_login(f: &mut Frame, app: &mut App) {
@@ -636,6 +1089,7 @@ fn render_login_new(f: &mut Frame, app: &mut NewApp) {
             Constraint::Length(7),      // Title
             Constraint::Length(6),      // Username field
             Constraint::Length(6),      // Password field
+            Constraint::Length(if login_state.store_passphrase.is_some() { 3 } else { 0 }), // Store passphrase
             Constraint::Length(4),      // Login button
             Constraint::Length(3),      // Error message
             Constraint::Min(3),         // Help
@@ -650,7 +1104,13 @@ fn render_login_new(f: &mut Frame, app: &mut NewApp) {
     let title_area = title_block.inner(main_chunks[0]);
     f.render_widget(title_block, main_chunks[0]);
 
-    let title_text = "Welcome to nok Matrix Edition!\n\nPlease enter your Matrix credentials:";
+    // Full Matrix IDs find their homeserver; bare usernames use the last one
+    let server_name = &app.state_manager.matrix().config.server_name;
+    let title_text = if server_name.is_empty() {
+        "Welcome to nok Matrix Edition!\n\nPlease enter your Matrix ID, e.g. @alice:example.org".to_string()
+    } else {
+        format!("Welcome to nok Matrix Edition!\n\nPlease enter your Matrix ID, or just a username on {}", server_name)
+    };
     let title_paragraph = Paragraph::new(title_text)
         .style(Style::default().fg(Color::White))
         .wrap(Wrap { trim: true });
@@ -662,8 +1122,14 @@ fn render_login_new(f: &mut Frame, app: &mut NewApp) {
     } else {
         Style::default().fg(Color::White)
     };
+    // ↑↓ switch between accounts that logged in before
+    let username_title = match (login_state.saved_account_index, login_state.saved_accounts.len()) {
+        (_, 0) => "Matrix ID".to_string(),
+        (Some(index), count) => format!("Matrix ID (↑↓ account {}/{})", index + 1, count),
+        (None, count) => format!("Matrix ID (↑↓ {} previous accounts)", count),
+    };
     let username_block = Block::default()
-        .title("Username")
+        .title(username_title)
         .borders(Borders::ALL)
         .border_style(username_border_style);
     let username_area = username_block.inner(main_chunks[1]);
@@ -700,13 +1166,27 @@ fn render_login_new(f: &mut Frame, app: &mut NewApp) {
         .style(Style::default().fg(Color::White));
     f.render_widget(password_paragraph, password_area);
 
+    // Store passphrase, when the key file is not used
+    if let Some(ref passphrase) = login_state.store_passphrase {
+        let focused = login_state.field_focus == MatrixLoginField::StorePassphrase;
+        let passphrase_block = Block::default()
+            .title("Store passphrase")
+            .borders(Borders::ALL)
+            .border_style(Style::default().fg(if focused { Color::Yellow } else { Color::White }));
+        let passphrase_area = passphrase_block.inner(main_chunks[3]);
+        f.render_widget(passphrase_block, main_chunks[3]);
+
+        let passphrase_text = format!("{}{}", "*".repeat(passphrase.chars().count()), if focused { "_" } else { "" });
+        f.render_widget(Paragraph::new(passphrase_text).style(Style::default().fg(Color::White)), passphrase_area);
+    }
+
     // Login button
     let login_block = Block::default()
         .title("Login")
         .borders(Borders::ALL)
         .border_style(Style::default().fg(Color::Green));
-    let login_area = login_block.inner(main_chunks[3]);
-    f.render_widget(login_block, main_chunks[3]);
+    let login_area = login_block.inner(main_chunks[4]);
+    f.render_widget(login_block, main_chunks[4]);
 
     let login_text = "Press Enter to login";
     let login_paragraph = Paragraph::new(login_text)
@@ -719,8 +1199,8 @@ fn render_login_new(f: &mut Frame, app: &mut NewApp) {
             .title("Error")
             .borders(Borders::ALL)
             .border_style(Style::default().fg(Color::Red));
-        let error_area = error_block.inner(main_chunks[4]);
-        f.render_widget(error_block, main_chunks[4]);
+        let error_area = error_block.inner(main_chunks[5]);
+        f.render_widget(error_block, main_chunks[5]);
 
         let error_paragraph = Paragraph::new(error.as_str())
             .style(Style::default().fg(Color::Red))
@@ -733,10 +1213,15 @@ fn render_login_new(f: &mut Frame, app: &mut NewApp) {
         .title("Help")
         .borders(Borders::ALL)
         .border_style(Style::default().fg(Color::Gray));
-    let help_area = help_block.inner(main_chunks[5]);
-    f.render_widget(help_block, main_chunks[5]);
-
-    let help_text = "• Tab: Switch between fields\n• Enter: Login\n• Esc: Exit\n\nExample: demo_user / demo1234";
+    let help_area = help_block.inner(main_chunks[6]);
+    f.render_widget(help_block, main_chunks[6]);
+
+    // Adding an account keeps the others logged in, so Esc goes back to them
+    let esc = if app.state_manager.matrix().logged_in_clients().next().is_some() { "Back" } else { "Exit" };
+    let help_text = format!(
+        "• Tab: Switch between fields\n• ↑↓: Switch between previous accounts\n• Enter: Login\n• Esc: {}\n\nExample: @alice:example.org / password",
+        esc
+    );
     let help_paragraph = Paragraph::new(help_text)
         .style(Style::default().fg(Color::Gray))
         .wrap(Wrap { trim: true });
@@ -751,16 +1236,23 @@ fn render_settings_new(f: &mut Frame, app: &mut NewApp) {
         .constraints([
             Constraint::Length(3),      // タイトル
             Constraint::Length(8),      // ユーザー名設定
-            Constraint::Length(6),      // ステータス設定
+            Constraint::Length(8),      // セキュリティ設定
+            Constraint::Min(6),         // デバイス一覧
             Constraint::Length(3),      // 保存ボタン
             Constraint::Length(8),      // ログ表示エリア
-            Constraint::Min(3),         // ヘルプ
+            Constraint::Length(3),      // ヘルプ
         ].as_ref())
         .split(size);
 
-    // タイトル
+    // タイトル: keys and devices are those of the account we act as
+    let title = match app.state_manager.matrix().user_id() {
+        Some(user_id) if app.state_manager.matrix().account_ids().len() > 1 => {
+            format!("Settings - {} (/account to switch)", user_id)
+        }
+        _ => "Settings".to_string(),
+    };
     let title_block = Block::default()
-        .title("Settings")
+        .title(title)
         .borders(Borders::ALL)
         .border_style(Style::default().fg(Color::Cyan));
     f.render_widget(title_block, main_chunks[0]);
@@ -773,6 +1265,108 @@ fn render_settings_new(f: &mut Frame, app: &mut NewApp) {
         .style(Style::default().fg(Color::White))
         .wrap(Wrap { trim: true });
     f.render_widget(settings_paragraph, main_chunks[1]);
+
+    render_security_settings(f, &app.ui.security, main_chunks[2]);
+    render_device_settings(f, &app.ui.devices, main_chunks[3]);
+
+    let help = if app.ui.security.prompt.is_some() || app.ui.devices.prompt.is_some() {
+        "Enter: Continue  Esc: Cancel"
+    } else {
+        "b: Set up cross-signing & key backup  r: Restore from recovery key  ↑↓: Select device  Space: Mark  n: Rename  x: Log out  m: Switch mode  Esc: Back"
+    };
+    f.render_widget(Paragraph::new(help).style(Style::default().fg(Color::DarkGray)).wrap(Wrap { trim: true }), main_chunks[6]);
+}
+
+/// Our devices, with the name or password being typed below the list
+fn render_device_settings(f: &mut Frame, devices: &crate::app::core::DeviceSettings, area: Rect) {
+    use crate::app::core::DevicePrompt;
+
+    let block = Block::default().title(format!("Devices ({})", devices.list.len())).borders(Borders::ALL);
+    let inner = block.inner(area);
+    f.render_widget(block, area);
+
+    let mut lines: Vec<Line> = devices.list.iter().enumerate().map(|(i, device)| {
+        let marker = if devices.marked.contains(&device.device_id) { "[x] " } else { "[ ] " };
+        let name = device.display_name.as_deref().unwrap_or("(no name)");
+        let last_seen = match (&device.last_seen_ip, &device.last_seen) {
+            (Some(ip), Some(at)) => format!("{} at {}", ip, at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M")),
+            (None, Some(at)) => at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string(),
+            (Some(ip), None) => ip.clone(),
+            (None, None) => "never seen".to_string(),
+        };
+        let (verified, verified_color) = if device.verified {
+            ("✓ verified", Color::Green)
+        } else {
+            ("unverified", Color::Yellow)
+        };
+        let style = if i == devices.selected {
+            Style::default().add_modifier(Modifier::REVERSED)
+        } else {
+            Style::default()
+        };
+        Line::from(vec![
+            Span::styled(format!("{}{} ", marker, name), style),
+            Span::styled(format!("{} ", device.device_id), Style::default().fg(Color::DarkGray)),
+            Span::styled(verified, Style::default().fg(verified_color)),
+            Span::raw(format!("  {}", last_seen)),
+            Span::styled(if device.is_current { "  (this device)" } else { "" }, Style::default().fg(Color::Cyan)),
+        ])
+    }).collect();
+    if devices.list.is_empty() {
+        lines.push(Line::styled("Needs a Matrix login", Style::default().fg(Color::DarkGray)));
+    }
+
+    let list_height = inner.height.saturating_sub(if devices.prompt.is_some() { 1 } else { 0 });
+    let scroll = (devices.selected as u16).saturating_sub(list_height.saturating_sub(1));
+    f.render_widget(Paragraph::new(lines).scroll((scroll, 0)), Rect { height: list_height, ..inner });
+
+    if let Some(prompt) = devices.prompt {
+        // The password is never echoed; the new name is
+        let input = match prompt {
+            DevicePrompt::Password => "*".repeat(devices.input.chars().count()),
+            DevicePrompt::Rename => devices.input.clone(),
+        };
+        let prompt_area = Rect { y: inner.y + list_height, height: 1, ..inner };
+        f.render_widget(Paragraph::new(format!("{}: {}_", prompt.label(), input)), prompt_area);
+    }
+}
+
+/// Cross-signing and key backup state, with the secret being typed or
+/// the recovery key just created
+fn render_security_settings(f: &mut Frame, security: &crate::app::core::SecuritySettings, area: Rect) {
+    use crate::matrix::{CrossSigningState, RecoveryStatus};
+
+    let block = Block::default().title("Security").borders(Borders::ALL);
+    let mut lines = Vec::new();
+    match &security.status {
+        Some(status) => {
+            let (cross_signing, color) = match status.cross_signing {
+                CrossSigningState::Ready => ("✓ set up", Color::Green),
+                CrossSigningState::KeysMissing => ("keys not on this device (restore with r)", Color::Yellow),
+                CrossSigningState::NotSetUp => ("not set up (b)", Color::Yellow),
+            };
+            lines.push(Line::from(vec![Span::raw("Cross-signing: "), Span::styled(cross_signing, Style::default().fg(color))]));
+            let (recovery, color) = match status.recovery {
+                RecoveryStatus::Enabled => ("✓ on", Color::Green),
+                RecoveryStatus::Disabled => ("off (b)", Color::Yellow),
+                RecoveryStatus::Incomplete => ("waiting for the recovery key (r)", Color::Yellow),
+                RecoveryStatus::Unknown => ("unknown until the first sync", Color::DarkGray),
+            };
+            lines.push(Line::from(vec![Span::raw("Key backup: "), Span::styled(recovery, Style::default().fg(color))]));
+        }
+        None => lines.push(Line::styled("Needs a Matrix login", Style::default().fg(Color::DarkGray))),
+    }
+    if let Some(key) = &security.recovery_key {
+        lines.push(Line::from(""));
+        lines.push(Line::styled("Recovery key (shown once, keep it safe):", Style::default().fg(Color::Yellow)));
+        lines.push(Line::styled(key.clone(), Style::default().fg(Color::White).add_modifier(Modifier::BOLD)));
+    }
+    if let Some(prompt) = security.prompt {
+        // Secrets are never echoed
+        lines.push(Line::from(""));
+        lines.push(Line::from(format!("{}: {}_", prompt.label(), "*".repeat(security.input.chars().count()))));
+    }
+    f.render_widget(Paragraph::new(lines).block(block).wrap(Wrap { trim: false }), area);
 }
 
 fn render_main_ui_new(f: &mut Frame, app: &mut NewApp) {
@@ -823,8 +1417,15 @@ fn render_main_ui_new(f: &mut Frame, app: &mut NewApp) {
     let messages_area = left_chunks[2];
 
     // --- Render Rooms Pane ---
+    let floor_name = app.data.active_space.as_ref()
+        .and_then(|id| app.data.rooms.iter().find(|r| r.matrix_id.as_ref() == Some(id)))
+        .map(|space| space.name.clone());
+    let rooms_title = match &floor_name {
+        Some(name) => format!("Rooms - {}", name),
+        None => "Rooms".to_string(),
+    };
     let rooms_block = Block::default()
-        .title("Rooms")
+        .title(rooms_title)
         .borders(Borders::ALL)
         .border_style(if app.core.focused_pane == CorePaneIdentifier::Rooms {
             Style::default().fg(Color::Cyan)
@@ -834,27 +1435,120 @@ fn render_main_ui_new(f: &mut Frame, app: &mut NewApp) {
     let actual_rooms_content_area = rooms_block.inner(rooms_area);
     f.render_widget(rooms_block, rooms_area);
 
-    let room_items: Vec<ListItem> = app.data.rooms.iter().enumerate().map(|(_i, r)| {
-        let content = if app.data.get_current_room().map_or(false, |cr| cr.id == r.id) {
-            format!("* {}", r.name)
+    // With several accounts logged in, rooms and people say which one they belong to
+    let account_tag = |account: Option<&String>| {
+        account.and_then(|account| app.state_manager.matrix().account_tag(account))
+            .map_or(String::new(), |tag| format!(" ({})", tag))
+    };
+
+    let mut room_items: Vec<ListItem> = Vec::new();
+    let mut dm_header_shown = false;
+    for entry in app.data.room_pane_entries() {
+        let (i, depth) = match entry {
+            RoomPaneEntry::Room { idx, depth } => (idx, depth),
+            RoomPaneEntry::Invite(idx) => {
+                // Pending invites follow the joined rooms, with who sent them
+                let invite = &app.data.invites[idx];
+                let mut lines = Vec::new();
+                if idx == 0 {
+                    lines.push(Line::styled("Invites", Style::default().fg(Color::DarkGray)));
+                }
+                lines.push(Line::from(vec![
+                    Span::styled(format!("  {}", invite.room_name), Style::default().fg(Color::LightMagenta)),
+                    Span::styled(format!(" from {}", invite.inviter()), Style::default().fg(Color::DarkGray)),
+                    Span::styled(account_tag(invite.account.as_ref()), Style::default().fg(Color::DarkGray)),
+                ]));
+                if let Some(topic) = invite.topic.as_deref().filter(|topic| !topic.is_empty()) {
+                    lines.push(Line::styled(format!("    {}", topic), Style::default().fg(Color::DarkGray)));
+                }
+                room_items.push(ListItem::new(lines));
+                continue;
+            }
+        };
+        let r = &app.data.rooms[i];
+
+        // Floors are headers that fold their rooms
+        if r.is_space {
+            let (arrow, folded) = if r.collapsed {
+                ("▸", format!(" ({} rooms)", r.space_children.len()))
+            } else {
+                ("▾", String::new())
+            };
+            room_items.push(ListItem::new(Line::from(vec![
+                Span::styled(format!("{} {}", arrow, r.name), Style::default().fg(Color::Magenta).add_modifier(Modifier::BOLD)),
+                Span::styled(folded, Style::default().fg(Color::DarkGray)),
+            ])));
+            continue;
+        }
+
+        // Matrix rooms have no legacy id, so compare by position
+        let is_current = i == app.data.current_room;
+        let indent = "  ".repeat(depth);
+        let kind = r.kind.map_or(String::new(), |kind| format!(" [{}]", kind.as_str()));
+        let lock = if r.is_encrypted { "🔒" } else { "" };
+        let content = if is_current {
+            format!("{}* {}{}{}", indent, lock, r.name, kind)
         } else {
-            format!("  {}", r.name)
+            format!("{}  {}{}{}", indent, lock, r.name, kind)
         };
-        let style = if app.data.get_current_room().map_or(false, |cr| cr.id == r.id) {
+        let style = if is_current {
             Style::default().fg(Color::Yellow)
         } else {
             Style::default().fg(Color::White)
         };
-        ListItem::new(content).style(style)
-    }).collect();
+        let badge_style = if r.highlight_count > 0 {
+            Style::default().fg(Color::LightRed).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(Color::DarkGray)
+        };
+
+        if !r.is_direct {
+            room_items.push(ListItem::new(Line::from(vec![
+                Span::styled(content, style),
+                Span::styled(r.occupancy_badge(), Style::default().fg(Color::Gray)),
+                Span::styled(r.unread_badge(), badge_style),
+                Span::styled(account_tag(r.account.as_ref()), Style::default().fg(Color::DarkGray)),
+            ])));
+            continue;
+        }
 
+        // DMs show the other person's presence, under a section header
+        let partner_status = r.dm_user_id.as_ref()
+            .and_then(|id| app.data.users.iter().find(|u| u.matrix_id.as_ref() == Some(id)))
+            .map(|u| u.status.clone())
+            .unwrap_or(crate::app::user::UserStatus::Offline);
+        let (status_char, status_color) = match partner_status {
+            crate::app::user::UserStatus::Online => ("●", Color::Green),
+            crate::app::user::UserStatus::Away => ("○", Color::Yellow),
+            crate::app::user::UserStatus::Busy => ("◆", Color::Red),
+            crate::app::user::UserStatus::Offline => ("◇", Color::Gray),
+        };
+        let dm_line = Line::from(vec![
+            Span::styled(content, style),
+            Span::styled(format!(" {}", status_char), Style::default().fg(status_color)),
+            Span::styled(r.unread_badge(), badge_style),
+            Span::styled(account_tag(r.account.as_ref()), Style::default().fg(Color::DarkGray)),
+        ]);
+
+        if dm_header_shown {
+            room_items.push(ListItem::new(dm_line));
+        } else {
+            dm_header_shown = true;
+            room_items.push(ListItem::new(vec![
+                Line::styled("Direct messages", Style::default().fg(Color::DarkGray)),
+                dm_line,
+            ]));
+        }
+    }
+
+    let room_entries = room_items.len();
     let rooms_list = List::new(room_items)
         .highlight_style(Style::default().fg(Color::Black).bg(Color::Cyan))
         .highlight_symbol("> ");
 
     let mut rooms_state = ListState::default();
-    if app.core.focused_pane == CorePaneIdentifier::Rooms && !app.data.rooms.is_empty() {
-        let safe_idx = if app.ui.selected_room_idx < app.data.rooms.len() {
+    if app.core.focused_pane == CorePaneIdentifier::Rooms && room_entries > 0 {
+        let safe_idx = if app.ui.selected_room_idx < room_entries {
             app.ui.selected_room_idx
         } else {
             0
@@ -865,8 +1559,14 @@ fn render_main_ui_new(f: &mut Frame, app: &mut NewApp) {
     f.render_stateful_widget(rooms_list, actual_rooms_content_area, &mut rooms_state);
 
     // --- Render Users Pane ---
+    let users_title = match (&floor_name, app.data.users_here_only) {
+        (Some(name), true) => format!("Users - {} - in this room now", name),
+        (Some(name), false) => format!("Users - {}", name),
+        (None, true) => "Users - in this room now".to_string(),
+        (None, false) => "Users".to_string(),
+    };
     let users_block = Block::default()
-        .title("Users")
+        .title(users_title)
         .borders(Borders::ALL)
         .border_style(if app.core.focused_pane == CorePaneIdentifier::Users {
             Style::default().fg(Color::Cyan)
@@ -876,7 +1576,8 @@ fn render_main_ui_new(f: &mut Frame, app: &mut NewApp) {
     let actual_users_content_area = users_block.inner(users_area);
     f.render_widget(users_block, users_area);
 
-    let user_items: Vec<ListItem> = app.data.users.iter().enumerate().map(|(_i, u)| {
+    let visible_users = app.data.visible_users();
+    let user_items: Vec<ListItem> = visible_users.iter().map(|u| {
         let status_char = match u.status {
             crate::app::user::UserStatus::Online => "●",
             crate::app::user::UserStatus::Away => "○",
@@ -890,8 +1591,19 @@ fn render_main_ui_new(f: &mut Frame, app: &mut NewApp) {
             crate::app::user::UserStatus::Offline => Color::Gray,
         };
 
-        let content = format!("{} {}", status_char, u.name);
-        ListItem::new(content).style(Style::default().fg(status_color))
+        // Tag them with the accounts that share a room with them
+        let mut accounts: Vec<&String> = u.rooms.iter()
+            .filter_map(|id| app.data.rooms.iter().find(|r| r.matrix_id.as_ref() == Some(id)))
+            .filter_map(|r| r.account.as_ref())
+            .collect();
+        accounts.sort();
+        accounts.dedup();
+        let tags: String = accounts.into_iter().map(|account| account_tag(Some(account))).collect();
+
+        ListItem::new(Line::from(vec![
+            Span::styled(format!("{} {}", status_char, u.name), Style::default().fg(status_color)),
+            Span::styled(tags, Style::default().fg(Color::DarkGray)),
+        ]))
     }).collect();
 
     let users_list = List::new(user_items)
@@ -899,9 +1611,9 @@ fn render_main_ui_new(f: &mut Frame, app: &mut NewApp) {
         .highlight_symbol("> ");
 
     let mut users_state = ListState::default();
-    if app.core.focused_pane == CorePaneIdentifier::Users && !app.data.users.is_empty() {
+    if app.core.focused_pane == CorePaneIdentifier::Users && !visible_users.is_empty() {
         if let Some(selected) = app.ui.selected_user {
-            let safe_idx = if selected < app.data.users.len() {
+            let safe_idx = if selected < visible_users.len() {
                 selected
             } else {
                 0
@@ -913,8 +1625,15 @@ fn render_main_ui_new(f: &mut Frame, app: &mut NewApp) {
     f.render_stateful_widget(users_list, actual_users_content_area, &mut users_state);
 
     // --- Render Messages Pane ---
+    let messages_title = match app.data.get_current_room() {
+        Some(room) => match room.topic.as_deref().filter(|topic| !topic.is_empty()) {
+            Some(topic) => format!("Messages - {}: {}", room.name, topic),
+            None => format!("Messages - {}", room.name),
+        },
+        None => "Messages".to_string(),
+    };
     let messages_block = Block::default()
-        .title("Messages")
+        .title(messages_title)
         .borders(Borders::ALL)
         .border_style(if app.core.focused_pane == CorePaneIdentifier::Messages {
             Style::default().fg(Color::Cyan)
@@ -937,20 +1656,27 @@ fn render_main_ui_new(f: &mut Frame, app: &mut NewApp) {
         };
     f.render_widget(messages_block, messages_area);
 
-    let message_items: Vec<ListItem> = app.data.messages.iter().map(|m| {
-        let time_str = m.formatted_time();
-        let content = format!("[{}] <{}>: {}", time_str, m.sender, m.content);
-        ListItem::new(content).style(Style::default().fg(Color::White))
-    }).collect();
+    // With a thread open, the Messages pane is split: main timeline on the
+    // left, the thread on the right. Selection follows the thread view.
+    let (timeline_area, thread_area) = if app.ui.open_thread.is_some() {
+        let chunks = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(50), Constraint::Percentage(50)])
+            .split(actual_messages_content_area);
+        (chunks[0], Some(chunks[1]))
+    } else {
+        (actual_messages_content_area, None)
+    };
 
-    let messages_list = List::new(message_items)
+    let timeline = app.data.visible_messages(None);
+    let messages_list = List::new(message_list_items(&timeline))
         .highlight_style(Style::default().fg(Color::Black).bg(Color::Cyan))
         .highlight_symbol("> ");
 
     let mut messages_state = ListState::default();
-    if app.core.focused_pane == CorePaneIdentifier::Messages && !app.data.messages.is_empty() {
+    if app.core.focused_pane == CorePaneIdentifier::Messages && thread_area.is_none() && !timeline.is_empty() {
         if let Some(selected) = app.ui.selected_message_idx {
-            let safe_idx = if selected < app.data.messages.len() {
+            let safe_idx = if selected < timeline.len() {
                 selected
             } else {
                 0
@@ -959,27 +1685,100 @@ fn render_main_ui_new(f: &mut Frame, app: &mut NewApp) {
         }
     }
 
-    f.render_stateful_widget(messages_list, actual_messages_content_area, &mut messages_state);
+    f.render_stateful_widget(messages_list, timeline_area, &mut messages_state);
+
+    if let Some(thread_area) = thread_area {
+        let thread = app.data.visible_messages(app.ui.open_thread.as_deref());
+        let thread_block = Block::default()
+            .title("Thread")
+            .borders(Borders::LEFT)
+            .border_style(Style::default().fg(Color::DarkGray));
+        let thread_list = List::new(message_list_items(&thread))
+            .block(thread_block)
+            .highlight_style(Style::default().fg(Color::Black).bg(Color::Cyan))
+            .highlight_symbol("> ");
+
+        let mut thread_state = ListState::default();
+        if app.core.focused_pane == CorePaneIdentifier::Messages && !thread.is_empty() {
+            thread_state.select(app.ui.selected_message_idx.map(|idx| idx.min(thread.len() - 1)));
+        }
+        f.render_stateful_widget(thread_list, thread_area, &mut thread_state);
+    }
 
     if let Some(input_area) = input_display_area {
-        let input_text = format!("> {}", app.ui.input);
+        let prompt = match app.ui.compose {
+            ComposeMode::Message if !app.data.huddle_members.is_empty() => "huddle>",
+            ComposeMode::Message if app.ui.open_thread.is_some() => "thread>",
+            ComposeMode::Message => ">",
+            ComposeMode::Reply(_) => "reply>",
+            ComposeMode::Edit(_) => "edit>",
+            ComposeMode::React(_) => "react>",
+        };
+        let input_text = format!("{} {}", prompt, app.ui.input);
         let input_paragraph = Paragraph::new(input_text)
             .style(Style::default().fg(Color::Yellow).bg(Color::Black));
         f.render_widget(input_paragraph, input_area);
     }
 
-    // --- Render Right Pane (Status) ---
-    let right_block = Block::default()
-        .title("Status")
+    // --- Render Right Pane (Office map over Status) ---
+    let right_chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Percentage(55), Constraint::Percentage(45)])
+        .split(right_pane_area);
+
+    let office_title = match app.data.get_current_room() {
+        Some(room) if app.ui.office_editor.is_some() => format!("Floor plan editor - {}", room.name),
+        Some(room) if app.data.office_room.is_some() => {
+            match app.data.office_map.zone_at(app.ui.my_aa_position) {
+                Some(zone) => format!("Office - {} · {}", room.name, zone.name),
+                None => format!("Office - {}", room.name),
+            }
+        }
+        _ => "Office".to_string(),
+    };
+    let office_block = Block::default()
+        .title(office_title)
         .borders(Borders::ALL)
         .border_style(if app.core.focused_pane == CorePaneIdentifier::AsciiArt {
             Style::default().fg(Color::Cyan)
         } else {
             Style::default().fg(Color::White)
         });
+    let office_area = office_block.inner(right_chunks[0]);
+    f.render_widget(office_block, right_chunks[0]);
+    let own_id = app.data.current_user.matrix_id.as_ref();
+    let desks: std::collections::HashMap<crate::app::Position, bool> = app.data.office_desks.iter()
+        .map(|(user_id, desk)| (*desk, Some(user_id) == own_id))
+        .collect();
+    if let Some(editor) = app.ui.office_editor.as_ref() {
+        let lines = office_map_lines(&editor.draft, &[], &desks, &[], &[], Some(editor), office_area);
+        f.render_widget(Paragraph::new(lines), office_area);
+    } else if app.data.office_room.is_some() {
+        let avatars = app.data.office_avatars(app.ui.my_aa_position);
+        let lines = office_map_lines(
+            &app.data.office_map,
+            &avatars,
+            &desks,
+            &app.data.huddle_members,
+            &app.data.office_knocks,
+            None,
+            office_area,
+        );
+        f.render_widget(Paragraph::new(lines), office_area);
+    } else {
+        f.render_widget(
+            Paragraph::new("Enter a room to see who is around").style(Style::default().fg(Color::DarkGray)),
+            office_area,
+        );
+    }
 
-    let content_area = right_block.inner(right_pane_area);
-    f.render_widget(right_block, right_pane_area);
+    let right_block = Block::default()
+        .title("Status")
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(Color::White));
+
+    let content_area = right_block.inner(right_chunks[1]);
+    f.render_widget(right_block, right_chunks[1]);
 
     // Status information
     let mut status_text = format!("Terminal: {}x{}\nConnection: {:?}\nFocus: {:?}\nMode: {:?}",
@@ -996,6 +1795,18 @@ fn render_main_ui_new(f: &mut Frame, app: &mut NewApp) {
         crate::app::user::UserStatus::Offline => "◇",
     };
     status_text.push_str(&format!("\nYour Status: {} {:?}", status_char, app.data.current_user.status));
+    if !app.data.huddle_members.is_empty() {
+        let names: Vec<&str> = app.data.huddle_members.iter()
+            .map(|user_id| app.data.users.iter()
+                .find(|u| u.matrix_id.as_ref() == Some(user_id))
+                .map_or(user_id.as_str(), |u| u.name.as_str()))
+            .collect();
+        status_text.push_str(&format!("\nHuddle: {}", names.join(", ")));
+    }
+    let held_knocks = app.state_manager.deferred_knock_count();
+    if held_knocks > 0 {
+        status_text.push_str(&format!("\nKnocks waiting: {}", held_knocks));
+    }
 
     // Add controls
     status_text.push_str("\n\nControls:");
@@ -1003,8 +1814,31 @@ fn render_main_ui_new(f: &mut Frame, app: &mut NewApp) {
     status_text.push_str("\n↑↓: Navigate");
     status_text.push_str("\nEnter: Select");
     status_text.push_str("\nk: Knock");
+    status_text.push_str("\nf: Switch floor");
+    if app.core.focused_pane == CorePaneIdentifier::AsciiArt && app.ui.office_editor.is_some() {
+        status_text.push_str("\n←↑→↓ / ijkl: Move cursor");
+        status_text.push_str("\n# . d +: Wall/Floor/Desk/Door");
+        status_text.push_str("\nz: Mark zone  x: Remove zone");
+        status_text.push_str("\nEnter: Save plan  Esc: Discard");
+    } else if app.core.focused_pane == CorePaneIdentifier::AsciiArt {
+        status_text.push_str("\n←↑→↓ / ijkl: Walk");
+        status_text.push_str("\nn: Knock on neighbour");
+        status_text.push_str("\ne: Edit floor plan");
+        status_text.push_str("\n/desk: Claim this desk");
+    }
     status_text.push_str("\ns: Settings");
     status_text.push_str("\nq: Quit");
+    if app.core.focused_pane == CorePaneIdentifier::Messages {
+        status_text.push_str("\nr/e/d/+: Reply/Edit/Delete/React");
+        status_text.push_str("\nt: Thread");
+    }
+    if app.core.focused_pane == CorePaneIdentifier::Users {
+        status_text.push_str("\nw: Here now / All members");
+        status_text.push_str("\np: Profile / Verify");
+    }
+    if app.core.focused_pane == CorePaneIdentifier::Rooms && app.data.get_selected_invite(app.ui.selected_room_idx).is_some() {
+        status_text.push_str("\na/d: Accept/Decline invite");
+    }
 
     // Show notifications
     if let Some(ref notification) = app.core.notification {
@@ -1217,6 +2051,103 @@ async fn run_app_loop<B: ratatui::backend::Backend>(
     Ok(())
 }
 
+/// Terminal style for a span of a formatted message
+fn rich_text_style(text_style: TextStyle) -> Style {
+    let mut style = Style::default().fg(Color::White);
+    if text_style.quote {
+        style = style.fg(Color::Gray);
+    }
+    if text_style.bold {
+        style = style.add_modifier(Modifier::BOLD);
+    }
+    if text_style.italic {
+        style = style.add_modifier(Modifier::ITALIC);
+    }
+    if text_style.strike {
+        style = style.add_modifier(Modifier::CROSSED_OUT);
+    }
+    if text_style.link {
+        style = style.fg(Color::LightBlue).add_modifier(Modifier::UNDERLINED);
+    }
+    if text_style.code {
+        style = style.fg(Color::LightYellow);
+    }
+    if text_style.code_block {
+        style = style.fg(Color::LightGreen).bg(Color::Black);
+    }
+    style
+}
+
+/// Timeline lines for the Messages pane: reply quote, body, reactions and
+/// thread indicator
+fn message_list_items<'a>(messages: &[&'a crate::app::Message]) -> Vec<ListItem<'a>> {
+    messages.iter().map(|m| {
+        let mut lines = Vec::new();
+        if let Some(reply) = &m.reply_to {
+            lines.push(Line::styled(
+                format!("  ↳ <{}>: {}", reply.sender, reply.content),
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
+
+        let time_str = m.formatted_time();
+        let body_style = if m.redacted {
+            Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC)
+        } else if m.message_type == "undecryptable" {
+            Style::default().fg(Color::LightRed).add_modifier(Modifier::ITALIC)
+        } else {
+            Style::default().fg(Color::White)
+        };
+
+        // Formatted bodies keep their styling; everything else is plain text
+        let mut body: Vec<Vec<Span>> = match &m.formatted {
+            Some(formatted) if !m.redacted && m.attachment.is_none() => formatted.iter()
+                .map(|line| line.iter().map(|span| Span::styled(span.text.clone(), rich_text_style(span.style))).collect())
+                .collect(),
+            _ => m.display_content().lines()
+                .map(|line| vec![Span::styled(line.to_string(), body_style)])
+                .collect(),
+        };
+        if body.is_empty() {
+            body.push(Vec::new());
+        }
+        if m.edited && m.formatted.is_some() && !m.redacted && m.attachment.is_none() {
+            if let Some(last) = body.last_mut() {
+                last.push(Span::styled(" (edited)", Style::default().fg(Color::DarkGray)));
+            }
+        }
+
+        let header_style = if m.highlighted {
+            Style::default().fg(Color::Black).bg(Color::Yellow).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(Color::White)
+        };
+        for (i, spans) in body.into_iter().enumerate() {
+            let prefix = if i == 0 {
+                Span::styled(format!("[{}] <{}>: ", time_str, m.sender), header_style)
+            } else {
+                Span::raw("    ")
+            };
+            lines.push(Line::from(std::iter::once(prefix).chain(spans).collect::<Vec<_>>()));
+        }
+
+        if !m.reactions.is_empty() {
+            lines.push(Line::styled(
+                format!("    {}", m.reactions_summary()),
+                Style::default().fg(Color::Yellow),
+            ));
+        }
+        if m.thread_replies > 0 {
+            let label = if m.thread_replies == 1 { "reply" } else { "replies" };
+            lines.push(Line::styled(
+                format!("    🧵 {} {} (t to open)", m.thread_replies, label),
+                Style::default().fg(Color::Magenta),
+            ));
+        }
+        ListItem::new(lines)
+    }).collect()
+}
+
 /// Main TUI application loop for new architecture
 pub async fn run_app_new(mut app: NewApp) -> Result<(), Box<dyn std::error::Error>> {
     // Setup terminal
@@ -1265,12 +2196,7 @@ async fn run_app_loop_new<B: ratatui::backend::Backend>(
         if crossterm::event::poll(timeout)? {
             match event::read()? {
                 Event::Key(key) => {
-                    // Handle quit
-                    if key.code == KeyCode::Char('q') && app.core.state != AppState::Login {
-                        break;
-                    }
-
-                    // Handle key input
+                    // Handle key input; the app decides which keys quit
                     if let Err(e) = app.handle_key(key).await {
                         app.core.set_error(format!("Key handling error: {}", e));
                     }
@@ -1289,6 +2215,8 @@ async fn run_app_loop_new<B: ratatui::backend::Backend>(
 
         // Regular update processing
         if last_tick.elapsed() >= tick_rate {
+            app.tick().await;
+
             // Regular redraw
             terminal.draw(|f| ui_new(f, &mut app))?;
             last_tick = std::time::Instant::now();
@@ -82,15 +82,10 @@ impl LoginValidator {
             return false;
         }
         
-        let without_at = &username[1..];
-        let parts: Vec<&str> = without_at.split(':').collect();
-        
-        if parts.len() != 2 {
+        // The server name may carry a port, e.g. @user:example.org:8448
+        let Some((localpart, domain)) = username[1..].split_once(':') else {
             return false;
-        }
-        
-        let localpart = parts[0];
-        let domain = parts[1];
+        };
         
         // Localpart validation (basic)
         if localpart.is_empty() || localpart.len() > 255 {
@@ -160,6 +155,7 @@ mod tests {
         assert!(LoginValidator::is_valid_matrix_format("@user:example.com"));
         assert!(LoginValidator::is_valid_matrix_format("@test123:nok.local"));
         assert!(LoginValidator::is_valid_matrix_format("@user_name:server.org"));
+        assert!(LoginValidator::is_valid_matrix_format("@user:example.com:8448"));
         
         assert!(!LoginValidator::is_valid_matrix_format("user:example.com"));
         assert!(!LoginValidator::is_valid_matrix_format("@user"));